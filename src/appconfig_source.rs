@@ -0,0 +1,317 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use figment::{
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use serde_json::json;
+
+use crate::{config_loader::load_config_from_string, AwsClient, AwsError};
+
+/// A configuration layer holding a configuration profile deployed with AWS `AppConfig`,
+/// merged with [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// The source starts an `AppConfig` session on first load and polls it on later loads, no more
+/// often than the interval `AppConfig` asks for, keeping the last version deployed. Clones share
+/// the session, so a clone kept aside can [`poll`](AppConfigSource::poll) for a new deployment
+/// and reload the configuration when one arrives.
+#[derive(Clone)]
+pub struct AppConfigSource {
+    client: AwsClient,
+    application: String,
+    environment: String,
+    profile: String,
+    format: Option<String>,
+    minimum_poll_interval: Option<Duration>,
+    session: Arc<Mutex<Session>>,
+}
+
+/// The state of the `AppConfig` session, along with the last version received.
+#[derive(Default)]
+struct Session {
+    token: Option<String>,
+    document: Option<(String, String)>,
+    poll_interval: Option<Duration>,
+    last_poll: Option<Instant>,
+}
+
+impl AppConfigSource {
+    /// Creates a source for the configuration profile `profile` of `application`, deployed to
+    /// `environment`, each given by its name or ID.
+    #[must_use]
+    pub fn new(
+        client: AwsClient,
+        application: impl Into<String>,
+        environment: impl Into<String>,
+        profile: impl Into<String>,
+    ) -> Self {
+        Self {
+            client,
+            application: application.into(),
+            environment: environment.into(),
+            profile: profile.into(),
+            format: None,
+            minimum_poll_interval: None,
+            session: Arc::new(Mutex::new(Session::default())),
+        }
+    }
+
+    /// Sets the format of the profile by its file extension, e.g. `toml`, rather than detecting
+    /// it from the response. Freeform profiles served as `text/plain` need this.
+    #[must_use]
+    pub fn format(mut self, extension: impl Into<String>) -> Self {
+        self.format = Some(extension.into());
+        self
+    }
+
+    /// Asks `AppConfig` to allow polls no more often than every `interval`, at least 15 seconds.
+    /// `AppConfig` otherwise allows a poll every 60 seconds.
+    #[must_use]
+    pub const fn minimum_poll_interval(mut self, interval: Duration) -> Self {
+        self.minimum_poll_interval = Some(interval);
+        self
+    }
+
+    /// How long `AppConfig` asked to wait before polling again, once the session has started.
+    #[must_use]
+    pub fn poll_interval(&self) -> Option<Duration> {
+        self.session().poll_interval
+    }
+
+    /// Polls `AppConfig` for the latest configuration, starting the session if need be, and
+    /// returns whether a new version was received.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AwsError::AccessDenied`] when the credentials may not read the profile, or
+    /// another [`AwsError`] when polling fails, e.g. with `BadRequestException` when polling
+    /// sooner than [`poll_interval`](AppConfigSource::poll_interval) allows.
+    pub fn poll(&self) -> Result<bool, AwsError> {
+        let token = self.session().token.clone();
+        let token = match token {
+            Some(token) => token,
+            None => self.start_session()?,
+        };
+
+        let response = self.client.send(
+            "appconfigdata",
+            "GET",
+            "/configuration",
+            &[("configuration_token", &token)],
+            &[],
+            "",
+        )?;
+        let next_token = response
+            .header("Next-Poll-Configuration-Token")
+            .ok_or_else(|| AwsError::Response("no next poll token".to_string()))?
+            .to_string();
+        let poll_interval = response
+            .header("Next-Poll-Interval-In-Seconds")
+            .and_then(|seconds| seconds.parse().ok())
+            .map(Duration::from_secs);
+        let format = self
+            .format
+            .clone()
+            .unwrap_or_else(|| detect_format(response.content_type()));
+        let body = response
+            .into_string()
+            .map_err(|e| AwsError::Response(e.to_string()))?;
+
+        let mut session = self.session();
+        session.token = Some(next_token);
+        session.poll_interval = poll_interval;
+        session.last_poll = Some(Instant::now());
+        // An empty body means the version polled last is still the latest.
+        if body.is_empty() {
+            return Ok(false);
+        }
+        session.document = Some((format, body));
+        drop(session);
+        Ok(true)
+    }
+
+    fn start_session(&self) -> Result<String, AwsError> {
+        let mut request = json!({
+            "ApplicationIdentifier": self.application,
+            "EnvironmentIdentifier": self.environment,
+            "ConfigurationProfileIdentifier": self.profile,
+        });
+        if let Some(interval) = self.minimum_poll_interval {
+            request["RequiredMinimumPollIntervalInSeconds"] = json!(interval.as_secs());
+        }
+
+        let body = request.to_string();
+        let response = self
+            .client
+            .send(
+                "appconfigdata",
+                "POST",
+                "/configurationsessions",
+                &[],
+                &[("content-type", "application/json")],
+                &body,
+            )?
+            .into_string()
+            .map_err(|e| AwsError::Response(e.to_string()))?;
+        serde_json::from_str::<serde_json::Value>(&response)
+            .map_err(|e| AwsError::Response(e.to_string()))?["InitialConfigurationToken"]
+            .as_str()
+            .map(ToString::to_string)
+            .ok_or_else(|| AwsError::Response("no initial configuration token".to_string()))
+    }
+
+    /// Polls unless the last poll was sooner than the interval `AppConfig` asked for, returning
+    /// the latest version received.
+    fn latest(&self) -> Result<(String, String), AwsError> {
+        let due = {
+            let session = self.session();
+            session.document.is_none()
+                || session
+                    .last_poll
+                    .zip(session.poll_interval)
+                    .is_none_or(|(last_poll, interval)| last_poll.elapsed() >= interval)
+        };
+        if due {
+            self.poll()?;
+        }
+
+        self.session()
+            .document
+            .clone()
+            .ok_or_else(|| AwsError::Response("no configuration has been deployed".to_string()))
+    }
+
+    fn session(&self) -> MutexGuard<'_, Session> {
+        self.session.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn detect_format(content_type: &str) -> String {
+    let content_type = content_type.to_ascii_lowercase();
+    if content_type.contains("yaml") {
+        "yaml".to_string()
+    } else if content_type.contains("toml") {
+        "toml".to_string()
+    } else {
+        "json".to_string()
+    }
+}
+
+impl Provider for AppConfigSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("AWS AppConfig").source(Source::Custom(format!(
+            "{}/{}/{}",
+            self.application, self.environment, self.profile
+        )))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        let (format, body) = self
+            .latest()
+            .map_err(|e| format!("unable to read AppConfig profile {}: {e}", self.profile))?;
+        load_config_from_string(&format!("appconfig.{format}"), &body)
+            .map_err(|e| format!("unable to parse AppConfig profile {}: {e}", self.profile))?
+            .data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        test_server::{ok, serve, status},
+        AwsCredentials, GraftonConfigBuilder, TokenExpandingConfig,
+    };
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct FeatureConfig {
+        name: String,
+        beta: bool,
+    }
+
+    impl TokenExpandingConfig for FeatureConfig {}
+
+    fn configuration(token: &str, interval: u64, content_type: &str, body: &str) -> String {
+        ok(content_type, body).replacen(
+            "Connection",
+            &format!(
+                "Next-Poll-Configuration-Token: {token}\r\n\
+                 Next-Poll-Interval-In-Seconds: {interval}\r\nConnection"
+            ),
+            1,
+        )
+    }
+
+    fn source(url: String) -> AppConfigSource {
+        let client = AwsClient::new("eu-west-1", AwsCredentials::new("AKID", "secret"));
+        AppConfigSource::new(client.endpoint(url), "myapp", "prod", "features")
+    }
+
+    #[test]
+    fn test_appconfig_source() {
+        let (url, server) = serve(vec![
+            ok(
+                "application/json",
+                r#"{ "InitialConfigurationToken": "token-1" }"#,
+            ),
+            configuration("token-2", 0, "application/json", r#"{ "beta": true }"#),
+            configuration("token-3", 90, "application/json", ""),
+            configuration("token-4", 90, "application/x-yaml", "beta: false\n"),
+        ]);
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\nbeta = false\n",
+        )
+        .unwrap();
+
+        let appconfig = source(url).minimum_poll_interval(Duration::from_secs(15));
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(appconfig.clone());
+
+        assert!(builder.load::<FeatureConfig>().unwrap().beta);
+        assert!(!appconfig.poll().unwrap());
+        assert!(builder.load::<FeatureConfig>().unwrap().beta);
+        assert_eq!(appconfig.poll_interval(), Some(Duration::from_secs(90)));
+        assert!(appconfig.poll().unwrap());
+        assert!(!builder.load::<FeatureConfig>().unwrap().beta);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].line, "POST /configurationsessions HTTP/1.1");
+        assert!(requests[0].has_header("content-type: application/json"));
+        let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["ApplicationIdentifier"], "myapp");
+        assert_eq!(body["RequiredMinimumPollIntervalInSeconds"], 15);
+        assert!(requests[0].headers.iter().any(|header| header
+            .starts_with("Authorization: AWS4-HMAC-SHA256 Credential=AKID/")
+            && header.contains("/eu-west-1/appconfig/aws4_request, ")));
+        assert_eq!(
+            requests[1].line,
+            "GET /configuration?configuration_token=token-1 HTTP/1.1"
+        );
+        assert_eq!(
+            requests[3].line,
+            "GET /configuration?configuration_token=token-3 HTTP/1.1"
+        );
+    }
+
+    #[test]
+    fn test_appconfig_source_error() {
+        let (url, server) = serve(vec![status("403 Forbidden")]);
+
+        assert_eq!(
+            source(url).poll(),
+            Err(AwsError::AccessDenied(String::new()))
+        );
+        server.join().unwrap();
+    }
+}
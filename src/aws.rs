@@ -0,0 +1,488 @@
+use std::{
+    env,
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Credentials used to sign requests to AWS.
+#[derive(Clone)]
+pub struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    #[must_use]
+    pub fn new(access_key_id: impl Into<String>, secret_access_key: impl Into<String>) -> Self {
+        Self {
+            access_key_id: access_key_id.into(),
+            secret_access_key: secret_access_key.into(),
+            session_token: None,
+        }
+    }
+
+    /// Adds the session token issued with temporary credentials.
+    #[must_use]
+    pub fn session_token(mut self, token: impl Into<String>) -> Self {
+        self.session_token = Some(token.into());
+        self
+    }
+
+    /// Reads `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and, when set,
+    /// `AWS_SESSION_TOKEN`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the variable that is not set.
+    pub fn from_env() -> Result<Self, crate::Error> {
+        Ok(Self {
+            access_key_id: required_var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: required_var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+fn required_var(name: &str) -> Result<String, crate::Error> {
+    env::var(name).map_err(|_| crate::Error::ConfigError(format!("{name} is not set")))
+}
+
+/// A minimal client for the AWS APIs used by the AWS providers, signing requests with
+/// Signature Version 4.
+///
+/// This is not the AWS SDK, so it does not follow the SDK's default credential chain. Credentials
+/// are only taken from the environment, with [`AwsClient::from_env`], or given explicitly; shared
+/// config and credentials files, SSO, web identity tokens, and container and instance metadata
+/// are not read. To use those, resolve the credentials with the SDK and pass them to
+/// [`AwsClient::new`], creating a new client before they expire.
+#[derive(Clone)]
+pub struct AwsClient {
+    region: String,
+    credentials: AwsCredentials,
+    endpoint: Option<String>,
+    timeout: Duration,
+}
+
+impl AwsClient {
+    #[must_use]
+    pub fn new(region: impl Into<String>, credentials: AwsCredentials) -> Self {
+        Self {
+            region: region.into(),
+            credentials,
+            endpoint: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Creates a client for the region in `AWS_REGION`, or `AWS_DEFAULT_REGION`, with the
+    /// credentials from [`AwsCredentials::from_env`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the variable that is not set.
+    pub fn from_env() -> Result<Self, crate::Error> {
+        let region = env::var("AWS_REGION").or_else(|_| required_var("AWS_DEFAULT_REGION"))?;
+        Ok(Self::new(region, AwsCredentials::from_env()?))
+    }
+
+    /// Sends requests to `endpoint`, e.g. `http://localhost:4566`, rather than the AWS endpoint
+    /// for each service and region.
+    #[must_use]
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into().trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Limits how long each request may take. Defaults to 30 seconds.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Calls an action of an AWS JSON protocol service, such as `AmazonSSM.GetParameter`.
+    #[cfg(any(feature = "aws-ssm", feature = "aws-secrets-manager"))]
+    pub(crate) fn call_json(
+        &self,
+        service: &str,
+        target: &str,
+        body: &Value,
+    ) -> Result<Value, AwsError> {
+        let body = body.to_string();
+        let headers = [
+            ("content-type", "application/x-amz-json-1.1"),
+            ("x-amz-target", target),
+        ];
+        let response = self.send(service, "POST", "/", &[], &headers, &body)?;
+        response
+            .into_string()
+            .map_err(|e| AwsError::Response(e.to_string()))
+            .and_then(|body| {
+                serde_json::from_str(&body).map_err(|e| AwsError::Response(e.to_string()))
+            })
+    }
+
+    /// Sends a signed request to `path` of the service with the endpoint prefix `service`, e.g.
+    /// `ssm`.
+    pub(crate) fn send(
+        &self,
+        service: &str,
+        method: &str,
+        path: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Result<ureq::Response, AwsError> {
+        let base = self
+            .endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://{service}.{}.amazonaws.com", self.region));
+        // A few services sign with a different name from their endpoint's.
+        let service = match service {
+            "appconfigdata" => "appconfig",
+            service => service,
+        };
+        let host = base
+            .split_once("://")
+            .map_or(base.as_str(), |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let amz_date = amz_date(SystemTime::now());
+        let mut signed: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), (*value).to_string()))
+            .chain([
+                ("host".to_string(), host),
+                ("x-amz-date".to_string(), amz_date.clone()),
+            ])
+            .chain(
+                self.credentials
+                    .session_token
+                    .clone()
+                    .map(|token| ("x-amz-security-token".to_string(), token)),
+            )
+            .collect();
+        signed.sort();
+
+        let authorization = self.authorization(
+            service,
+            &amz_date,
+            &CanonicalRequest {
+                method,
+                path,
+                query: &query,
+                headers: &signed,
+                body,
+            },
+        );
+
+        let url = if query.is_empty() {
+            format!("{base}{path}")
+        } else {
+            format!("{base}{path}?{query}")
+        };
+        let mut request = ureq::request(method, &url)
+            .timeout(self.timeout)
+            .set("Authorization", &authorization);
+        for (name, value) in signed.iter().filter(|(name, _)| name != "host") {
+            request = request.set(name, value);
+        }
+
+        let response = if method == "GET" {
+            request.call()
+        } else {
+            request.send_string(body)
+        };
+        response.map_err(|e| match e {
+            ureq::Error::Status(status, response) => {
+                AwsError::from_status(status, &response.into_string().unwrap_or_default())
+            }
+            e @ ureq::Error::Transport(_) => AwsError::Transport(e.to_string()),
+        })
+    }
+
+    fn authorization(&self, service: &str, amz_date: &str, request: &CanonicalRequest) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{}/{service}/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            sha256_hex(request.to_string().as_bytes())
+        );
+
+        let key = signing_key(
+            &self.credentials.secret_access_key,
+            date,
+            &self.region,
+            service,
+        );
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={}, Signature={signature}",
+            self.credentials.access_key_id,
+            request.signed_headers()
+        )
+    }
+}
+
+/// An error from an AWS API call.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AwsError {
+    /// The request could not be sent or its response read.
+    #[error("request failed: {0}")]
+    Transport(String),
+    /// The credentials are not permitted to perform the action, or to use the key that
+    /// encrypts the resource.
+    #[error("access denied: {0}")]
+    AccessDenied(String),
+    /// AWS rejected the request, with the error's type, e.g. `ParameterNotFound`, and message.
+    #[error("{code}: {message}")]
+    Service { code: String, message: String },
+    /// The response could not be understood.
+    #[error("invalid response: {0}")]
+    Response(String),
+}
+
+impl AwsError {
+    fn from_status(status: u16, body: &str) -> Self {
+        let error: Value = serde_json::from_str(body).unwrap_or_default();
+        let code = error["__type"]
+            .as_str()
+            .or_else(|| error["code"].as_str())
+            .map(|code| code.rsplit('#').next().unwrap_or(code));
+        let message = error["message"]
+            .as_str()
+            .or_else(|| error["Message"].as_str())
+            .unwrap_or(body)
+            .to_string();
+
+        match code {
+            Some("AccessDeniedException" | "AccessDenied") => Self::AccessDenied(message),
+            None if status == 403 => Self::AccessDenied(message),
+            code => Self::Service {
+                code: code.map_or_else(|| format!("HTTP {status}"), ToString::to_string),
+                message,
+            },
+        }
+    }
+}
+
+struct CanonicalRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    query: &'a str,
+    headers: &'a [(String, String)],
+    body: &'a str,
+}
+
+impl CanonicalRequest<'_> {
+    fn signed_headers(&self) -> String {
+        self.headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
+
+impl std::fmt::Display for CanonicalRequest<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.method)?;
+        writeln!(f, "{}", uri_encode(self.path, false))?;
+        writeln!(f, "{}", self.query)?;
+        for (name, value) in self.headers {
+            writeln!(f, "{name}:{}", value.trim())?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{}", self.signed_headers())?;
+        write!(f, "{}", sha256_hex(self.body.as_bytes()))
+    }
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    [date, region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{secret}").into_bytes(), |key, part| {
+            hmac(&key, part.as_bytes())
+        })
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Percent-encodes `value` as AWS requires, leaving `/` alone in paths.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                char::from(byte).to_string()
+            }
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Formats `time` as an ISO 8601 basic UTC timestamp, e.g. `20150830T123600Z`.
+fn amz_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let days = i64::try_from(seconds / 86_400).unwrap_or_default();
+    let seconds = seconds % 86_400;
+
+    // Converts days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signing_key() {
+        // The example from the AWS Signature Version 4 documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+    }
+
+    #[test]
+    fn test_authorization() {
+        // The example from the AWS Signature Version 4 documentation.
+        let client = AwsClient::new(
+            "us-east-1",
+            AwsCredentials::new("AKIDEXAMPLE", "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+        );
+        let headers = [
+            (
+                "content-type".to_string(),
+                "application/x-www-form-urlencoded; charset=utf-8".to_string(),
+            ),
+            ("host".to_string(), "iam.amazonaws.com".to_string()),
+            ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+        ];
+        let request = CanonicalRequest {
+            method: "GET",
+            path: "/",
+            query: "Action=ListUsers&Version=2010-05-08",
+            headers: &headers,
+            body: "",
+        };
+
+        assert_eq!(
+            client.authorization("iam", "20150830T123600Z", &request),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+
+    #[test]
+    fn test_amz_date() {
+        assert_eq!(amz_date(UNIX_EPOCH), "19700101T000000Z");
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(1_440_938_161)),
+            "20150830T123601Z"
+        );
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(951_868_799)),
+            "20000229T235959Z"
+        );
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("/my app/db", false), "/my%20app/db");
+        assert_eq!(uri_encode("/my app/db", true), "%2Fmy%20app%2Fdb");
+    }
+
+    #[test]
+    fn test_error_from_status() {
+        assert_eq!(
+            AwsError::from_status(
+                400,
+                r#"{"__type":"com.amazon#AccessDeniedException","message":"denied"}"#
+            ),
+            AwsError::AccessDenied("denied".to_string())
+        );
+        assert_eq!(
+            AwsError::from_status(
+                400,
+                r#"{"__type":"ResourceNotFoundException","Message":"missing"}"#
+            ),
+            AwsError::Service {
+                code: "ResourceNotFoundException".to_string(),
+                message: "missing".to_string(),
+            }
+        );
+        assert_eq!(
+            AwsError::from_status(403, ""),
+            AwsError::AccessDenied(String::new())
+        );
+        assert_eq!(
+            AwsError::from_status(500, ""),
+            AwsError::Service {
+                code: "HTTP 500".to_string(),
+                message: String::new(),
+            }
+        );
+    }
+}
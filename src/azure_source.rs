@@ -0,0 +1,410 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::Value;
+
+use crate::config_loader::insert_nested;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const IMDS_ENDPOINT: &str = "http://169.254.169.254";
+const KEY_VAULT_RESOURCE: &str = "https://vault.azure.net";
+const KEY_VAULT_REFERENCE: &str = "application/vnd.microsoft.appconfig.keyvaultref+json";
+
+/// Obtains Microsoft Entra ID access tokens for Azure App Configuration and Key Vault.
+///
+/// Clones share the tokens obtained from a managed identity, which are renewed before they
+/// expire.
+#[derive(Clone)]
+pub struct AzureCredential {
+    source: TokenSource,
+    tokens: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+}
+
+#[derive(Clone)]
+enum TokenSource {
+    Token(String),
+    ManagedIdentity {
+        endpoint: String,
+        client_id: Option<String>,
+    },
+}
+
+impl AzureCredential {
+    /// Uses `token` for every request, e.g. from `az account get-access-token`. It must be
+    /// valid for each resource it is sent to.
+    #[must_use]
+    pub fn with_token(token: impl Into<String>) -> Self {
+        Self::new(TokenSource::Token(token.into()))
+    }
+
+    /// Obtains tokens for the managed identity of the Azure host, or the user-assigned identity
+    /// with `client_id`, from the instance metadata service.
+    #[must_use]
+    pub fn managed_identity(client_id: Option<String>) -> Self {
+        Self::new(TokenSource::ManagedIdentity {
+            endpoint: IMDS_ENDPOINT.to_string(),
+            client_id,
+        })
+    }
+
+    fn new(source: TokenSource) -> Self {
+        Self {
+            source,
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// An access token for `resource`, e.g. `https://vault.azure.net`.
+    fn token(&self, resource: &str, timeout: Duration) -> Result<String, String> {
+        let (endpoint, client_id) = match &self.source {
+            TokenSource::Token(token) => return Ok(token.clone()),
+            TokenSource::ManagedIdentity {
+                endpoint,
+                client_id,
+            } => (endpoint, client_id),
+        };
+
+        let cached = self.tokens().get(resource).cloned();
+        if let Some((token, expires)) = cached {
+            if Instant::now() < expires {
+                return Ok(token);
+            }
+        }
+
+        let mut request = ureq::get(&format!("{endpoint}/metadata/identity/oauth2/token"))
+            .timeout(timeout)
+            .set("Metadata", "true")
+            .query("api-version", "2018-02-01")
+            .query("resource", resource);
+        if let Some(client_id) = client_id {
+            request = request.query("client_id", client_id);
+        }
+        let response = read_json(request.call())
+            .map_err(|e| format!("unable to get an Azure managed identity token: {e}"))?;
+
+        let token = response["access_token"]
+            .as_str()
+            .ok_or("the Azure instance metadata service returned no access token")?
+            .to_string();
+        // The lifetime is sent as a string. Renew the token a minute before it expires.
+        let lifetime = match &response["expires_in"] {
+            Value::String(seconds) => seconds.parse().unwrap_or_default(),
+            seconds => seconds.as_u64().unwrap_or_default(),
+        };
+        let expires = Instant::now() + Duration::from_secs(lifetime.saturating_sub(60));
+        self.tokens()
+            .insert(resource.to_string(), (token.clone(), expires));
+        Ok(token)
+    }
+
+    fn tokens(&self) -> MutexGuard<'_, HashMap<String, (String, Instant)>> {
+        self.tokens.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A configuration layer read from the key-values in an Azure App Configuration store, merged
+/// with [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// Key-values without a label are read first, then those labelled with each run mode in
+/// `RUN_MODE`, or with the labels given to [`label`](AzureAppConfigSource::label), which
+/// override them. The key prefix is removed from each key and the rest split on `:`, so with a
+/// prefix of `myapp:`, the key `myapp:database:port` sets `database.port`. Key Vault references
+/// are replaced with the secrets they refer to.
+pub struct AzureAppConfigSource {
+    endpoint: String,
+    credential: AzureCredential,
+    key_prefix: String,
+    labels: Option<Vec<String>>,
+    timeout: Duration,
+}
+
+impl AzureAppConfigSource {
+    /// Creates a source for the store at `endpoint`, e.g. `https://mystore.azconfig.io`.
+    #[must_use]
+    pub fn new(endpoint: impl Into<String>, credential: AzureCredential) -> Self {
+        Self {
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            credential,
+            key_prefix: String::new(),
+            labels: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Reads only the keys starting with `prefix`, removing it from their paths.
+    #[must_use]
+    pub fn key_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.key_prefix = prefix.into();
+        self
+    }
+
+    /// Reads key-values labelled `label` above unlabelled ones, rather than those labelled
+    /// with the run mode. Later labels take precedence over earlier ones.
+    #[must_use]
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.labels.get_or_insert_with(Vec::new).push(label.into());
+        self
+    }
+
+    /// Limits how long each request may take. Defaults to 30 seconds.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.labels.clone().unwrap_or_else(|| {
+            env::var("RUN_MODE")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|mode| !mode.is_empty())
+                .map(ToString::to_string)
+                .collect()
+        })
+    }
+
+    /// Reads the key-values under the prefix into a table.
+    fn read(&self) -> Result<Value, String> {
+        let token = self.credential.token(&self.endpoint, self.timeout)?;
+
+        let mut root = serde_json::Map::new();
+        // `\0` selects the key-values without a label.
+        for label in std::iter::once("\0".to_string()).chain(self.labels()) {
+            for item in self.list(&token, &label)? {
+                let key = item["key"].as_str().unwrap_or_default();
+                let segments: Vec<String> = key
+                    .strip_prefix(&self.key_prefix)
+                    .unwrap_or(key)
+                    .split(':')
+                    .filter(|segment| !segment.is_empty())
+                    .map(ToString::to_string)
+                    .collect();
+                let value = item["value"].as_str().unwrap_or_default();
+                let is_reference = item["content_type"]
+                    .as_str()
+                    .is_some_and(|content_type| content_type.starts_with(KEY_VAULT_REFERENCE));
+
+                let value = if is_reference {
+                    self.resolve_reference(key, value)?
+                } else {
+                    value.to_string()
+                };
+                insert_nested(&mut root, &segments, Value::String(value));
+            }
+        }
+        Ok(Value::Object(root))
+    }
+
+    /// Lists the key-values under the prefix with `label`, following each page of results.
+    fn list(&self, token: &str, label: &str) -> Result<Vec<Value>, String> {
+        let mut items = Vec::new();
+        let mut next = Some(format!(
+            "/kv?key={}*&label={}&api-version=1.0",
+            utf8_percent_encode(&self.key_prefix, NON_ALPHANUMERIC),
+            utf8_percent_encode(label, NON_ALPHANUMERIC),
+        ));
+        while let Some(path) = next {
+            let url = format!("{}{path}", self.endpoint);
+            let mut page = read_json(
+                ureq::get(&url)
+                    .timeout(self.timeout)
+                    .set("Authorization", &format!("Bearer {token}"))
+                    .call(),
+            )
+            .map_err(|e| format!("unable to read Azure App Configuration {url}: {e}"))?;
+
+            if let Value::Array(page_items) = page["items"].take() {
+                items.extend(page_items);
+            }
+            next = page["@nextLink"].as_str().map(ToString::to_string);
+        }
+        Ok(items)
+    }
+
+    /// Reads the Key Vault secret that the reference `value` of `key` refers to.
+    fn resolve_reference(&self, key: &str, value: &str) -> Result<String, String> {
+        let reference: Value = serde_json::from_str(value)
+            .map_err(|e| format!("invalid Key Vault reference in {key}: {e}"))?;
+        let uri = reference["uri"]
+            .as_str()
+            .ok_or_else(|| format!("Key Vault reference in {key} has no uri"))?;
+
+        let token = self.credential.token(KEY_VAULT_RESOURCE, self.timeout)?;
+        let secret = read_json(
+            ureq::get(uri)
+                .timeout(self.timeout)
+                .set("Authorization", &format!("Bearer {token}"))
+                .query("api-version", "7.4")
+                .call(),
+        )
+        .map_err(|e| format!("unable to read Key Vault secret {uri} for {key}: {e}"))?;
+        secret["value"]
+            .as_str()
+            .map(ToString::to_string)
+            .ok_or_else(|| format!("Key Vault secret {uri} has no value"))
+    }
+}
+
+impl Provider for AzureAppConfigSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("Azure App Configuration").source(Source::Custom(format!(
+            "{}/{}",
+            self.endpoint, self.key_prefix
+        )))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        Serialized::defaults(self.read()?).data()
+    }
+}
+
+fn read_json(response: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    response
+        .map_err(|e| match e {
+            ureq::Error::Status(403, _) => "permission denied".to_string(),
+            e => e.to_string(),
+        })?
+        .into_string()
+        .map_err(|e| e.to_string())
+        .and_then(|body| serde_json::from_str(&body).map_err(|e| e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        test_server::{ok, serve, status},
+        GraftonConfigBuilder, TokenExpandingConfig,
+    };
+
+    #[test]
+    fn test_azure_app_config_source() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Database {
+            host: String,
+            port: u16,
+            password: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct AzureConfig {
+            database: Database,
+        }
+
+        impl TokenExpandingConfig for AzureConfig {}
+
+        let (url, server) = serve(vec![
+            ok(
+                "application/json",
+                r#"{ "access_token": "store-token", "expires_in": "3599" }"#,
+            ),
+            ok(
+                "application/json",
+                &json!({
+                    "items": [{ "key": "myapp:database:port", "label": null, "value": "5432" }],
+                    "@nextLink": "/kv?after=1",
+                })
+                .to_string(),
+            ),
+            ok(
+                "application/json",
+                &json!({
+                    "items": [{ "key": "myapp:database:host", "label": null, "value": "db" }],
+                })
+                .to_string(),
+            ),
+            ok("application/json", r#"{ "items": [] }"#),
+            ok(
+                "application/json",
+                &json!({ "items": [{ "key": "myapp:database:port", "label": "prod", "value": "6432" }] })
+                    .to_string(),
+            ),
+        ]);
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "[database]\nhost = \"localhost\"\nport = 1\npassword = \"\"\n",
+        )
+        .unwrap();
+
+        let mut credential = AzureCredential::managed_identity(Some("client".to_string()));
+        credential.source = TokenSource::ManagedIdentity {
+            endpoint: url.clone(),
+            client_id: Some("client".to_string()),
+        };
+        let config: AzureConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(
+                AzureAppConfigSource::new(url, credential)
+                    .key_prefix("myapp:")
+                    .label("staging")
+                    .label("prod"),
+            )
+            .load()
+            .unwrap();
+
+        assert_eq!(config.database.host, "db");
+        assert_eq!(config.database.port, 6432);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].line.starts_with(
+            "GET /metadata/identity/oauth2/token?api-version=2018-02-01&resource=http"
+        ));
+        assert!(requests[0].line.contains("&client_id=client "));
+        assert!(requests[0].has_header("Metadata: true"));
+        assert_eq!(
+            requests[1].line,
+            "GET /kv?key=myapp%3A*&label=%00&api-version=1.0 HTTP/1.1"
+        );
+        assert!(requests[1].has_header("Authorization: Bearer store-token"));
+        assert_eq!(requests[2].line, "GET /kv?after=1 HTTP/1.1");
+        assert_eq!(
+            requests[3].line,
+            "GET /kv?key=myapp%3A*&label=staging&api-version=1.0 HTTP/1.1"
+        );
+        assert!(requests[4].body.is_empty());
+    }
+
+    #[test]
+    fn test_key_vault_reference() {
+        let (url, server) = serve(vec![
+            status("403 Forbidden"),
+            ok("application/json", r#"{ "value": "hunter2" }"#),
+        ]);
+
+        let source = AzureAppConfigSource::new(&url, AzureCredential::with_token("token"));
+        let reference = json!({ "uri": format!("{url}/secrets/db-password") }).to_string();
+
+        assert!(source
+            .resolve_reference("database:password", &reference)
+            .unwrap_err()
+            .contains("permission denied"));
+        assert_eq!(
+            source.resolve_reference("database:password", &reference),
+            Ok("hunter2".to_string())
+        );
+        assert!(source
+            .resolve_reference("database:password", "{}")
+            .unwrap_err()
+            .contains("no uri"));
+
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use crate::Error;
+
+/// Reports changes to a remote source, so the configuration can be reloaded.
+///
+/// Watchers are returned by [`RedisSource::watch`](crate::RedisSource::watch),
+/// [`ZooKeeperSource::watch`](crate::ZooKeeperSource::watch) and
+/// [`GrpcSource::watch`](crate::GrpcSource::watch), by [`ChangeWatcher::files`] for the
+/// configuration files, and by [`ChangeWatcher::sighup`] for operators reloading with
+/// `kill -HUP`. [`SharedConfig::reload_on`](crate::SharedConfig::reload_on)
+/// reloads a shared configuration each time a watcher reports a change.
+///
+/// Notifications are received on a background thread, which stops when the watcher is dropped
+/// and the next notification arrives.
+pub struct ChangeWatcher {
+    name: String,
+    receiver: Receiver<Result<(), String>>,
+    /// Disconnects when the watcher is dropped, stopping the timer of a watcher from
+    /// [`ChangeWatcher::every`] without waiting for its next tick.
+    timer: Option<Sender<()>>,
+}
+
+impl ChangeWatcher {
+    /// Creates a watcher for the notifications called `name`, returning the sender that
+    /// changes, or the error that stopped them, are reported with, so applications can reload on
+    /// triggers of their own.
+    #[must_use]
+    pub fn channel(name: impl Into<String>) -> (Sender<Result<(), String>>, Self) {
+        let (sender, receiver) = mpsc::channel();
+        let watcher = Self {
+            name: name.into(),
+            receiver,
+            timer: None,
+        };
+        (sender, watcher)
+    }
+
+    /// Reports a change every `interval`, for sources that can only be polled. Reloading a
+    /// [`SharedConfig`](crate::SharedConfig) whose content has not changed leaves it as it is,
+    /// so only actual changes reach its subscribers. The timer stops when the watcher is
+    /// dropped.
+    #[must_use]
+    pub fn every(interval: Duration) -> Self {
+        let (sender, mut watcher) = Self::channel("refresh timer");
+        let (timer, stopped) = mpsc::channel::<()>();
+        watcher.timer = Some(timer);
+        thread::spawn(move || {
+            while stopped.recv_timeout(interval) == Err(RecvTimeoutError::Timeout) {
+                if sender.send(Ok(())).is_err() {
+                    break;
+                }
+            }
+        });
+        watcher
+    }
+
+    /// Reports changes to the files in `dir` and its subdirectories, such as `conf.d`, as they
+    /// are created, modified or removed. Editors and orchestrators often write several files
+    /// at once, so the watcher is usually [debounced](Self::debounce).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ExternalError`] if `dir` cannot be watched.
+    #[cfg(feature = "file-watch")]
+    pub fn files(dir: &std::path::Path) -> Result<Self, Error> {
+        use notify::{recommended_watcher, EventKind, RecursiveMode, Watcher};
+
+        let (events, received) = mpsc::channel();
+        let files = recommended_watcher(events)
+            .and_then(|mut files| files.watch(dir, RecursiveMode::Recursive).map(|()| files))
+            .map_err(|e| Error::ExternalError {
+                context: format!("unable to watch {}", dir.display()),
+                source: Box::new(e),
+            })?;
+        let (sender, watcher) = Self::channel(format!("file watch on {}", dir.display()));
+        thread::spawn(move || {
+            for event in received {
+                let change = match event {
+                    Ok(event) => match event.kind {
+                        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
+                            Ok(())
+                        }
+                        _ => continue,
+                    },
+                    Err(e) => Err(e.to_string()),
+                };
+                if sender.send(change).is_err() {
+                    break;
+                }
+            }
+            drop(files);
+        });
+        Ok(watcher)
+    }
+
+    /// Reports each `SIGHUP` the process receives. The signal no longer terminates the process
+    /// once this is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ExternalError`] if the signal handler cannot be installed.
+    #[cfg(all(unix, feature = "sighup"))]
+    pub fn sighup() -> Result<Self, Error> {
+        use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+        let mut signals = Signals::new([SIGHUP]).map_err(|e| Error::ExternalError {
+            context: "unable to handle SIGHUP".to_string(),
+            source: Box::new(e),
+        })?;
+        let (sender, watcher) = Self::channel("SIGHUP handler");
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                if sender.send(Ok(())).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(watcher)
+    }
+
+    /// Holds back changes until none has arrived for `window`, then reports them once, so a
+    /// burst of changes, such as several files written in quick succession, causes one reload
+    /// rather than one per change.
+    #[must_use]
+    pub fn debounce(self, window: Duration) -> Self {
+        let (sender, mut watcher) = Self::channel(self.name);
+        watcher.timer = self.timer;
+        let receiver = self.receiver;
+        thread::spawn(move || {
+            while let Ok(mut change) = receiver.recv() {
+                while change.is_ok() {
+                    match receiver.recv_timeout(window) {
+                        Ok(next) => change = next,
+                        Err(_) => break,
+                    }
+                }
+                let failed = change.is_err();
+                if sender.send(change).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        watcher
+    }
+
+    /// Waits up to `timeout`, or indefinitely if `None`, for a change, returning whether one
+    /// arrived. Changes that arrive together, such as the fields of one Redis `HSET`, are
+    /// reported once.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ConfigError`] if the connection to the source was lost, after which it
+    /// should be watched again.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool, Error> {
+        let received = match timeout {
+            Some(timeout) => match self.receiver.recv_timeout(timeout) {
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                received => received.map_err(|_| ()),
+            },
+            None => self.receiver.recv().map_err(|_| ()),
+        };
+        let lost = |message: String| Error::ConfigError(format!("lost {}: {message}", self.name));
+        received
+            .map_err(|()| lost("connection closed".to_string()))?
+            .map_err(lost)?;
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Ok(())) => {}
+                Ok(Err(_)) | Err(TryRecvError::Empty | TryRecvError::Disconnected) => {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debounce() {
+        let (sender, watcher) = ChangeWatcher::channel("test changes");
+        let watcher = watcher.debounce(Duration::from_millis(100));
+
+        for _ in 0..5 {
+            sender.send(Ok(())).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!watcher.wait(Some(Duration::from_millis(20))).unwrap());
+        assert!(watcher.wait(Some(Duration::from_secs(5))).unwrap());
+        assert!(!watcher.wait(Some(Duration::from_millis(200))).unwrap());
+
+        sender.send(Ok(())).unwrap();
+        sender.send(Err("connection reset".to_string())).unwrap();
+        let error = watcher.wait(Some(Duration::from_secs(5))).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("lost test changes: connection reset"));
+    }
+}
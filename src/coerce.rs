@@ -0,0 +1,671 @@
+use {
+    crate::{
+        suggestion::closest,
+        token_expander::{format_new_array_path, format_new_path},
+        Error, UnknownKey,
+    },
+    serde::{
+        de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Unexpected, Visitor},
+        forward_to_deserialize_any, Deserializer, Serialize,
+    },
+    serde_json::{map, Value},
+    std::{cell::RefCell, collections::HashMap, iter::Enumerate, str::FromStr, vec},
+};
+
+/// Deserializes an expanded configuration value into `C`, coercing strings into numeric and
+/// boolean fields where they parse as such.
+///
+/// Token expansion always produces strings, so `port = "${vars.port}"` yields `"8080"` even when
+/// the target field is a `u16`. Mismatches are reported with the path of the offending value.
+pub fn deserialize_coerced<C: DeserializeOwned>(value: Value) -> Result<C, Error> {
+    deserialize_with_paths(Lenient::new(value))
+}
+
+/// Deserializes as [`deserialize_coerced`] does, also returning the values that no field of
+/// `C` consumed, such as misspelled keys, in the order they were found, each with the field it
+/// was probably meant for.
+pub fn deserialize_coerced_unused<C: DeserializeOwned>(
+    value: Value,
+) -> Result<(C, Vec<UnknownKey>), Error> {
+    let fields = RefCell::new(HashMap::new());
+    let mut unused = Vec::new();
+    let mut track = |path: serde_ignored::Path| unused.push(unknown_key(&path));
+    let lenient = Lenient {
+        value,
+        collector: Some(Collector::new(None, &fields)),
+    };
+    let config = deserialize_with_paths(serde_ignored::Deserializer::new(lenient, &mut track))?;
+    Ok((config, suggest_fields(unused, &fields.into_inner())))
+}
+
+/// Deserializes as [`deserialize_coerced_unused`] does, but carries on past numbers, booleans
+/// and strings that do not fit their fields, returning an [`Error::InvalidValue`] for each
+/// rather than failing at the first.
+///
+/// A mismatch that deserialization cannot carry on past, such as a table where a string is
+/// expected, is reported after those found before it.
+pub fn deserialize_coerced_collecting<C: DeserializeOwned>(
+    value: Value,
+) -> Result<(C, Vec<UnknownKey>), Vec<Error>> {
+    let mismatches = RefCell::new(Vec::new());
+    let fields = RefCell::new(HashMap::new());
+    let mut unused = Vec::new();
+    let mut track = |path: serde_ignored::Path| unused.push(unknown_key(&path));
+    let lenient = Lenient {
+        value,
+        collector: Some(Collector::new(Some(&mismatches), &fields)),
+    };
+    let result = deserialize_with_paths(serde_ignored::Deserializer::new(lenient, &mut track));
+
+    let mut errors = mismatches.into_inner();
+    match result {
+        Ok(config) if errors.is_empty() => {
+            Ok((config, suggest_fields(unused, &fields.into_inner())))
+        }
+        Ok(_) => Err(errors),
+        Err(e) => {
+            errors.push(e);
+            Err(errors)
+        }
+    }
+}
+
+/// Deserializes `value` into `C` as far as it can, carrying on past values that do not fit their
+/// fields, such as tokens yet to be expanded, and serializes the result, so that the defaults
+/// `C` gives the fields missing from `value` can be read. Returns `None` if a mismatch cannot be
+/// carried on past.
+pub fn serialized_defaults<C: DeserializeOwned + Serialize>(value: &Value) -> Option<Value> {
+    let mismatches = RefCell::new(Vec::new());
+    let fields = RefCell::new(HashMap::new());
+    let lenient = Lenient {
+        value: value.clone(),
+        collector: Some(Collector::new(Some(&mismatches), &fields)),
+    };
+    let config = C::deserialize(lenient).ok()?;
+    serde_json::to_value(config).ok()
+}
+
+fn deserialize_with_paths<'de, C, D>(deserializer: D) -> Result<C, Error>
+where
+    C: DeserializeOwned,
+    D: Deserializer<'de, Error = serde_json::Error>,
+{
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        let error = e.into_inner();
+        if path == "." {
+            Error::DeserializationError(error)
+        } else {
+            Error::InvalidValue {
+                path,
+                message: error.to_string(),
+                location: None,
+            }
+        }
+    })
+}
+
+/// Formats a path reported by `serde_ignored` in the same dotted form as other paths.
+fn ignored_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => {
+            format_new_array_path(&ignored_path(parent), *index)
+        }
+        serde_ignored::Path::Map { parent, key } => format_new_path(&ignored_path(parent), key),
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => ignored_path(parent),
+    }
+}
+
+/// An unused key, split into the path of the value it was found in and its name, to be
+/// matched with the fields of that value once they are known.
+fn unknown_key(path: &serde_ignored::Path) -> (String, Option<String>) {
+    match path {
+        serde_ignored::Path::Map { parent, key } => (ignored_path(parent), Some(key.clone())),
+        path => (ignored_path(path), None),
+    }
+}
+
+/// Turns the unused keys found into [`UnknownKey`]s, suggesting the field of the same struct
+/// closest to each.
+fn suggest_fields(unused: Vec<(String, Option<String>)>, fields: &Fields) -> Vec<UnknownKey> {
+    unused
+        .into_iter()
+        .map(|(parent, key)| {
+            let Some(key) = key else {
+                return UnknownKey {
+                    path: parent,
+                    suggestion: None,
+                };
+            };
+            let suggestion = fields
+                .get(&parent)
+                .and_then(|fields| closest(&key, fields.iter().copied()))
+                .map(|field| format_new_path(&parent, field));
+            UnknownKey {
+                path: format_new_path(&parent, &key),
+                suggestion,
+            }
+        })
+        .collect()
+}
+
+/// The fields of each struct deserialized, by path.
+type Fields = HashMap<String, &'static [&'static str]>;
+
+/// A deserializer over a [`Value`] that accepts strings for numbers and booleans, and numbers
+/// and booleans for strings, as environment variables such as `APP__NAME=123` are parsed into.
+struct Lenient<'a> {
+    value: Value,
+    /// What is collected along the way, when unused keys or mismatches are reported.
+    collector: Option<Collector<'a>>,
+}
+
+/// The mismatches collected by [`deserialize_coerced_collecting`] and the fields of the structs
+/// deserialized, with the path of the value being deserialized.
+#[derive(Clone)]
+struct Collector<'a> {
+    /// Where mismatched values are collected, when deserialization carries on past them.
+    mismatches: Option<&'a RefCell<Vec<Error>>>,
+    fields: &'a RefCell<Fields>,
+    path: String,
+}
+
+impl<'a> Collector<'a> {
+    const fn new(mismatches: Option<&'a RefCell<Vec<Error>>>, fields: &'a RefCell<Fields>) -> Self {
+        Self {
+            mismatches,
+            fields,
+            path: String::new(),
+        }
+    }
+}
+
+impl<'a> Lenient<'a> {
+    const fn new(value: Value) -> Self {
+        Self {
+            value,
+            collector: None,
+        }
+    }
+
+    /// A deserializer for `value`, nested in this one at the path given by `path`.
+    fn nested(
+        value: Value,
+        collector: Option<&Collector<'a>>,
+        path: impl FnOnce(&str) -> String,
+    ) -> Self {
+        Self {
+            value,
+            collector: collector.map(|collector| Collector {
+                path: path(&collector.path),
+                ..*collector
+            }),
+        }
+    }
+
+    fn parse<T: FromStr>(&self) -> Option<T> {
+        match &self.value {
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Whether the value fits, according to `fits`, or mismatches are not being collected.
+    fn fits(&self, fits: impl FnOnce(&Value) -> bool) -> bool {
+        self.collector
+            .as_ref()
+            .is_none_or(|collector| collector.mismatches.is_none())
+            || fits(&self.value)
+    }
+
+    /// Records that the value does not fit the field `visitor` expects, and deserializes
+    /// `placeholder` in its place so that deserialization carries on. If the placeholder does
+    /// not fit either, the mismatch is returned as the error that stops deserialization.
+    fn mismatch<'de, V: Visitor<'de>>(
+        self,
+        visitor: V,
+        integer: bool,
+        placeholder: impl FnOnce(V) -> Result<V::Value, serde_json::Error>,
+    ) -> Result<V::Value, serde_json::Error> {
+        let error: serde_json::Error = match unexpected(&self.value, integer) {
+            (unexpected, true) => de::Error::invalid_value(unexpected, &visitor),
+            (unexpected, false) => de::Error::invalid_type(unexpected, &visitor),
+        };
+        match (placeholder(visitor), self.collector) {
+            (
+                Ok(value),
+                Some(Collector {
+                    mismatches: Some(mismatches),
+                    path,
+                    ..
+                }),
+            ) => {
+                mismatches.borrow_mut().push(Error::InvalidValue {
+                    path,
+                    message: error.to_string(),
+                    location: None,
+                });
+                Ok(value)
+            }
+            _ => Err(error),
+        }
+    }
+}
+
+/// Describes `value` as serde does in its errors, and whether it is of the right type but an
+/// invalid value, as an out of range number is for an `integer` field.
+fn unexpected(value: &Value, integer: bool) -> (Unexpected<'_>, bool) {
+    let integer_value = match value {
+        Value::Number(n) => n
+            .as_i64()
+            .map(Unexpected::Signed)
+            .or_else(|| n.as_u64().map(Unexpected::Unsigned)),
+        Value::String(s) => s.trim().parse().ok().map(Unexpected::Signed),
+        _ => None,
+    };
+    if let (true, Some(unexpected)) = (integer, integer_value) {
+        return (unexpected, true);
+    }
+
+    let unexpected = match value {
+        Value::Null => Unexpected::Unit,
+        Value::Bool(b) => Unexpected::Bool(*b),
+        Value::Number(n) => {
+            integer_value.unwrap_or_else(|| Unexpected::Float(n.as_f64().unwrap_or_default()))
+        }
+        Value::String(s) => Unexpected::Str(s),
+        Value::Array(_) => Unexpected::Seq,
+        Value::Object(_) => Unexpected::Map,
+    };
+    (unexpected, false)
+}
+
+/// Whether `value` is, or is a string holding, an integer that fits in `T`.
+fn integer_fits<T: TryFrom<i128> + FromStr>(value: &Value) -> bool {
+    match value {
+        Value::Number(n) => n
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from))
+            .is_some_and(|n| T::try_from(n).is_ok()),
+        Value::String(s) => s.trim().parse::<T>().is_ok(),
+        _ => false,
+    }
+}
+
+/// Whether `value` is, or is a string holding, a `T`.
+fn parses_as<T: FromStr>(value: &Value, is_t: impl FnOnce(&Value) -> bool) -> bool {
+    is_t(value)
+        || value
+            .as_str()
+            .is_some_and(|s| s.trim().parse::<T>().is_ok())
+}
+
+macro_rules! coerce_integer {
+    ($($method:ident: $ty:ty)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                if !self.fits(integer_fits::<$ty>) {
+                    self.mismatch(visitor, true, |visitor| visitor.visit_u8(0))
+                } else if let Some(n) = self.parse::<i64>() {
+                    visitor.visit_i64(n)
+                } else if let Some(n) = self.parse::<u64>() {
+                    visitor.visit_u64(n)
+                } else {
+                    self.deserialize_any(visitor)
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Lenient<'_> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Array(values) => visitor.visit_seq(LenientSeq {
+                values: values.into_iter().enumerate(),
+                collector: self.collector,
+            }),
+            Value::Object(map) => visitor.visit_map(LenientMap {
+                entries: map.into_iter(),
+                value: None,
+                collector: self.collector,
+            }),
+            other => other.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if !self.fits(|value| parses_as::<bool>(value, Value::is_boolean)) {
+            return self.mismatch(visitor, false, |visitor| visitor.visit_bool(false));
+        }
+        match self.parse::<bool>() {
+            Some(b) => visitor.visit_bool(b),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    coerce_integer! {
+        deserialize_i8: i8 deserialize_i16: i16 deserialize_i32: i32 deserialize_i64: i64
+        deserialize_i128: i128 deserialize_u8: u8 deserialize_u16: u16 deserialize_u32: u32
+        deserialize_u64: u64 deserialize_u128: u128
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if !self.fits(|value| parses_as::<f64>(value, Value::is_number)) {
+            return self.mismatch(visitor, false, |visitor| visitor.visit_f64(0.0));
+        }
+        match self.parse::<f64>() {
+            Some(n) => visitor.visit_f64(n),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match &self.value {
+            Value::Number(n) => visitor.visit_string(n.to_string()),
+            Value::Bool(b) => visitor.visit_string(b.to_string()),
+            _ if self.fits(Value::is_string) => self.deserialize_any(visitor),
+            _ => self.mismatch(visitor, false, |visitor| visitor.visit_str("")),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if let Some(collector) = &self.collector {
+            let path = collector.path.clone();
+            collector.fields.borrow_mut().insert(path, fields);
+        }
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.value.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        char bytes byte_buf unit unit_struct seq tuple tuple_struct map identifier
+        ignored_any
+    }
+}
+
+struct LenientSeq<'a> {
+    values: Enumerate<vec::IntoIter<Value>>,
+    collector: Option<Collector<'a>>,
+}
+
+impl<'de> SeqAccess<'de> for LenientSeq<'_> {
+    type Error = serde_json::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        self.values
+            .next()
+            .map(|(index, value)| {
+                seed.deserialize(Lenient::nested(value, self.collector.as_ref(), |path| {
+                    format_new_array_path(path, index)
+                }))
+            })
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct LenientMap<'a> {
+    entries: map::IntoIter,
+    value: Option<(String, Value)>,
+    collector: Option<Collector<'a>>,
+}
+
+impl<'de> MapAccess<'de> for LenientMap<'_> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some((key.clone(), value));
+        seed.deserialize(Lenient::new(Value::String(key))).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value is missing"))?;
+        seed.deserialize(Lenient::nested(value, self.collector.as_ref(), |path| {
+            format_new_path(path, &key)
+        }))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde::Deserialize, serde_json::json, std::collections::HashMap};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Server {
+        port: u16,
+        ratio: f64,
+        enabled: bool,
+        name: String,
+        retries: Option<i32>,
+        weights: Vec<u8>,
+        limits: HashMap<u16, u32>,
+    }
+
+    #[test]
+    fn test_coerces_strings() {
+        let server: Server = deserialize_coerced(json!({
+            "port": "8080",
+            "ratio": "0.5",
+            "enabled": "true",
+            "name": 8080,
+            "retries": "3",
+            "weights": ["1", 2],
+            "limits": {"80": "100"}
+        }))
+        .unwrap();
+
+        assert_eq!(
+            server,
+            Server {
+                port: 8080,
+                ratio: 0.5,
+                enabled: true,
+                name: "8080".to_string(),
+                retries: Some(3),
+                weights: vec![1, 2],
+                limits: HashMap::from([(80, 100)]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_mismatch_names_path() {
+        #[derive(Debug, Deserialize)]
+        struct Config {
+            #[allow(dead_code)]
+            servers: Vec<Server>,
+        }
+
+        let result: Result<Config, _> = deserialize_coerced(json!({
+            "servers": [{
+                "port": "70000",
+                "ratio": 1,
+                "enabled": false,
+                "name": "a",
+                "retries": null,
+                "weights": [],
+                "limits": {}
+            }]
+        }));
+
+        match result {
+            Err(Error::InvalidValue { path, message, .. }) => {
+                assert_eq!(path, "servers[0].port");
+                assert!(message.contains("70000"), "{message}");
+            }
+            other => panic!("expected InvalidValue, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_preserves_source() {
+        let error = deserialize_coerced::<Vec<u16>>(json!({"port": 80})).unwrap_err();
+        assert!(matches!(error, Error::DeserializationError(_)), "{error:?}");
+        let source = std::error::Error::source(&error).unwrap();
+        assert!(source.downcast_ref::<serde_json::Error>().is_some());
+    }
+
+    #[test]
+    fn test_collects_mismatches() {
+        let value = json!({
+            "port": "eighty",
+            "ratio": "half",
+            "enabled": "yes",
+            "name": ["a"],
+            "retries": 3,
+            "weights": [1, 300],
+            "limits": {"80": "100"}
+        });
+        let errors = deserialize_coerced_collecting::<Server>(value).unwrap_err();
+        let errors: Vec<(String, String)> = errors
+            .into_iter()
+            .map(|error| match error {
+                Error::InvalidValue { path, message, .. } => (path, message),
+                other => panic!("expected InvalidValue, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    "enabled".to_string(),
+                    "invalid type: string \"yes\", expected a boolean".to_string()
+                ),
+                (
+                    "name".to_string(),
+                    "invalid type: sequence, expected a string".to_string()
+                ),
+                (
+                    "port".to_string(),
+                    "invalid type: string \"eighty\", expected u16".to_string()
+                ),
+                (
+                    "ratio".to_string(),
+                    "invalid type: string \"half\", expected f64".to_string()
+                ),
+                (
+                    "weights[1]".to_string(),
+                    "invalid value: integer `300`, expected u8".to_string()
+                ),
+            ]
+        );
+
+        let (server, unused): (Server, _) = deserialize_coerced_collecting(json!({
+            "port": "8080",
+            "ratio": 1,
+            "enabled": true,
+            "name": "a",
+            "retries": null,
+            "weights": [],
+            "limits": {},
+            "prot": 80
+        }))
+        .unwrap();
+        assert_eq!(server.port, 8080);
+        assert_eq!(
+            unused,
+            [UnknownKey {
+                path: "prot".to_string(),
+                suggestion: Some("port".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reports_unused_keys() {
+        #[derive(Debug, Deserialize)]
+        struct Website {
+            port: u16,
+            #[serde(default)]
+            hosts: Vec<Host>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Host {
+            name: String,
+        }
+
+        let (website, unused): (Website, _) = deserialize_coerced_unused(json!({
+            "port": "8080",
+            "prot": 80,
+            "hosts": [{"name": "a"}, {"name": "b", "nmae": "c"}],
+        }))
+        .unwrap();
+
+        assert_eq!(website.port, 8080);
+        assert_eq!(website.hosts[1].name, "b");
+        let unused: Vec<String> = unused.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            unused,
+            [
+                "hosts[1].nmae (did you mean hosts[1].name?)",
+                "prot (did you mean port?)"
+            ]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::{collections::BTreeSet, fmt};
+
+use serde_json::Value;
+
+use crate::token_expander::{format_new_array_path, format_new_path, path_matches_pattern};
+
+/// What replaces the values of secrets in a [`ConfigChange`].
+pub const REDACTED: &str = "[redacted]";
+
+/// Words that mark a key as holding a secret wherever they appear in its name, such as
+/// `password` in `db_password`.
+const SECRET_WORDS: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "credential",
+    "private_key",
+    "api_key",
+    "apikey",
+];
+
+/// A value that a reload added, removed or modified, as reported by
+/// [`SharedConfig::on_change`](crate::SharedConfig::on_change).
+///
+/// Paths use the same dotted form as error paths, e.g. `servers[0].port`. The values of
+/// secrets, whose keys contain words such as `password`, `secret` or `token`, or which match
+/// a pattern given to [`SharedConfig::redact`](crate::SharedConfig::redact), are replaced with
+/// [`REDACTED`], so changes can be logged as they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigChange {
+    Added {
+        path: String,
+        value: Value,
+    },
+    Removed {
+        path: String,
+        value: Value,
+    },
+    Modified {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl ConfigChange {
+    /// The path of the value that changed.
+    #[must_use]
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Added { path, .. } | Self::Removed { path, .. } | Self::Modified { path, .. } => {
+                path
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added { path, value } => write!(f, "added {path} = {value}"),
+            Self::Removed { path, value } => write!(f, "removed {path} (was {value})"),
+            Self::Modified { path, old, new } => write!(f, "changed {path} from {old} to {new}"),
+        }
+    }
+}
+
+/// Lists the values that differ between `old` and `new` in order of their paths, down to the
+/// leaves that both have, redacting the secrets among them and those matching `redactions`.
+pub fn diff(old: &Value, new: &Value, redactions: &[String]) -> Vec<ConfigChange> {
+    let mut changes = Vec::new();
+    diff_at("", Some(old), Some(new), redactions, &mut changes);
+    changes
+}
+
+fn diff_at(
+    path: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    redactions: &[String],
+    changes: &mut Vec<ConfigChange>,
+) {
+    match (old, new) {
+        (Some(Value::Object(old)), Some(Value::Object(new))) => {
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                diff_at(
+                    &format_new_path(path, key),
+                    old.get(key),
+                    new.get(key),
+                    redactions,
+                    changes,
+                );
+            }
+        }
+        (Some(Value::Array(old)), Some(Value::Array(new))) => {
+            for index in 0..old.len().max(new.len()) {
+                diff_at(
+                    &format_new_array_path(path, index),
+                    old.get(index),
+                    new.get(index),
+                    redactions,
+                    changes,
+                );
+            }
+        }
+        (Some(old), Some(new)) if old == new => {}
+        (None, Some(value)) => changes.push(ConfigChange::Added {
+            path: path.to_string(),
+            value: redact(path, value, redactions),
+        }),
+        (Some(value), None) => changes.push(ConfigChange::Removed {
+            path: path.to_string(),
+            value: redact(path, value, redactions),
+        }),
+        (Some(old), Some(new)) => changes.push(ConfigChange::Modified {
+            path: path.to_string(),
+            old: redact(path, old, redactions),
+            new: redact(path, new, redactions),
+        }),
+        (None, None) => {}
+    }
+}
+
+/// Returns `value`, found at `path`, with the secrets within it replaced by [`REDACTED`].
+fn redact(path: &str, value: &Value, redactions: &[String]) -> Value {
+    if is_secret(path, redactions) {
+        return Value::String(REDACTED.to_string());
+    }
+    match value {
+        Value::Object(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| {
+                    let value = redact(&format_new_path(path, key), value, redactions);
+                    (key.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| redact(&format_new_array_path(path, index), item, redactions))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+fn is_secret(path: &str, redactions: &[String]) -> bool {
+    let key = path.rsplit('.').next().unwrap_or(path).to_lowercase();
+    SECRET_WORDS.iter().any(|word| key.contains(word))
+        || redactions
+            .iter()
+            .any(|pattern| path_matches_pattern(path, pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let old = json!({
+            "website": { "port": 80, "hosts": ["a", "b"] },
+            "database": { "url": "postgres://db/app", "password": "old" },
+            "legacy": true,
+        });
+        let new = json!({
+            "website": { "port": 8080, "hosts": ["a"] },
+            "database": { "url": "postgres://replica/app", "password": "new" },
+            "cache": { "ttl": 60, "auth_token": "abc" },
+        });
+        let changes = diff(&old, &new, &["database.url".to_string()]);
+        assert_eq!(changes[0].path(), "cache");
+        let described: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            described,
+            [
+                r#"added cache = {"auth_token":"[redacted]","ttl":60}"#,
+                r#"changed database.password from "[redacted]" to "[redacted]""#,
+                r#"changed database.url from "[redacted]" to "[redacted]""#,
+                "removed legacy (was true)",
+                r#"removed website.hosts[1] (was "b")"#,
+                "changed website.port from 80 to 8080",
+            ]
+        );
+        assert!(diff(&old, &old, &[]).is_empty());
+    }
+}
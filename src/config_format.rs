@@ -0,0 +1,211 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider,
+};
+use serde_json::Value;
+
+#[cfg(any(feature = "json5", feature = "ron", feature = "ini"))]
+use {figment::providers::Format, serde::de::DeserializeOwned};
+
+#[cfg(feature = "ini")]
+use serde_json::Map;
+
+/// A configuration file format that the loader can be taught via
+/// [`GraftonConfigBuilder::format`](crate::GraftonConfigBuilder::format).
+///
+/// Files with one of the format's extensions are picked up for each configuration layer, after
+/// the built-in formats, e.g. `default.properties` and `local.properties`.
+pub trait ConfigFormat: Send + Sync {
+    /// The file extensions handled by this format, without the leading dot.
+    fn extensions(&self) -> &[&str];
+
+    /// Parses the contents of a configuration file. The result must be a table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `contents` is not valid for this format.
+    fn parse(&self, contents: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// A figment provider for a file parsed by a [`ConfigFormat`].
+pub struct FormatFile {
+    format: Arc<dyn ConfigFormat>,
+    path: PathBuf,
+}
+
+impl FormatFile {
+    pub fn new(format: Arc<dyn ConfigFormat>, path: &Path) -> Self {
+        Self {
+            format,
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl Provider for FormatFile {
+    fn metadata(&self) -> Metadata {
+        Metadata::from("configuration file", self.path.as_path())
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("unable to read {}: {e}", self.path.display()))?;
+        let value = self
+            .format
+            .parse(&contents)
+            .map_err(|e| format!("unable to parse {}: {e}", self.path.display()))?;
+        Serialized::defaults(value).data()
+    }
+}
+
+/// JSON5: JSON extended with comments, trailing commas, unquoted keys and single quoted strings.
+#[cfg(feature = "json5")]
+pub struct Json5;
+
+#[cfg(feature = "json5")]
+impl Format for Json5 {
+    type Error = json5::Error;
+
+    const NAME: &'static str = "JSON5";
+
+    fn from_str<T: DeserializeOwned>(string: &str) -> Result<T, Self::Error> {
+        json5::from_str(string)
+    }
+}
+
+/// RON, Rusty Object Notation, whose structs, tuples and enums read like Rust literals.
+#[cfg(feature = "ron")]
+pub struct Ron;
+
+#[cfg(feature = "ron")]
+impl Format for Ron {
+    type Error = ron::Error;
+
+    const NAME: &'static str = "RON";
+
+    fn from_str<T: DeserializeOwned>(string: &str) -> Result<T, Self::Error> {
+        use serde::de::Error;
+
+        // Parse through a self-describing value first, so that an anonymous struct such as
+        // `(port: 80)` may form the top level table
+        let value: serde_json::Value = ron::from_str(string).map_err(ron::Error::custom)?;
+        T::deserialize(value).map_err(ron::Error::custom)
+    }
+}
+
+/// INI, as used by legacy configuration. The values of the general section form the top level
+/// table and each `[section]` a table of its own, with dots in section names nesting tables, e.g.
+/// `[website.plugin]`. All values are strings, which are coerced into typed fields on load.
+#[cfg(feature = "ini")]
+pub struct Ini;
+
+#[cfg(feature = "ini")]
+impl Format for Ini {
+    type Error = figment::Error;
+
+    const NAME: &'static str = "INI";
+
+    fn from_str<T: DeserializeOwned>(string: &str) -> Result<T, Self::Error> {
+        use serde::de::Error;
+
+        let file = ini::Ini::load_from_str(string).map_err(figment::Error::custom)?;
+
+        let mut root = Map::new();
+        for (section, properties) in &file {
+            let table = match section {
+                Some(name) => section_table(&mut root, name).map_err(figment::Error::custom)?,
+                None => &mut root,
+            };
+            for (key, value) in properties {
+                table.insert(key.to_string(), Value::String(value.to_string()));
+            }
+        }
+
+        T::deserialize(Value::Object(root)).map_err(figment::Error::custom)
+    }
+}
+
+#[cfg(feature = "ini")]
+fn section_table<'a>(
+    root: &'a mut Map<String, Value>,
+    name: &str,
+) -> Result<&'a mut Map<String, Value>, String> {
+    name.split('.').try_fold(root, |table, key| {
+        match table
+            .entry(key)
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(child) => Ok(child),
+            _ => Err(format!("section `{name}` conflicts with the value `{key}`")),
+        }
+    })
+}
+
+#[cfg(all(test, any(feature = "json5", feature = "ron", feature = "ini")))]
+mod tests {
+    use {super::*, serde_json::json, serde_json::Value};
+
+    #[cfg(feature = "ron")]
+    #[test]
+    fn test_ron() {
+        let value: Value = Ron::from_str(
+            r#"(
+                // comments are allowed
+                name: "grafton",
+                ports: [80, 443],
+                tls: Some(true),
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({"name": "grafton", "ports": [80, 443], "tls": true})
+        );
+
+        assert!(Ron::from_str::<Value>("(name: )").is_err());
+    }
+
+    #[cfg(feature = "ini")]
+    #[test]
+    fn test_ini() {
+        let value: Value = Ini::from_str(
+            "; legacy settings\n\
+             name = grafton\n\
+             [website]\n\
+             port = 8080\n\
+             [website.plugin]\n\
+             enabled = true\n",
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "name": "grafton",
+                "website": {"port": "8080", "plugin": {"enabled": "true"}}
+            })
+        );
+
+        assert!(Ini::from_str::<Value>("name = a\n[name]\nkey = b\n").is_err());
+    }
+
+    #[cfg(feature = "json5")]
+    #[test]
+    fn test_json5() {
+        let value: Value = Json5::from_str(
+            r"{
+                // comments are allowed
+                name: 'grafton',
+                ports: [80, 443,],
+            }",
+        )
+        .unwrap();
+        assert_eq!(value, json!({"name": "grafton", "ports": [80, 443]}));
+    }
+}
@@ -1,232 +1,4140 @@
 #![allow(clippy::module_name_repetitions)]
 
 use std::{
-    env,
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    io::Read,
     path::{Path, PathBuf},
-    sync::Mutex,
+    sync::Arc,
+    time::Instant,
 };
 
+#[cfg(feature = "reload")]
+use std::time::Duration;
+
 use figment::{
-    providers::{Format, Toml},
-    Figment,
+    providers::{Env, Format, Json, Toml, Yaml},
+    value::{Dict, Map as ProfileMap, Uncased},
+    Figment, Metadata, Profile, Provider,
 };
 use serde_json::Value;
 
-use crate::{token_expander::expand_tokens, Error, TokenExpandingConfig};
+#[cfg(feature = "json5")]
+use crate::config_format::Json5;
+
+#[cfg(feature = "ron")]
+use crate::config_format::Ron;
+
+#[cfg(feature = "ini")]
+use crate::config_format::Ini;
+
+#[cfg(feature = "json-schema")]
+use crate::schema_validation::validate_against_schema;
+#[cfg(feature = "reload")]
+use crate::SharedConfig;
+
+use crate::{
+    coerce::{
+        deserialize_coerced, deserialize_coerced_collecting, deserialize_coerced_unused,
+        serialized_defaults,
+    },
+    config_format::{ConfigFormat, FormatFile},
+    config_source::{block_on, load_sources, ConfigSource, SourceLayer},
+    load_report::{ConfigReport, LayerSummary, LoadReport, LoadWarning},
+    mounted_dir_source::MountedDirSource,
+    provenance::{provenance, value_source, Explanation, Provenance},
+    source_location::{locate, parse_error},
+    token_builtins::TokenResolver,
+    token_expander::{
+        expand_tokens_collecting, expand_tokens_counted, format_new_array_path, format_new_path,
+        get_value_from_path, split_path, ExpansionOptions,
+    },
+    Error, TokenExpandingConfig, UnknownKey, ValidatableConfig,
+};
+
+/// Placeholder within a layer name that is replaced by the active run mode.
+const RUN_MODE_PLACEHOLDER: &str = "{run_mode}";
+/// Placeholders within a layer name that are replaced by the host name and the user name.
+const HOSTNAME_PLACEHOLDER: &str = "{hostname}";
+const USERNAME_PLACEHOLDER: &str = "{username}";
+const HOST_AND_USER_LAYERS: &[&str] = &["hosts/{hostname}", "users/{username}"];
+const DEFAULT_LAYERS: &[&str] = &["default", "local", RUN_MODE_PLACEHOLDER, "local.{run_mode}"];
+const DROP_IN_DIR: &str = "conf.d";
+/// Where Docker Swarm and Compose mount secrets, and the token namespace they are read into.
+const DOCKER_SECRETS_DIR: &str = "/run/secrets";
+const SECRETS_NAMESPACE: &str = "secrets";
+const INCLUDE_KEY: &str = "include";
+const INCLUDE_DEPTH_LIMIT: usize = 16;
+
+/// File extensions recognised for each configuration layer, in the order they are merged.
+const CONFIG_EXTENSIONS: &[&str] = &[
+    "toml",
+    "yaml",
+    "yml",
+    "json",
+    #[cfg(feature = "json5")]
+    "json5",
+    #[cfg(feature = "ron")]
+    "ron",
+];
+const VARS_SECTION: &str = "vars";
+
+/// Key holding the version of the configuration's layout, see
+/// [`GraftonConfigBuilder::migration`].
+const CONFIG_VERSION_KEY: &str = "config_version";
+
+#[cfg(feature = "dotenv")]
+const DOTENV_FILE: &str = ".env";
+const META_RUN_MODE: &str = "__run_mode";
+const META_CONFIG_DIR: &str = "__config_dir";
+const DEFAULT_ENV_SEPARATOR: &str = "__";
+const DEFAULT_RUN_MODE_ENV: &str = "RUN_MODE";
+const ARGS_PROVIDER: &str = "command line arguments";
+
+/// Load configuration from the given directory.
+///
+/// The configuration is loaded from the following files in the given directory:
+/// - `default.toml`
+/// - `local.toml`
+/// - `{run_mode}.toml`
+/// - `local.{run_mode}.toml`
+///
+/// The run mode may list several modes separated by commas, e.g. `prod,eu,canary`, in which
+/// case the file for each mode is merged in turn.
+///
+/// Each file may instead be written in YAML, with a `.yaml` or `.yml` extension, in JSON, or,
+/// with the `json5` and `ron` features, in JSON5 and RON. Where a layer exists in more than one
+/// format, the files are merged in that order.
+///
+/// A file may pull in other files with a top level `include` array of paths relative to it.
+/// These are merged beneath the including file, so that it can override them.
+///
+/// Any files in a `conf.d` directory within the given directory are then merged in
+/// lexicographic order, so that packages and operators can add drop-in fragments.
+///
+/// With the `dotenv` feature, `.env` and `.env.{run_mode}` are merged above all other files.
+/// Their keys map to configuration paths by splitting on `__` and lowercasing, so
+/// `WEBSITE__PORT` sets `website.port`. Values containing tokens must be single quoted, as
+/// `.env` files expand `${VAR}` references within double quotes themselves.
+///
+/// With the `ini` feature, `default.ini`, `local.ini` and `{run_mode}.ini` are merged beneath
+/// all other files, so that settings from a legacy INI configuration can be overridden during a
+/// migration.
+///
+/// The merged configuration is extracted once as a raw value, token expanded, and then
+/// deserialized a single time into `C`, so serde attributes such as `skip_serializing_if`
+/// on the target type cannot drop data between passes.
+///
+/// Strings are coerced into numeric and boolean fields where they parse as such, so a token
+/// such as `port = "${vars.port}"` can populate a `u16`.
+///
+/// Tokens may reference fields of `C` that are not set but have a default, such as one given
+/// by `#[serde(default)]`, which resolve to that default. Defaults are otherwise left as they
+/// are, so tokens within them are only expanded where another value references them.
+///
+/// A top level `[vars]` table may hold helper values for tokens to reference. It is removed
+/// after expansion, so `C` needs no corresponding field.
+///
+/// Tokens may also reference `${__config_dir}`, the absolute path of the configuration
+/// directory, and `${__run_mode}`, the active run mode when one is set.
+///
+/// A relative `config_dir` is resolved against the current directory.
+///
+/// # Errors
+///
+/// This function returns an error if a configuration file cannot be read or parsed, if a token
+/// cannot be expanded, or if the merged configuration cannot be deserialized into `C`. Missing
+/// files are skipped rather than being an error.
+pub fn load_config_from_dir<C: TokenExpandingConfig>(
+    config_dir: impl AsRef<Path>,
+) -> Result<C, Error> {
+    load_config_from_dir_with_options(config_dir, &ExpansionOptions::default())
+}
+
+/// Load configuration from the given directory using the given run mode, or none, rather than
+/// the `RUN_MODE` environment variable.
+///
+/// See [`load_config_from_dir`] for the files that are loaded.
+///
+/// # Errors
+///
+/// This function returns an error if there is an error parsing or expanding the configuration.
+pub fn load_config_from_dir_with_mode<C: TokenExpandingConfig>(
+    config_dir: impl AsRef<Path>,
+    run_mode: Option<&str>,
+) -> Result<C, Error> {
+    let builder = GraftonConfigBuilder::new().dir(config_dir);
+    match run_mode {
+        Some(run_mode) => builder.run_mode(run_mode),
+        None => builder.without_run_mode(),
+    }
+    .load()
+}
+
+/// Checks the configuration in the given directory, as [`load_config_from_dir`] would load
+/// it, without failing at the first problem. Intended for validating configuration changes in
+/// CI.
+///
+/// See [`GraftonConfigBuilder::check`] for what is checked.
+pub fn check_config_dir<C: TokenExpandingConfig>(config_dir: impl AsRef<Path>) -> ConfigReport {
+    GraftonConfigBuilder::new().dir(config_dir).check::<C>()
+}
+
+/// Load configuration from the given directory, expanding tokens with the given
+/// [`ExpansionOptions`].
+///
+/// See [`load_config_from_dir`] for the files that are loaded.
+///
+/// # Errors
+///
+/// This function returns an error if there is an error parsing, expanding or deserializing the
+/// configuration, as for [`load_config_from_dir`].
+pub fn load_config_from_dir_with_options<C: TokenExpandingConfig>(
+    config_dir: impl AsRef<Path>,
+    options: &ExpansionOptions,
+) -> Result<C, Error> {
+    GraftonConfigBuilder::new()
+        .dir(config_dir)
+        .expansion_options(options.clone())
+        .load()
+}
+
+/// Load configuration for the application `app_name` from the first of its standard
+/// configuration directories that exists:
+///
+/// 1. the directory named by `{APP_NAME}_CONFIG_DIR`, e.g. `MYAPP_CONFIG_DIR`
+/// 2. `$XDG_CONFIG_HOME/{app_name}`
+/// 3. `~/.config/{app_name}`
+/// 4. `%APPDATA%\{app_name}` on Windows, or `/etc/{app_name}` elsewhere
+///
+/// The directory is then loaded as by [`load_config_from_dir`].
+///
+/// # Errors
+///
+/// This function returns an error if none of the directories exist or if there is an error
+/// loading the configuration.
+pub fn load_config_auto<C: TokenExpandingConfig>(app_name: &str) -> Result<C, Error> {
+    let candidates = config_dir_candidates(app_name, |name| env::var_os(name));
+    let Some(config_dir) = candidates.iter().find(|dir| dir.is_dir()) else {
+        let searched: Vec<String> = candidates
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        return Err(Error::ConfigError(format!(
+            "No configuration directory found for {app_name}, searched: {}",
+            searched.join(", ")
+        )));
+    };
+    load_config_from_dir(config_dir)
+}
+
+/// Load configuration from a TOML string, expanding tokens and deserializing it as
+/// [`load_config_from_dir`] does, without reading any files or environment variables.
+///
+/// # Errors
+///
+/// This function returns an error if there is an error parsing, expanding or deserializing the
+/// configuration.
+pub fn load_config_from_str<C: TokenExpandingConfig>(toml: &str) -> Result<C, Error> {
+    load_config_from_str_layers(&[("default.toml", toml)])
+}
+
+/// Load configuration from TOML read from `reader`, such as stdin, expanding tokens and
+/// deserializing it as [`load_config_from_str`] does.
+///
+/// # Errors
+///
+/// This function returns an error if reading fails or if there is an error parsing, expanding
+/// or deserializing the configuration.
+pub fn load_config_from_reader<C: TokenExpandingConfig>(mut reader: impl Read) -> Result<C, Error> {
+    let mut toml = String::new();
+    reader
+        .read_to_string(&mut toml)
+        .map_err(|source| Error::IoError {
+            path: "<reader>".to_string(),
+            source,
+        })?;
+    load_config_from_str(&toml)
+}
+
+/// Load configuration from in-memory layers, each a name and its contents, merged in order.
+///
+/// The format of each layer is detected from the extension of its name, e.g. `local.yaml`, and
+/// is TOML when it has none.
+///
+/// # Errors
+///
+/// This function returns an error if a layer's format is not supported or if there is an
+/// error parsing, expanding or deserializing the configuration.
+pub fn load_config_from_str_layers<C: TokenExpandingConfig>(
+    layers: &[(&str, &str)],
+) -> Result<C, Error> {
+    let mut figment = Figment::new();
+    for (name, contents) in layers {
+        figment = figment.merge(load_config_from_string(name, contents)?);
+    }
+    extract_config(
+        &figment,
+        &ExpansionOptions::default(),
+        &Value::Object(serde_json::Map::new()),
+    )
+}
+
+/// Maps an environment variable name to a dotted configuration path, see
+/// [`GraftonConfigBuilder::env_mapper`].
+type EnvMapper = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Upgrades a configuration from one version of its layout to the next, see
+/// [`GraftonConfigBuilder::migration`].
+type Migration = Arc<dyn Fn(Value) -> Value + Send + Sync>;
+/// Reads the defaults of the configuration struct for a merged configuration, see
+/// [`serialized_defaults`].
+type Defaults = fn(&Value) -> Option<Value>;
+
+/// Builds and loads a configuration, for when [`load_config_from_dir`] does not offer enough
+/// control.
+#[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct GraftonConfigBuilder {
+    config_dir: PathBuf,
+    upward_search: Option<UpwardSearch>,
+    layers: Vec<String>,
+    layer_policies: HashMap<String, LayerPolicy>,
+    renamed_keys: Vec<(String, String)>,
+    migrations: BTreeMap<u64, Migration>,
+    host_and_user_layers: bool,
+    run_mode: RunModeSource,
+    run_mode_env: String,
+    default_run_mode: Option<String>,
+    env_prefix: Option<String>,
+    env_separator: String,
+    env_mappings: Vec<(String, String)>,
+    env_mapper: Option<EnvMapper>,
+    args: Option<Vec<String>>,
+    args_prefix: Option<String>,
+    hermetic: bool,
+    deny_unknown_keys: bool,
+    accumulate_errors: bool,
+    docker_secrets: Option<PathBuf>,
+    options: ExpansionOptions,
+    formats: Vec<Arc<dyn ConfigFormat>>,
+    providers: Vec<Arc<dyn Provider + Send + Sync>>,
+    sources: Vec<Arc<dyn ConfigSource>>,
+    #[cfg(feature = "reload")]
+    refresh_interval: Option<Duration>,
+    #[cfg(feature = "json-schema")]
+    json_schema: Option<Value>,
+}
+
+impl GraftonConfigBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            config_dir: PathBuf::from("."),
+            upward_search: None,
+            layers: DEFAULT_LAYERS.iter().map(ToString::to_string).collect(),
+            layer_policies: HashMap::new(),
+            renamed_keys: Vec::new(),
+            migrations: BTreeMap::new(),
+            host_and_user_layers: false,
+            run_mode: RunModeSource::Environment,
+            run_mode_env: DEFAULT_RUN_MODE_ENV.to_string(),
+            default_run_mode: None,
+            env_prefix: None,
+            env_separator: DEFAULT_ENV_SEPARATOR.to_string(),
+            env_mappings: Vec::new(),
+            env_mapper: None,
+            args: None,
+            args_prefix: None,
+            hermetic: false,
+            deny_unknown_keys: false,
+            accumulate_errors: false,
+            docker_secrets: None,
+            options: ExpansionOptions::new(),
+            formats: Vec::new(),
+            providers: Vec::new(),
+            sources: Vec::new(),
+            #[cfg(feature = "reload")]
+            refresh_interval: None,
+            #[cfg(feature = "json-schema")]
+            json_schema: None,
+        }
+    }
+
+    /// Sets the directory that configuration files are loaded from. Defaults to the current
+    /// directory.
+    #[must_use]
+    pub fn dir(mut self, config_dir: impl AsRef<Path>) -> Self {
+        self.config_dir = config_dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Resolves a relative configuration directory against the nearest of the current directory
+    /// and its ancestors that contains it, as cargo finds `Cargo.toml`, so that binaries run
+    /// from nested paths still find e.g. `config/`.
+    #[must_use]
+    pub fn search_upward(mut self, enabled: bool) -> Self {
+        self.upward_search = enabled.then_some(UpwardSearch::ConfigDir);
+        self
+    }
+
+    /// Resolves a relative configuration directory against the nearest of the current directory
+    /// and its ancestors that contains `marker`, such as `Cargo.lock` or `.git`.
+    #[must_use]
+    pub fn search_upward_for(mut self, marker: impl AsRef<Path>) -> Self {
+        self.upward_search = Some(UpwardSearch::Marker(marker.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Replaces the configuration layers, which default to `default`, `local`, `{run_mode}` and
+    /// `local.{run_mode}`. Layers are named without an extension, as their format is detected from
+    /// the files present, and are merged in the order given.
+    ///
+    /// `{run_mode}` within a name is replaced by the active run mode, and the layer skipped when
+    /// there is none, e.g. `["base", "region", "{run_mode}", "secrets", "override"]`. Likewise
+    /// `{hostname}` and `{username}` are replaced by the host and user names. Names may include
+    /// a subdirectory, e.g. `hosts/{hostname}`.
+    #[must_use]
+    pub fn layers<I, S>(mut self, layers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.layers = layers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets what happens when no file is found for a layer, named as given to
+    /// [`layers`](Self::layers), e.g. `{run_mode}`. The first layer defaults to
+    /// [`LayerPolicy::WarnIfMissing`] and the others to [`LayerPolicy::Optional`].
+    ///
+    /// Layers skipped for want of a run mode, host or user name are never missing.
+    #[must_use]
+    pub fn layer_policy(mut self, layer: impl Into<String>, policy: LayerPolicy) -> Self {
+        self.layer_policies.insert(layer.into(), policy);
+        self
+    }
+
+    /// Merges `hosts/{hostname}.toml` and then `users/{username}.toml` after the other layers,
+    /// when present, so that individual machines and users can override the configuration.
+    #[must_use]
+    pub const fn host_and_user_layers(mut self, enabled: bool) -> Self {
+        self.host_and_user_layers = enabled;
+        self
+    }
+
+    /// Sets the run mode, selecting the `{run_mode}.toml` layer. Defaults to the value of the
+    /// `RUN_MODE` environment variable, see [`GraftonConfigBuilder::run_mode_env`].
+    #[must_use]
+    pub fn run_mode(mut self, run_mode: impl Into<String>) -> Self {
+        self.run_mode = RunModeSource::Explicit(Some(run_mode.into()));
+        self
+    }
+
+    /// Loads without a run mode, ignoring the `RUN_MODE` environment variable.
+    #[must_use]
+    pub fn without_run_mode(mut self) -> Self {
+        self.run_mode = RunModeSource::Explicit(None);
+        self
+    }
+
+    /// Sets the environment variable that selects the run mode when none is given explicitly,
+    /// e.g. `APP_ENV`. Defaults to `RUN_MODE`.
+    #[must_use]
+    pub fn run_mode_env(mut self, name: impl Into<String>) -> Self {
+        self.run_mode_env = name.into();
+        self
+    }
+
+    /// Sets the run mode used when the run mode environment variable is unset.
+    #[must_use]
+    pub fn default_run_mode(mut self, run_mode: impl Into<String>) -> Self {
+        self.default_run_mode = Some(run_mode.into());
+        self
+    }
+
+    /// Merges environment variables starting with `prefix` above all configuration files. The
+    /// prefix is removed, the rest of the name lowercased and split on the separator into a
+    /// path, so with a prefix of `APP__`, `APP__WEBSITE__BIND_ADDRESS` sets
+    /// `website.bind_address`. Environment variables are not merged without a prefix.
+    #[must_use]
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Sets the separator that splits environment variable names into a path, see
+    /// [`GraftonConfigBuilder::env_prefix`]. Defaults to `__`.
+    #[must_use]
+    pub fn env_separator(mut self, separator: impl Into<String>) -> Self {
+        self.env_separator = separator.into();
+        self
+    }
+
+    /// Maps environment variables starting with `prefix` onto the configuration at `path`,
+    /// merged above all configuration files. The rest of the name is lowercased and split on
+    /// the separator, so `env_mapping("WEBSITE_", "website")` has `WEBSITE_BIND_ADDRESS` set
+    /// `website.bind_address`. May be called more than once.
+    #[must_use]
+    pub fn env_mapping(mut self, prefix: impl Into<String>, path: impl Into<String>) -> Self {
+        self.env_mappings.push((prefix.into(), path.into()));
+        self
+    }
+
+    /// Maps environment variables onto the configuration with a closure returning the dotted
+    /// path a variable sets, or `None` to ignore it, merged above all configuration files.
+    #[must_use]
+    pub fn env_mapper<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.env_mapper = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Merges command-line arguments of the form `--website.port=9000` or
+    /// `--website.port 9000` above everything else, setting the dotted path to the value.
+    /// Everything after `--` is ignored, and any other argument fails the load. The program name
+    /// should not be included.
+    #[must_use]
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = Some(args.into_iter().map(Into::into).collect());
+        self.args_prefix = None;
+        self
+    }
+
+    /// Merges the process's command-line arguments that start with `--` followed by `prefix`,
+    /// so that with a prefix of `config.`, `--config.website.port=9000` or
+    /// `--config.website.port 9000` sets `website.port`. Other arguments are left to the
+    /// application and ignored, as is everything after `--`.
+    #[must_use]
+    pub fn cli_args(self, prefix: impl Into<String>) -> Self {
+        let mut builder = self.args(env::args().skip(1));
+        builder.args_prefix = Some(prefix.into());
+        builder
+    }
+
+    /// Isolates loading from the environment, so that the result depends only on the files and
+    /// arguments given. The run mode environment variable, environment variable layering, host
+    /// and user layers, Docker secrets, and `${env:...}` and `${cred:...}` tokens are all
+    /// disabled; a run mode may still be set explicitly or by
+    /// [`GraftonConfigBuilder::default_run_mode`].
+    #[must_use]
+    pub const fn hermetic(mut self, enabled: bool) -> Self {
+        self.hermetic = enabled;
+        self
+    }
+
+    /// Reads the secrets Docker mounts in `/run/secrets` into the `secrets` token namespace,
+    /// so that the secret `db_password` is available as `${secrets.db_password}` without
+    /// becoming part of the configuration itself. A missing directory provides no secrets, and
+    /// hermetic loading ignores them.
+    #[must_use]
+    pub fn docker_secrets(mut self, enabled: bool) -> Self {
+        self.docker_secrets = enabled.then(|| PathBuf::from(DOCKER_SECRETS_DIR));
+        self
+    }
+
+    /// Reads secrets into the `secrets` token namespace from `dir` rather than `/run/secrets`,
+    /// see [`GraftonConfigBuilder::docker_secrets`].
+    #[must_use]
+    pub fn docker_secrets_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.docker_secrets = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the options used to expand tokens within the loaded configuration.
+    #[must_use]
+    pub fn expansion_options(mut self, options: ExpansionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Resolves tokens in `namespace` with `resolver`, see [`ExpansionOptions::resolver`].
+    #[must_use]
+    pub fn resolver(
+        mut self,
+        namespace: impl Into<String>,
+        resolver: impl TokenResolver + 'static,
+    ) -> Self {
+        self.options = self.options.resolver(namespace, resolver);
+        self
+    }
+
+    /// Makes a token that cannot be resolved an error, see
+    /// [`ExpansionOptions::strict_tokens`].
+    #[must_use]
+    pub fn strict_tokens(mut self, strict: bool) -> Self {
+        self.options = self.options.strict_tokens(strict);
+        self
+    }
+
+    /// Moves a key that has been renamed, e.g. `server.addr`, to its new path, e.g.
+    /// `website.bind_address`, before tokens are expanded, so configuration files written for
+    /// earlier versions keep working. May be called more than once.
+    ///
+    /// Each old key found is reported as a [`LoadWarning::DeprecatedKey`], naming where it was
+    /// set. A value set at the new path takes precedence over one at the old path.
+    #[must_use]
+    pub fn renamed_key(mut self, old: impl Into<String>, new: impl Into<String>) -> Self {
+        self.renamed_keys.push((old.into(), new.into()));
+        self
+    }
+
+    /// Registers a migration upgrading configurations from version `from` of their layout to
+    /// the next, so files written for earlier releases keep working after breaking changes.
+    ///
+    /// The version is read from the `config_version` key of the merged configuration, and
+    /// taken to be 1 when absent. Migrations from that version onwards are applied in order,
+    /// before tokens are expanded, and `config_version` is removed before deserialization, as
+    /// `vars` is. A version newer than the last migration supports is an error.
+    #[must_use]
+    pub fn migration<F>(mut self, from: u64, migration: F) -> Self
+    where
+        F: Fn(Value) -> Value + Send + Sync + 'static,
+    {
+        self.migrations.insert(from, Arc::new(migration));
+        self
+    }
+
+    /// Makes keys that no field of the configuration struct consumes, such as misspelled
+    /// ones, an error (`Error::UnknownKeys`) instead of silently ignoring them.
+    ///
+    /// Unlike `#[serde(deny_unknown_fields)]`, this works with `#[serde(flatten)]`, although
+    /// keys left over by flattened structs are not reported.
+    #[must_use]
+    pub const fn deny_unknown_keys(mut self, deny: bool) -> Self {
+        self.deny_unknown_keys = deny;
+        self
+    }
+
+    /// Makes a load carry on past problems, so that every one of them is reported at once in
+    /// `Error::Multiple`, and a configuration can be fixed in one pass.
+    ///
+    /// Every layer is parsed before failing on a syntax error, every token that cannot be
+    /// resolved with [`strict_tokens`](Self::strict_tokens) is reported, and every value that
+    /// does not fit its field, as well as unknown keys with
+    /// [`deny_unknown_keys`](Self::deny_unknown_keys). A single problem is returned as it is.
+    #[must_use]
+    pub const fn accumulate_errors(mut self, accumulate: bool) -> Self {
+        self.accumulate_errors = accumulate;
+        self
+    }
+
+    /// Registers an additional file format. Its files are merged after those of the built-in
+    /// formats within each layer, and it takes over any extension it shares with them.
+    #[must_use]
+    pub fn format(mut self, format: impl ConfigFormat + 'static) -> Self {
+        self.formats.push(Arc::new(format));
+        self
+    }
+
+    /// Merges an additional figment provider, such as
+    /// [`HttpSource`](crate::HttpSource), above the configuration files and below
+    /// environment variables. Providers are merged in the order added.
+    #[must_use]
+    pub fn provider(mut self, provider: impl Provider + Send + Sync + 'static) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Merges an asynchronous configuration source above the configuration files and
+    /// providers, and below environment variables. Sources are merged in order of priority.
+    #[must_use]
+    pub fn source(mut self, source: impl ConfigSource + 'static) -> Self {
+        self.sources.push(Arc::new(source));
+        self
+    }
+
+    /// Merges a boxed asynchronous configuration source, as [`source`](Self::source) does.
+    #[must_use]
+    pub fn boxed_source(mut self, source: Box<dyn ConfigSource>) -> Self {
+        self.sources.push(Arc::from(source));
+        self
+    }
+
+    /// Checks the merged configuration against a JSON Schema before it is deserialized, so
+    /// that mistakes in keys the configuration struct would silently ignore are caught.
+    ///
+    /// The schema sees the configuration as it is after token expansion, without the `vars`
+    /// section. Violations fail the load with [`Error::ValidationFailed`].
+    #[cfg(feature = "json-schema")]
+    #[must_use]
+    pub fn json_schema(mut self, schema: Value) -> Self {
+        self.json_schema = Some(schema);
+        self
+    }
+
+    /// Sets how often [`SharedConfig::refresh_periodically`] reloads the configuration, to pick
+    /// up changes to remote providers such as `HttpSource`. Sources that declare a shorter
+    /// [`refresh_interval`](ConfigSource::refresh_interval) are refreshed that often instead.
+    #[cfg(feature = "reload")]
+    #[must_use]
+    pub const fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = Some(interval);
+        self
+    }
+
+    /// Loads the configuration. See [`load_config_from_dir`] for the files that are loaded
+    /// and how they are processed.
+    ///
+    /// Configuration sources are loaded on the current thread, blocking it until they are
+    /// done. Use [`load_async`](Self::load_async) to load them from asynchronous code.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error parsing, expanding or deserializing the
+    /// configuration.
+    pub fn load<C: TokenExpandingConfig>(&self) -> Result<C, Error> {
+        self.load_with_report().map(|(config, _)| config)
+    }
+
+    /// Loads the configuration as [`load`](Self::load) does, awaiting the configuration
+    /// sources.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a source fails to load, or if there is an error parsing, expanding
+    /// or deserializing the configuration.
+    pub async fn load_async<C: TokenExpandingConfig>(&self) -> Result<C, Error> {
+        self.load_with_report_async()
+            .await
+            .map(|(config, _)| config)
+    }
+
+    /// Loads the configuration as [`load`](Self::load) does, along with a [`LoadReport`] of
+    /// the problems that did not stop it from loading, such as keys that no field of the
+    /// configuration struct consumed.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`load`](Self::load).
+    pub fn load_with_report<C: TokenExpandingConfig>(&self) -> Result<(C, LoadReport), Error> {
+        let started = Instant::now();
+        let sources = block_on(load_sources(&self.sources))?;
+        let (config, mut report) = self.load_with_sources(sources)?;
+        report.duration = started.elapsed();
+        Ok((config, report))
+    }
+
+    /// Loads the configuration as [`load_async`](Self::load_async) does, along with a
+    /// [`LoadReport`], as [`load_with_report`](Self::load_with_report) does.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`load_async`](Self::load_async).
+    pub async fn load_with_report_async<C: TokenExpandingConfig>(
+        &self,
+    ) -> Result<(C, LoadReport), Error> {
+        let started = Instant::now();
+        let sources = load_sources(&self.sources).await?;
+        let (config, mut report) = self.load_with_sources(sources)?;
+        report.duration = started.elapsed();
+        Ok((config, report))
+    }
+
+    /// Loads the configuration as [`load`](Self::load) does, then checks it with
+    /// [`ValidatableConfig::validate`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ValidationFailed`], listing every problem found, if the configuration
+    /// is invalid, and otherwise the same errors as [`load`](Self::load).
+    pub fn load_validated<C: ValidatableConfig>(&self) -> Result<C, Error> {
+        let config: C = self.load()?;
+        config.validate().map_err(Error::ValidationFailed)?;
+        Ok(config)
+    }
+
+    /// Loads the configuration as [`load_async`](Self::load_async) does, then checks it with
+    /// [`ValidatableConfig::validate`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ValidationFailed`], listing every problem found, if the configuration
+    /// is invalid, and otherwise the same errors as [`load_async`](Self::load_async).
+    pub async fn load_validated_async<C: ValidatableConfig>(&self) -> Result<C, Error> {
+        let config: C = self.load_async().await?;
+        config.validate().map_err(Error::ValidationFailed)?;
+        Ok(config)
+    }
+
+    /// Loads the configuration as [`load`](Self::load) does, then enforces the constraints
+    /// declared on its fields with `#[derive(validator::Validate)]`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ValidationFailed`], listing every constraint violated, if the
+    /// configuration is invalid, and otherwise the same errors as [`load`](Self::load).
+    #[cfg(feature = "validator")]
+    pub fn load_and_validate<C>(&self) -> Result<C, Error>
+    where
+        C: TokenExpandingConfig + validator::Validate,
+    {
+        let config: C = self.load()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads the configuration as [`load_async`](Self::load_async) does, then enforces the
+    /// constraints declared on its fields with `#[derive(validator::Validate)]`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ValidationFailed`], listing every constraint violated, if the
+    /// configuration is invalid, and otherwise the same errors as
+    /// [`load_async`](Self::load_async).
+    #[cfg(feature = "validator")]
+    pub async fn load_and_validate_async<C>(&self) -> Result<C, Error>
+    where
+        C: TokenExpandingConfig + validator::Validate,
+    {
+        let config: C = self.load_async().await?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads the configuration as [`load`](Self::load) does, into a [`SharedConfig`] handle
+    /// that keeps the builder to reload it with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error parsing, expanding or deserializing the
+    /// configuration.
+    #[cfg(feature = "reload")]
+    pub fn load_shared<C: TokenExpandingConfig>(self) -> Result<SharedConfig<C>, Error> {
+        let (config, value) = self.load_expanded()?;
+        Ok(SharedConfig::new(self, config, value))
+    }
+
+    /// Loads the configuration as [`load`](Self::load) does, along with the expanded value it
+    /// was deserialized from, which holds fields that serializing the configuration may skip.
+    #[cfg(feature = "reload")]
+    pub(crate) fn load_expanded<C: TokenExpandingConfig>(&self) -> Result<(C, Value), Error> {
+        let sources = block_on(load_sources(&self.sources))?;
+        self.load_expanded_with_sources(sources)
+    }
+
+    /// Loads the configuration as [`load_async`](Self::load_async) does, along with the
+    /// expanded value, as [`load_expanded`](Self::load_expanded) does.
+    #[cfg(feature = "reload")]
+    pub(crate) async fn load_expanded_async<C: TokenExpandingConfig>(
+        &self,
+    ) -> Result<(C, Value), Error> {
+        let sources = load_sources(&self.sources).await?;
+        self.load_expanded_with_sources(sources)
+    }
+
+    #[cfg(feature = "reload")]
+    fn load_expanded_with_sources<C: TokenExpandingConfig>(
+        &self,
+        sources: Vec<SourceLayer>,
+    ) -> Result<(C, Value), Error> {
+        let (merged, value) = self.merge(sources, &self.options, Some(serialized_defaults::<C>))?;
+        let (config, _) = self.deserialize(merged, value.clone())?;
+        Ok((config, value))
+    }
+
+    /// The shortest of the refresh intervals given to the builder and declared by its
+    /// sources.
+    #[cfg(feature = "reload")]
+    pub(crate) fn shortest_refresh_interval(&self) -> Option<Duration> {
+        self.sources
+            .iter()
+            .filter_map(|source| source.refresh_interval())
+            .chain(self.refresh_interval)
+            .min()
+    }
+
+    /// Checks the configuration as [`load`](Self::load) would load it, reporting every problem
+    /// found rather than failing at the first.
+    ///
+    /// Every file of every layer is parsed on its own, so that a syntax error in one does not
+    /// hide those in others. If they all parse, the configuration is loaded with
+    /// [`strict_tokens`](Self::strict_tokens) and
+    /// [`accumulate_errors`](Self::accumulate_errors), so that every token that cannot be
+    /// resolved and every value that does not fit its field is reported. The warnings of the
+    /// [`LoadReport`] are included.
+    #[must_use]
+    pub fn check<C: TokenExpandingConfig>(&self) -> ConfigReport {
+        let mut report = ConfigReport::default();
+        match self.parse_config_files() {
+            Ok(errors) if errors.is_empty() => {}
+            Ok(errors) => {
+                report.errors = errors;
+                return report;
+            }
+            Err(e) => {
+                report.errors.push(e);
+                return report;
+            }
+        }
+
+        let sources = match block_on(load_sources(&self.sources)) {
+            Ok(sources) => sources,
+            Err(e) => {
+                report.errors.push(e);
+                return report;
+            }
+        };
+        let strict = self.options.clone().strict_tokens(true);
+        match self
+            .clone()
+            .accumulate_errors(true)
+            .load_with_options::<C>(sources, &strict)
+        {
+            Ok((_, merged)) => report.warnings = merged.report.warnings,
+            Err(Error::Multiple(errors)) => report.errors.extend(errors),
+            Err(e) => report.errors.push(e),
+        }
+        report
+    }
+
+    /// Parses each file of each layer on its own, returning the errors found.
+    fn parse_config_files(&self) -> Result<Vec<Error>, Error> {
+        let config_dir = &self.resolve_config_dir()?;
+        let run_mode = self.active_run_mode();
+        let layers = resolve_layers(&self.all_layers(), run_mode.as_deref());
+        let extensions = self.extensions();
+        let mut config_paths = setup_config_paths(config_dir, &layers, &extensions);
+        config_paths.extend(drop_in_paths(config_dir, &extensions));
+
+        Ok(config_paths
+            .iter()
+            .filter_map(|path| {
+                self.load_config_with_includes(path, &mut Vec::new())
+                    .and_then(|figment| extract_value(&figment))
+                    .err()
+            })
+            .collect())
+    }
+
+    /// The run mode selecting the `{run_mode}` layers, if any.
+    fn active_run_mode(&self) -> Option<String> {
+        match &self.run_mode {
+            RunModeSource::Environment if self.hermetic => self.default_run_mode.clone(),
+            RunModeSource::Environment => {
+                determine_run_mode(&self.run_mode_env).or_else(|| self.default_run_mode.clone())
+            }
+            RunModeSource::Explicit(run_mode) => run_mode.clone(),
+        }
+    }
+
+    /// The layers to merge, including the host and user layers when they are enabled.
+    fn all_layers(&self) -> Vec<String> {
+        let mut layers = self.layers.clone();
+        if self.host_and_user_layers && !self.hermetic {
+            layers.extend(HOST_AND_USER_LAYERS.iter().map(ToString::to_string));
+        }
+        layers
+    }
+
+    /// Loads the configuration as [`load`](Self::load) does, along with the [`Provenance`] of
+    /// each value: the file and layer, environment variables or provider that set it.
+    ///
+    /// Paths are those of the merged files, before [migrations](Self::migration) and
+    /// [renames](Self::renamed_key), and values set by tokens are attributed to where the
+    /// token was written.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`load`](Self::load).
+    pub fn config_with_provenance<C: TokenExpandingConfig>(
+        &self,
+    ) -> Result<(C, Provenance), Error> {
+        let sources = block_on(load_sources(&self.sources))?;
+        let (config, merged) = self.load_with_options::<C>(sources, &self.options)?;
+        Ok((config, provenance(&merged.figment, &merged.config_dir)))
+    }
+
+    /// Explains how the value at `path`, e.g. `website.port` or `servers[0].port`, came to be:
+    /// its final value, the layer that set it, the layers it overrode and the value it was
+    /// expanded from, if tokens changed it.
+    ///
+    /// Paths are those of the merged files, before [migrations](Self::migration) and
+    /// [renames](Self::renamed_key). The configuration is not deserialized.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a source fails to load, or if there is an error parsing or
+    /// expanding the configuration.
+    pub fn explain(&self, path: &str) -> Result<Explanation, Error> {
+        let sources = block_on(load_sources(&self.sources))?;
+        let (merged, value) = self.merge(sources, &self.options, None)?;
+        let segments = split_path(path);
+
+        let mut set_by = Vec::new();
+        for layer in &merged.layers {
+            let layer_value = extract_value(layer)?;
+            if let Some(found) = get_value_from_path(&segments, &layer_value) {
+                let source = layer
+                    .metadata()
+                    .last()
+                    .map(|metadata| value_source(metadata, &merged.config_dir));
+                if let Some(source) = source {
+                    set_by.push((source, found.clone()));
+                }
+            }
+        }
+
+        let final_value = get_value_from_path(&segments, &value).cloned();
+        let unexpanded = get_value_from_path(&segments, &merged.raw)
+            .filter(|raw| Some(*raw) != final_value.as_ref())
+            .cloned();
+        let source = set_by.pop().map(|(source, _)| source);
+        Ok(Explanation {
+            path: path.to_string(),
+            value: final_value,
+            source,
+            overridden: set_by,
+            unexpanded,
+        })
+    }
+
+    fn load_with_sources<C: TokenExpandingConfig>(
+        &self,
+        sources: Vec<SourceLayer>,
+    ) -> Result<(C, LoadReport), Error> {
+        self.load_with_options(sources, &self.options)
+            .map(|(config, merged)| (config, merged.report))
+    }
+
+    fn load_with_options<C: TokenExpandingConfig>(
+        &self,
+        sources: Vec<SourceLayer>,
+        options: &ExpansionOptions,
+    ) -> Result<(C, Merged), Error> {
+        let (merged, value) = self.merge(sources, options, Some(serialized_defaults::<C>))?;
+        self.deserialize(merged, value)
+    }
+
+    /// Deserializes the expanded configuration `value`, reporting the keys no field consumed
+    /// along with the problems `merged` found.
+    fn deserialize<C: TokenExpandingConfig>(
+        &self,
+        mut merged: Merged,
+        value: Value,
+    ) -> Result<(C, Merged), Error> {
+        let mut errors = std::mem::take(&mut merged.errors);
+        let deserialized = if self.accumulate_errors {
+            deserialize_coerced_collecting(value)
+        } else {
+            deserialize_coerced_unused(value).map_err(|e| vec![e])
+        };
+        let (config, unused) = match deserialized {
+            Ok(deserialized) => deserialized,
+            Err(mismatches) => {
+                errors.extend(mismatches.into_iter().map(|e| locate(e, &merged.figment)));
+                return Err(Error::from_many(errors));
+            }
+        };
+        if self.deny_unknown_keys && !unused.is_empty() {
+            errors.push(Error::UnknownKeys(unused));
+            return Err(Error::from_many(errors));
+        }
+        if !errors.is_empty() {
+            return Err(Error::from_many(errors));
+        }
+
+        for UnknownKey { path, suggestion } in unused {
+            raise(
+                &mut merged.report,
+                LoadWarning::UnusedKey { path, suggestion },
+            );
+        }
+        Ok((config, merged))
+    }
+
+    /// Merges the layers of the configuration and expands its tokens, returning the expanded
+    /// configuration with what went into it. Tokens can also reference the values `defaults`
+    /// gives the fields of the configuration struct that are not set.
+    fn merge(
+        &self,
+        sources: Vec<SourceLayer>,
+        options: &ExpansionOptions,
+        defaults: Option<Defaults>,
+    ) -> Result<(Merged, Value), Error> {
+        let config_dir = &self.resolve_config_dir()?;
+        let run_mode = self.active_run_mode();
+        let mut meta = meta_tokens(config_dir, run_mode.as_deref());
+        if let (Some(dir), false, Value::Object(meta)) =
+            (&self.docker_secrets, self.hermetic, &mut meta)
+        {
+            let secrets = MountedDirSource::new(dir).optional(true).read()?;
+            meta.insert(SECRETS_NAMESPACE.to_string(), secrets);
+        }
+        let layers = self.all_layers();
+        let mut report = LoadReport {
+            run_mode: run_mode.clone(),
+            ..LoadReport::default()
+        };
+        let resolved = resolve_layers(&layers, run_mode.as_deref());
+        let extensions = self.extensions();
+        let mut config_paths = setup_config_paths(config_dir, &resolved, &extensions);
+        self.check_missing_layers(
+            config_dir,
+            &layers,
+            run_mode.as_deref(),
+            &config_paths,
+            &mut report,
+        )?;
+        report.files_missing = resolved
+            .iter()
+            .filter(|layer| !layer_found(config_dir, layer, &config_paths))
+            .map(|layer| expected_layer_path(config_dir, layer))
+            .inspect(|path| tracing::debug!(path = %path.display(), "config layer missing"))
+            .collect();
+        config_paths.extend(drop_in_paths(config_dir, &extensions));
+
+        let mut merged_layers = Vec::new();
+        for path in &config_paths {
+            if path.exists() {
+                merged_layers.push(self.load_config_with_includes(path, &mut Vec::new())?);
+                report.files_found.push(path.clone());
+            }
+        }
+
+        #[cfg(feature = "dotenv")]
+        for path in dotenv_paths(config_dir, run_mode.as_deref()) {
+            if path.exists() {
+                merged_layers.push(load_dotenv_file(&path)?);
+                report.files_found.push(path);
+            }
+        }
+
+        for provider in &self.providers {
+            merged_layers.push(Figment::from(SharedProvider(Arc::clone(provider))));
+        }
+
+        merged_layers.extend(sources.into_iter().map(Figment::from));
+
+        if !self.hermetic {
+            let env = self.merge_env_vars(Figment::new());
+            if let Ok(value) = extract_value(&env) {
+                for key in leaf_paths(&value, "") {
+                    tracing::debug!(key, "environment override applied");
+                }
+            }
+            merged_layers.push(env);
+        }
+
+        if let Some(args) = &self.args {
+            merged_layers.push(Figment::from(SourceLayer {
+                name: ARGS_PROVIDER.to_string(),
+                value: parse_args(args, self.args_prefix.as_deref())?,
+            }));
+        }
+
+        report.layers = self.summarize_layers(&merged_layers, config_dir)?;
+
+        let figment = merged_layers
+            .iter()
+            .fold(Figment::new(), |figment, layer| figment.merge(layer));
+        let mut raw = self.migrate(extract_value(&figment)?)?;
+        self.rename_deprecated_keys(&figment, &mut raw, &mut report);
+
+        let root = defaults
+            .and_then(|defaults| defaults(&raw))
+            .map(|defaults| fill_defaults(&raw, defaults));
+        let collect = self.accumulate_errors;
+        let (value, substitutions, errors) = if self.hermetic {
+            let options = options.clone().allow_env_tokens(false);
+            expand_value(&raw, root.as_ref(), &options, &meta, collect)?
+        } else {
+            expand_value(&raw, root.as_ref(), options, &meta, collect)?
+        };
+        report.tokens_expanded = substitutions;
+
+        #[cfg(feature = "json-schema")]
+        if let Some(schema) = &self.json_schema {
+            validate_against_schema(schema, &value)?;
+        }
+
+        let merged = Merged {
+            layers: merged_layers,
+            figment,
+            raw,
+            report,
+            errors,
+            config_dir: config_dir.clone(),
+        };
+        Ok((merged, value))
+    }
+
+    /// Parses each layer, counting the values it sets. With
+    /// [`accumulate_errors`](Self::accumulate_errors), every layer is parsed before failing.
+    fn summarize_layers(
+        &self,
+        layers: &[Figment],
+        config_dir: &Path,
+    ) -> Result<Vec<LayerSummary>, Error> {
+        let mut summaries = Vec::new();
+        let mut errors = Vec::new();
+        for layer in layers {
+            let keys = match extract_value(layer) {
+                Ok(value) => count_leaves(&value),
+                Err(e) if self.accumulate_errors => {
+                    errors.push(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some(metadata) = layer.metadata().last() {
+                let source = value_source(metadata, config_dir);
+                tracing::debug!(source = %source, keys, "config layer loaded");
+                summaries.push(LayerSummary { source, keys });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(summaries)
+        } else {
+            Err(Error::from_many(errors))
+        }
+    }
+
+    /// Applies the [migrations](Self::migration) from the configuration's version onwards.
+    fn migrate(&self, mut value: Value) -> Result<Value, Error> {
+        let Some(last) = self.migrations.keys().next_back() else {
+            return Ok(value);
+        };
+        let latest = last + 1;
+
+        let version = match value.get(CONFIG_VERSION_KEY) {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .or_else(|| version.as_str().and_then(|v| v.trim().parse().ok()))
+                .ok_or_else(|| Error::InvalidValue {
+                    path: CONFIG_VERSION_KEY.to_string(),
+                    message: format!("expected a version number, found {version}"),
+                    location: None,
+                })?,
+        };
+        if version > latest {
+            return Err(Error::UnsupportedConfigVersion { version, latest });
+        }
+
+        for migration in self
+            .migrations
+            .range(version..)
+            .map(|(_, migration)| migration)
+        {
+            value = migration(value);
+        }
+        if let Value::Object(map) = &mut value {
+            map.remove(CONFIG_VERSION_KEY);
+        }
+        Ok(value)
+    }
+
+    /// Moves the values at the [renamed keys](Self::renamed_key) to their new paths.
+    fn rename_deprecated_keys(
+        &self,
+        figment: &Figment,
+        value: &mut Value,
+        report: &mut LoadReport,
+    ) {
+        let Value::Object(root) = value else {
+            return;
+        };
+        for (old, new) in &self.renamed_keys {
+            let old_segments: Vec<String> = old.split('.').map(ToString::to_string).collect();
+            let Some(moved) = remove_nested(root, &old_segments) else {
+                continue;
+            };
+            let new_segments: Vec<String> = new.split('.').map(ToString::to_string).collect();
+            if find_nested(root, &new_segments).is_none() {
+                insert_nested(root, &new_segments, moved);
+            }
+
+            let source = figment.find_metadata(old).map(|metadata| {
+                metadata
+                    .source
+                    .as_ref()
+                    .map_or_else(|| metadata.name.to_string(), ToString::to_string)
+            });
+            let warning = LoadWarning::DeprecatedKey {
+                old: old.clone(),
+                new: new.clone(),
+                source,
+            };
+            raise(report, warning);
+        }
+    }
+
+    /// Applies the [`LayerPolicy`] of each layer for which no file was found.
+    fn check_missing_layers(
+        &self,
+        config_dir: &Path,
+        layers: &[String],
+        run_mode: Option<&str>,
+        config_paths: &[PathBuf],
+        report: &mut LoadReport,
+    ) -> Result<(), Error> {
+        for (index, layer) in layers.iter().enumerate() {
+            let policy = self
+                .layer_policies
+                .get(layer)
+                .copied()
+                .unwrap_or(if index == 0 {
+                    LayerPolicy::WarnIfMissing
+                } else {
+                    LayerPolicy::Optional
+                });
+            if policy == LayerPolicy::Optional {
+                continue;
+            }
+
+            for resolved in resolve_layers(std::slice::from_ref(layer), run_mode) {
+                if layer_found(config_dir, &resolved, config_paths) {
+                    continue;
+                }
+
+                let path = expected_layer_path(config_dir, &resolved)
+                    .display()
+                    .to_string();
+                if policy == LayerPolicy::Required {
+                    return Err(Error::MissingLayer {
+                        layer: resolved,
+                        path,
+                    });
+                }
+                let warning = LoadWarning::MissingLayer {
+                    layer: resolved,
+                    path,
+                };
+                raise(report, warning);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the configuration directory to an absolute path, searching upwards for it when
+    /// asked to.
+    pub(crate) fn resolve_config_dir(&self) -> Result<PathBuf, Error> {
+        let Some(search) = self
+            .upward_search
+            .as_ref()
+            .filter(|_| self.config_dir.is_relative())
+        else {
+            return absolute_config_dir(&self.config_dir);
+        };
+
+        let current_dir = env::current_dir().map_err(|source| Error::IoError {
+            path: ".".to_string(),
+            source,
+        })?;
+        let marker = match search {
+            UpwardSearch::ConfigDir => &self.config_dir,
+            UpwardSearch::Marker(marker) => marker,
+        };
+        let base = find_upward(&current_dir, marker).unwrap_or(&current_dir);
+        Ok(base.join(&self.config_dir))
+    }
+
+    /// Merges the environment variables selected by the prefix and mappings.
+    fn merge_env_vars(&self, mut figment: Figment) -> Figment {
+        if let Some(prefix) = &self.env_prefix {
+            figment = figment.merge(Env::prefixed(prefix).split(&self.env_separator));
+        }
+
+        if !self.env_mappings.is_empty() {
+            let mappings = self.env_mappings.clone();
+            let separator = self.env_separator.clone();
+            figment = figment.merge(Env::raw().filter_map(move |key| {
+                map_env_var(&mappings, &separator, key.as_str()).map(Uncased::from)
+            }));
+        }
+
+        if let Some(mapper) = &self.env_mapper {
+            let mapper = Arc::clone(mapper);
+            figment = figment
+                .merge(Env::raw().filter_map(move |key| mapper(key.as_str()).map(Uncased::from)));
+        }
+
+        figment
+    }
+
+    /// The extensions looked for within each layer, in the order their files are merged.
+    fn extensions(&self) -> Vec<&str> {
+        let mut extensions = CONFIG_EXTENSIONS.to_vec();
+        for extension in self.formats.iter().flat_map(|format| format.extensions()) {
+            if !extensions.contains(extension) {
+                extensions.push(extension);
+            }
+        }
+        extensions
+    }
+
+    /// Loads a file along with the files named by its `include` key, which are resolved
+    /// relative to it and merged beneath it in the order given. `stack` holds the files
+    /// currently being included, to detect cycles.
+    fn load_config_with_includes(
+        &self,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<Figment, Error> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if stack.contains(&canonical) {
+            let cycle: Vec<String> = stack
+                .iter()
+                .chain([&canonical])
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(Error::ConfigError(format!(
+                "Include cycle detected: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        if stack.len() > INCLUDE_DEPTH_LIMIT {
+            return Err(Error::ConfigError(format!(
+                "Include depth limit of {INCLUDE_DEPTH_LIMIT} exceeded at {}",
+                path.display()
+            )));
+        }
+
+        let config = self.load_config_from_file(path)?;
+        if !config.contains(INCLUDE_KEY) {
+            return Ok(config);
+        }
+
+        let includes: Vec<String> =
+            config
+                .extract_inner(INCLUDE_KEY)
+                .map_err(|e| Error::FigmentError {
+                    context: format!("Invalid include in {}", path.display()),
+                    source: Box::new(e),
+                })?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        stack.push(canonical);
+        let mut figment = Figment::new();
+        for include in includes {
+            let include_path = base_dir.join(include);
+            figment = figment.merge(self.load_config_with_includes(&include_path, stack)?);
+        }
+        stack.pop();
+
+        Ok(figment.merge(config))
+    }
+
+    fn load_config_from_file(&self, path: &Path) -> Result<Figment, Error> {
+        let extension = file_extension(path);
+        let extension = extension.as_deref();
+        let format = self.formats.iter().rev().find(|format| {
+            format
+                .extensions()
+                .iter()
+                .any(|candidate| extension.is_some_and(|e| e.eq_ignore_ascii_case(candidate)))
+        });
+
+        format.map_or_else(
+            || load_config_from_file(path),
+            |format| Ok(Figment::from(FormatFile::new(Arc::clone(format), path))),
+        )
+    }
+}
+
+/// Merges a provider shared with [`GraftonConfigBuilder`], so that it can be loaded again.
+struct SharedProvider(Arc<dyn Provider + Send + Sync>);
+
+impl Provider for SharedProvider {
+    fn metadata(&self) -> Metadata {
+        self.0.metadata()
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        self.0.data()
+    }
+
+    fn profile(&self) -> Option<Profile> {
+        self.0.profile()
+    }
+}
+
+/// What [`GraftonConfigBuilder`] searches upwards from the current directory for.
+#[derive(Clone)]
+enum UpwardSearch {
+    ConfigDir,
+    Marker(PathBuf),
+}
+
+/// What [`GraftonConfigBuilder`] does when no file is found for a layer, see
+/// [`GraftonConfigBuilder::layer_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerPolicy {
+    /// Fails the load with [`Error::MissingLayer`].
+    Required,
+    /// Loads without the layer.
+    Optional,
+    /// Loads without the layer, reporting a [`LoadWarning::MissingLayer`] and emitting it as a
+    /// `tracing` event. The default for the first layer.
+    WarnIfMissing,
+}
+
+/// What went into a configuration merged by [`GraftonConfigBuilder`].
+struct Merged {
+    /// Each layer, in the order they were merged.
+    layers: Vec<Figment>,
+    figment: Figment,
+    /// The merged configuration before token expansion.
+    raw: Value,
+    report: LoadReport,
+    /// Problems found while merging, with [`GraftonConfigBuilder::accumulate_errors`], that
+    /// did not stop it.
+    errors: Vec<Error>,
+    config_dir: PathBuf,
+}
+
+/// Where [`GraftonConfigBuilder`] takes the run mode from.
+#[derive(Clone)]
+enum RunModeSource {
+    Environment,
+    Explicit(Option<String>),
+}
+
+impl Default for GraftonConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn determine_run_mode(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+/// The directories searched by [`load_config_auto`], in order, reading environment variables
+/// through `var`.
+fn config_dir_candidates(
+    app_name: &str,
+    var: impl Fn(&str) -> Option<std::ffi::OsString>,
+) -> Vec<PathBuf> {
+    let dir_var = format!("{}_CONFIG_DIR", app_name.to_uppercase().replace('-', "_"));
+
+    let mut candidates: Vec<PathBuf> = var(&dir_var).map(PathBuf::from).into_iter().collect();
+    candidates.extend(var("XDG_CONFIG_HOME").map(|dir| PathBuf::from(dir).join(app_name)));
+    candidates.extend(var("HOME").map(|dir| PathBuf::from(dir).join(".config").join(app_name)));
+    if cfg!(windows) {
+        candidates.extend(var("APPDATA").map(|dir| PathBuf::from(dir).join(app_name)));
+    } else {
+        candidates.push(Path::new("/etc").join(app_name));
+    }
+    candidates
+}
+
+/// Maps an environment variable to a configuration path using the first mapping whose prefix
+/// it starts with, ignoring case.
+fn map_env_var(mappings: &[(String, String)], separator: &str, key: &str) -> Option<String> {
+    mappings.iter().find_map(|(prefix, path)| {
+        let rest = key
+            .get(..prefix.len())
+            .filter(|start| start.eq_ignore_ascii_case(prefix))
+            .map(|_| &key[prefix.len()..])
+            .filter(|rest| !rest.is_empty())?;
+        let rest = rest.to_lowercase().replace(separator, ".");
+        Some(if path.is_empty() {
+            rest
+        } else {
+            format!("{path}.{rest}")
+        })
+    })
+}
+
+/// Finds the nearest of `start` and its ancestors that contains `marker`.
+fn find_upward<'a>(start: &'a Path, marker: &Path) -> Option<&'a Path> {
+    start
+        .ancestors()
+        .find(|ancestor| ancestor.join(marker).exists())
+}
+
+/// Resolves a relative configuration directory against the current directory.
+fn absolute_config_dir(config_dir: &Path) -> Result<PathBuf, Error> {
+    std::path::absolute(config_dir).map_err(|source| Error::IoError {
+        path: config_dir.display().to_string(),
+        source,
+    })
+}
+
+/// Builds the reserved meta values that tokens may reference alongside the configuration.
+fn meta_tokens(config_dir: &Path, run_mode: Option<&str>) -> Value {
+    let config_dir = config_dir
+        .canonicalize()
+        .unwrap_or_else(|_| config_dir.to_path_buf());
+
+    let mut meta = serde_json::Map::new();
+    meta.insert(
+        META_CONFIG_DIR.to_string(),
+        Value::String(config_dir.to_string_lossy().into_owned()),
+    );
+    if let Some(run_mode) = run_mode {
+        meta.insert(
+            META_RUN_MODE.to_string(),
+            Value::String(run_mode.to_string()),
+        );
+    }
+    Value::Object(meta)
+}
+
+/// Splits a comma separated run mode such as `prod,eu,canary` into its individual modes.
+fn run_modes(run_mode: Option<&str>) -> Vec<&str> {
+    run_mode
+        .map(|run_mode| {
+            run_mode
+                .split(',')
+                .map(str::trim)
+                .filter(|mode| !mode.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Substitutes each run mode, in order, and the host and user names into the layer names,
+/// dropping layers that need a run mode when there is none.
+fn resolve_layers(layers: &[String], run_mode: Option<&str>) -> Vec<String> {
+    let modes = run_modes(run_mode);
+    layers
+        .iter()
+        .flat_map(|layer| {
+            if layer.contains(RUN_MODE_PLACEHOLDER) {
+                modes
+                    .iter()
+                    .map(|mode| layer.replace(RUN_MODE_PLACEHOLDER, mode))
+                    .collect()
+            } else {
+                vec![layer.clone()]
+            }
+        })
+        .filter_map(substitute_machine_names)
+        .collect()
+}
+
+/// Substitutes the host and user names into a layer name, or returns `None` if one is needed
+/// but cannot be determined.
+fn substitute_machine_names(layer: String) -> Option<String> {
+    let mut layer = layer;
+    if layer.contains(HOSTNAME_PLACEHOLDER) {
+        layer = layer.replace(HOSTNAME_PLACEHOLDER, &whoami::fallible::hostname().ok()?);
+    }
+    if layer.contains(USERNAME_PLACEHOLDER) {
+        layer = layer.replace(USERNAME_PLACEHOLDER, &whoami::fallible::username().ok()?);
+    }
+    Some(layer)
+}
+
+/// Finds the files for each configuration layer in `config_dir`, detecting their format by
+/// extension, and returns them in the order they are merged.
+fn setup_config_paths(config_dir: &Path, layers: &[String], extensions: &[&str]) -> Vec<PathBuf> {
+    let mut listings: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+
+    let mut layer_files = |layer: &str, extension: &str| {
+        let layer_path = config_dir.join(layer);
+        let (Some(dir), Some(name)) = (layer_path.parent(), layer_path.file_name()) else {
+            return Vec::new();
+        };
+
+        listings
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| list_files(dir))
+            .iter()
+            .filter(|path| {
+                path.file_stem() == Some(name)
+                    && file_extension(path).is_some_and(|e| e.eq_ignore_ascii_case(extension))
+            })
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    let mut paths = Vec::new();
+
+    #[cfg(feature = "ini")]
+    for layer in layers {
+        paths.extend(layer_files(layer, "ini"));
+    }
+
+    for layer in layers {
+        for extension in extensions {
+            paths.extend(layer_files(layer, extension));
+        }
+    }
+
+    paths
+}
+
+/// Lists the files in `dir` in sorted order, or none if it cannot be read.
+fn list_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file())
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+#[cfg(feature = "dotenv")]
+fn dotenv_paths(config_dir: &Path, run_mode: Option<&str>) -> Vec<PathBuf> {
+    let mut paths = vec![config_dir.join(DOTENV_FILE)];
+    for mode in run_modes(run_mode) {
+        paths.push(config_dir.join(format!("{DOTENV_FILE}.{mode}")));
+    }
+    paths
+}
+
+/// Loads a `.env` file as a configuration layer, mapping `WEBSITE__PORT` to `website.port`.
+#[cfg(feature = "dotenv")]
+fn load_dotenv_file(path: &Path) -> Result<Figment, Error> {
+    let read_error = |e: dotenvy::Error| Error::ExternalError {
+        context: format!("Error reading {}", path.display()),
+        source: Box::new(e),
+    };
+
+    let mut root = serde_json::Map::new();
+    for entry in dotenvy::from_path_iter(path).map_err(read_error)? {
+        let (key, value) = entry.map_err(read_error)?;
+        let segments: Vec<String> = key
+            .split("__")
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        insert_nested(&mut root, &segments, Value::String(value));
+    }
+
+    Ok(Figment::from(figment::providers::Serialized::defaults(
+        Value::Object(root),
+    )))
+}
+
+/// Parses `--website.port=9000` and `--website.port 9000` style arguments into a table,
+/// stopping at `--`. With a `prefix`, only arguments starting with `--{prefix}` are parsed, with
+/// the prefix removed from their paths, and others are skipped; without one, any other argument
+/// is an error.
+fn parse_args(args: &[String], prefix: Option<&str>) -> Result<Value, Error> {
+    let invalid = |arg: &str, problem: &str| {
+        Error::ConfigError(format!(
+            "Invalid command-line argument {arg:?}: {problem}, expected --path=value or \
+             --path value"
+        ))
+    };
+
+    let mut root = serde_json::Map::new();
+    let mut args = args.iter().take_while(|arg| *arg != "--").peekable();
+    while let Some(arg) = args.next() {
+        let option = arg
+            .strip_prefix("--")
+            .and_then(|option| option.strip_prefix(prefix.unwrap_or_default()));
+        let Some(option) = option else {
+            if prefix.is_some() {
+                continue;
+            }
+            return Err(invalid(arg, "not an option"));
+        };
+        let (path, value) = match option.split_once('=') {
+            Some((path, value)) => (path, value),
+            None => match args.next_if(|value| !value.starts_with("--")) {
+                Some(value) => (option, value.as_str()),
+                None => return Err(invalid(arg, "missing a value")),
+            },
+        };
+        let segments: Vec<String> = path.split('.').map(ToString::to_string).collect();
+        if segments.iter().any(String::is_empty) {
+            return Err(invalid(arg, "empty path segment"));
+        }
+        insert_nested(&mut root, &segments, Value::String(value.to_string()));
+    }
+    Ok(Value::Object(root))
+}
+
+/// Inserts `value` at the path given by `segments`, replacing anything in the way with tables.
+pub fn insert_nested(
+    table: &mut serde_json::Map<String, Value>,
+    segments: &[String],
+    value: Value,
+) {
+    let Some((last, parents)) = segments.split_last() else {
+        return;
+    };
+
+    let table = parents.iter().fold(table, |table, segment| {
+        let child = table
+            .entry(segment.clone())
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+        if !child.is_object() {
+            *child = Value::Object(serde_json::Map::new());
+        }
+        child.as_object_mut().expect("child was just made a table")
+    });
+    table.insert(last.clone(), value);
+}
+
+/// Whether any of `config_paths` is a file of `layer`.
+fn layer_found(config_dir: &Path, layer: &str, config_paths: &[PathBuf]) -> bool {
+    let base = config_dir.join(layer);
+    config_paths
+        .iter()
+        .any(|path| path.with_extension("") == base)
+}
+
+/// The file reported for a missing layer, in the first of the configuration formats.
+fn expected_layer_path(config_dir: &Path, layer: &str) -> PathBuf {
+    config_dir.join(format!("{layer}.{}", CONFIG_EXTENSIONS[0]))
+}
+
+/// Finds the value at the path given by `segments`.
+fn find_nested<'a>(
+    table: &'a serde_json::Map<String, Value>,
+    segments: &[String],
+) -> Option<&'a Value> {
+    let (last, parents) = segments.split_last()?;
+    let table = parents.iter().try_fold(table, |table, segment| {
+        table.get(segment).and_then(Value::as_object)
+    })?;
+    table.get(last)
+}
+
+/// Removes the value at the path given by `segments`, returning it if there was one, along
+/// with the tables it leaves empty.
+fn remove_nested(table: &mut serde_json::Map<String, Value>, segments: &[String]) -> Option<Value> {
+    match segments {
+        [] => None,
+        [last] => table.remove(last),
+        [first, rest @ ..] => {
+            let child = table.get_mut(first)?.as_object_mut()?;
+            let removed = remove_nested(child, rest);
+            if child.is_empty() {
+                table.remove(first);
+            }
+            removed
+        }
+    }
+}
+
+/// Finds the drop-in files in `{config_dir}/conf.d`, which are merged after all layers in
+/// lexicographic order of their names. Files in formats that aren't recognised are ignored.
+fn drop_in_paths(config_dir: &Path, extensions: &[&str]) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(config_dir.join(DROP_IN_DIR)) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && file_extension(path).is_some_and(|extension| {
+                    extensions
+                        .iter()
+                        .any(|candidate| extension.eq_ignore_ascii_case(candidate))
+                })
+        })
+        .collect();
+    paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    paths
+}
+
+/// The lowercased extension of `path`, so that e.g. `default.YAML` is detected as YAML.
+fn file_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+fn load_config_from_file(path: &Path) -> Result<Figment, Error> {
+    if !path.exists() {
+        return Err(Error::ConfigError(format!(
+            "File not found: {}",
+            path.display()
+        )));
+    }
+
+    match file_extension(path).as_deref() {
+        Some("toml") => Ok(Figment::new().merge(Toml::file(path))),
+        Some("yaml" | "yml") => Ok(Figment::new().merge(Yaml::file(path))),
+        Some("json") => Ok(Figment::new().merge(Json::file(path))),
+        #[cfg(feature = "json5")]
+        Some("json5") => Ok(Figment::new().merge(Json5::file(path))),
+        #[cfg(feature = "ron")]
+        Some("ron") => Ok(Figment::new().merge(Ron::file(path))),
+        #[cfg(feature = "ini")]
+        Some("ini") => Ok(Figment::new().merge(Ini::file(path))),
+        _ => Err(Error::ConfigError(format!(
+            "Unsupported configuration file format: {}",
+            path.display()
+        ))),
+    }
+}
+
+/// Parses configuration contents whose format is given by the extension of `name`, or TOML
+/// when it has none.
+pub fn load_config_from_string(name: &str, contents: &str) -> Result<Figment, Error> {
+    match file_extension(Path::new(name)).as_deref() {
+        None | Some("toml") => Ok(Figment::new().merge(Toml::string(contents))),
+        Some("yaml" | "yml") => Ok(Figment::new().merge(Yaml::string(contents))),
+        Some("json") => Ok(Figment::new().merge(Json::string(contents))),
+        #[cfg(feature = "json5")]
+        Some("json5") => Ok(Figment::new().merge(Json5::string(contents))),
+        #[cfg(feature = "ron")]
+        Some("ron") => Ok(Figment::new().merge(Ron::string(contents))),
+        #[cfg(feature = "ini")]
+        Some("ini") => Ok(Figment::new().merge(Ini::string(contents))),
+        _ => Err(Error::ConfigError(format!(
+            "Unsupported configuration format: {name}"
+        ))),
+    }
+}
+
+/// Extracts the merged configuration, expands its tokens with `meta` as context, removes the
+/// sections used only while loading and deserializes the result.
+fn extract_config<C: TokenExpandingConfig>(
+    figment: &Figment,
+    options: &ExpansionOptions,
+    meta: &Value,
+) -> Result<C, Error> {
+    let value = extract_value(figment)?;
+    let root = serialized_defaults::<C>(&value).map(|defaults| fill_defaults(&value, defaults));
+    let (expanded, _, _) = expand_value(&value, root.as_ref(), options, meta, false)?;
+    deserialize_coerced(expanded).map_err(|e| locate(e, figment))
+}
+
+/// Merges the figment into a single value, reporting files that fail to parse with the
+/// position of the problem.
+fn extract_value(figment: &Figment) -> Result<Value, Error> {
+    figment.extract().map_err(|e| {
+        let file = e
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.source.as_ref())
+            .and_then(|source| source.file_path())
+            .map(Path::to_path_buf);
+        match file {
+            Some(file) => parse_error(&file, &e),
+            None => Error::FigmentError {
+                context: "Error extracting config".to_string(),
+                source: Box::new(e),
+            },
+        }
+    })
+}
+
+/// Expands the tokens of a merged configuration, resolving them against `root` if given, drops
+/// the sections that only serve token expansion, and returns it with the number of
+/// substitutions made. If `collect` is set, the tokens that cannot be resolved with
+/// `strict_tokens` are returned rather than failing.
+fn expand_value(
+    config_value: &Value,
+    root: Option<&Value>,
+    options: &ExpansionOptions,
+    meta: &Value,
+    collect: bool,
+) -> Result<(Value, usize, Vec<Error>), Error> {
+    let (mut replaced, substitutions, unresolved) = if collect {
+        expand_tokens_collecting(config_value, root, options, Some(meta))?
+    } else {
+        let (replaced, substitutions) =
+            expand_tokens_counted(config_value, root, options, Some(meta))?;
+        (replaced, substitutions, Vec::new())
+    };
+    if let Value::Object(map) = &mut replaced {
+        map.remove(VARS_SECTION);
+        map.remove(INCLUDE_KEY);
+    }
+
+    Ok((replaced, substitutions, unresolved))
+}
+
+/// Fills the keys missing from `value` with those of `defaults`, the serialized defaults of the
+/// configuration struct, leaving out those that are `null`.
+fn fill_defaults(value: &Value, defaults: Value) -> Value {
+    let (Value::Object(map), Value::Object(defaults)) = (value, defaults) else {
+        return value.clone();
+    };
+    let mut filled = map.clone();
+    for (key, default) in defaults {
+        match filled.get(&key) {
+            Some(value) => {
+                let value = fill_defaults(value, default);
+                filled.insert(key, value);
+            }
+            None if !default.is_null() => {
+                filled.insert(key, default);
+            }
+            None => {}
+        }
+    }
+    Value::Object(filled)
+}
+
+/// Counts the values at the leaves of `value`.
+fn count_leaves(value: &Value) -> usize {
+    match value {
+        Value::Object(map) => map.values().map(count_leaves).sum(),
+        Value::Array(items) => items.iter().map(count_leaves).sum(),
+        _ => 1,
+    }
+}
+
+/// The paths of the leaves of `value`, nested within `path`.
+fn leaf_paths(value: &Value, path: &str) -> Vec<String> {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .flat_map(|(key, value)| leaf_paths(value, &format_new_path(path, key)))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .flat_map(|(index, item)| leaf_paths(item, &format_new_array_path(path, index)))
+            .collect(),
+        _ => vec![path.to_string()],
+    }
+}
+
+/// Adds `warning` to the `report`, emitting it as a `tracing` event for the host application
+/// to log.
+fn raise(report: &mut LoadReport, warning: LoadWarning) {
+    tracing::warn!(%warning, "config warning raised");
+    report.warnings.push(warning);
+}
+
+//Note:  RUST_TEST_THREADS = "1" has been set in .cargo/config.toml because these tests are still not entirely thread safe
+#[cfg(test)]
+mod tests {
+    use derivative::Derivative;
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{ValidationError, ValueSource};
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::{LazyLock, Mutex};
+
+    // Mutex to ensure thread safety when accessing/modifying environment variables
+    static ENV_MUTEX: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+    #[derive(Debug, Serialize, Deserialize, Derivative, Clone)]
+    #[derivative(Default)]
+    #[serde(default)]
+    struct TestConfig {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[derivative(Default(value = "None"))]
+        pub run_mode: Option<String>,
+        pub test_value: Option<String>,
+    }
+
+    impl TokenExpandingConfig for TestConfig {}
+
+    fn create_config_file(path: &std::path::Path, content: &str) {
+        let mut file = File::create(path).unwrap();
+        writeln!(file, "{content}").unwrap();
+    }
+
+    #[test]
+    fn test_load_config_with_default_run_mode() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "default"
+        "#,
+        );
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("default".to_string()));
+    }
+
+    #[test]
+    fn test_load_config_with_specific_run_mode() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "default"
+        "#,
+        );
+
+        create_config_file(
+            &dir.path().join("prod.toml"),
+            r#"
+            test_value = "prod"
+        "#,
+        );
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "prod");
+        }
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("prod".to_string()));
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+        }
+    }
+
+    #[test]
+    fn test_load_config_with_null_run_mode() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "default"
+        "#,
+        );
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("default".to_string()));
+    }
+
+    #[test]
+    fn test_load_config_with_nonexistent_run_mode_file() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "default"
+        "#,
+        );
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "nonexistent");
+        }
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("default".to_string()));
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+        }
+    }
+
+    #[test]
+    fn test_load_yaml_config() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.yaml"),
+            r#"
+run_mode: "yaml"
+test_value: "default ${run_mode}"
+        "#,
+        );
+
+        create_config_file(
+            &dir.path().join("local.yml"),
+            r#"
+run_mode: "local yml"
+        "#,
+        );
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.run_mode, Some("local yml".to_string()));
+        assert_eq!(config.test_value, Some("default local yml".to_string()));
+    }
+
+    #[test]
+    fn test_toml_and_yaml_layers_merge_in_order() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "toml"
+        "#,
+        );
+
+        create_config_file(
+            &dir.path().join("local.yaml"),
+            r#"
+test_value: "yaml"
+        "#,
+        );
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("yaml".to_string()));
+    }
+
+    #[test]
+    fn test_json_layer_overrides_toml() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            run_mode = "toml"
+            test_value = "from ${run_mode}"
+        "#,
+        );
+
+        create_config_file(&dir.path().join("local.json"), r#"{ "run_mode": "json" }"#);
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("from json".to_string()));
+    }
+
+    #[cfg(feature = "json5")]
+    #[test]
+    fn test_load_json5_config() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.json5"),
+            r"{
+                // Edited by hand
+                run_mode: 'json5',
+                test_value: 'from ${run_mode}',
+            }",
+        );
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("from json5".to_string()));
+    }
+
+    #[cfg(feature = "ron")]
+    #[test]
+    fn test_load_ron_config() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "from ${run_mode}"
+        "#,
+        );
+
+        create_config_file(&dir.path().join("local.ron"), r#"(run_mode: Some("ron"))"#);
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("from ron".to_string()));
+    }
+
+    #[cfg(feature = "ini")]
+    #[test]
+    fn test_ini_layers_have_lowest_precedence() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "toml"
+        "#,
+        );
+
+        create_config_file(
+            &dir.path().join("local.ini"),
+            "run_mode = legacy\ntest_value = ini\n",
+        );
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.run_mode, Some("legacy".to_string()));
+        assert_eq!(config.test_value, Some("toml".to_string()));
+    }
+
+    #[test]
+    fn test_custom_format() {
+        struct Properties;
+
+        impl ConfigFormat for Properties {
+            fn extensions(&self) -> &[&str] {
+                &["properties"]
+            }
+
+            fn parse(
+                &self,
+                contents: &str,
+            ) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+                let mut map = serde_json::Map::new();
+                for line in contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                {
+                    let (key, value) = line
+                        .split_once('=')
+                        .ok_or_else(|| format!("expected `key=value` but got `{line}`"))?;
+                    map.insert(
+                        key.trim().to_string(),
+                        Value::String(value.trim().to_string()),
+                    );
+                }
+                Ok(Value::Object(map))
+            }
+        }
+
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            run_mode = "toml"
+            test_value = "from ${run_mode}"
+        "#,
+        );
+
+        create_config_file(
+            &dir.path().join("local.properties"),
+            "run_mode = properties",
+        );
+
+        let config: TestConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .format(Properties)
+            .load()
+            .unwrap();
+        assert_eq!(config.test_value, Some("from properties".to_string()));
+
+        create_config_file(&dir.path().join("local.properties"), "not a property");
+        assert!(GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .format(Properties)
+            .load::<TestConfig>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_mixed_format_directory() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct ServerConfig {
+            host: String,
+            port: u16,
+            tls: bool,
+        }
+
+        impl TokenExpandingConfig for ServerConfig {}
+
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            host = "localhost"
+            port = 8080
+            tls = false
+        "#,
+        );
+        create_config_file(&dir.path().join("local.YAML"), "port: 9090");
+        create_config_file(
+            &dir.path().join("prod.json"),
+            r#"{ "host": "example.com", "tls": true }"#,
+        );
+        create_config_file(&dir.path().join("default.txt"), "not configuration");
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "prod");
+        }
+
+        let config: ServerConfig = load_config_from_dir(dir.path()).unwrap();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+        }
+
+        assert_eq!(config.host, "example.com");
+        assert_eq!(config.port, 9090);
+        assert!(config.tls);
+    }
+
+    #[cfg(feature = "dotenv")]
+    #[test]
+    fn test_dotenv_layers() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Website {
+            port: u16,
+            bind_address: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct DotenvConfig {
+            website: Website,
+            name: String,
+        }
+
+        impl TokenExpandingConfig for DotenvConfig {}
+
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            name = "app"
+
+            [website]
+            port = 80
+            bind_address = "127.0.0.1"
+        "#,
+        );
+        create_config_file(
+            &dir.path().join(".env"),
+            "# Local overrides\nWEBSITE__PORT=8080\nNAME='${website.bind_address}:${website.port}'",
+        );
+        create_config_file(
+            &dir.path().join(".env.prod"),
+            "WEBSITE__BIND_ADDRESS=0.0.0.0",
+        );
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "prod");
+        }
+
+        let config: DotenvConfig = load_config_from_dir(dir.path()).unwrap();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+        }
+
+        assert_eq!(config.website.port, 8080);
+        assert_eq!(config.website.bind_address, "0.0.0.0");
+        assert_eq!(config.name, "0.0.0.0:8080");
+
+        create_config_file(&dir.path().join(".env"), "NAME='unterminated");
+        let error = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .load::<DotenvConfig>()
+            .unwrap_err();
+        assert!(matches!(error, Error::ExternalError { .. }), "{error:?}");
+        let source = std::error::Error::source(&error).unwrap();
+        assert!(source.downcast_ref::<dotenvy::Error>().is_some());
+    }
+
+    #[test]
+    fn test_builder_options() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Website {
+            port: u16,
+            url: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct BuilderConfig {
+            website: Website,
+        }
+
+        impl TokenExpandingConfig for BuilderConfig {}
+
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("config")).unwrap();
+
+        create_config_file(
+            &dir.path().join("config/default.toml"),
+            r#"
+            [website]
+            port = 80
+            url = "http://localhost:${website.port}"
+        "#,
+        );
+        create_config_file(
+            &dir.path().join("config/staging.toml"),
+            r#"
+            [website]
+            url = "https://staging.example.com:${website.port}"
+        "#,
+        );
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("GRAFTON_BUILDER_TEST_WEBSITE__PORT", "8443");
+        }
+
+        let config: BuilderConfig = GraftonConfigBuilder::new()
+            .dir(dir.path().join("config"))
+            .run_mode("staging")
+            .env_prefix("GRAFTON_BUILDER_TEST_")
+            .strict_tokens(true)
+            .load()
+            .unwrap();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("GRAFTON_BUILDER_TEST_WEBSITE__PORT");
+        }
+
+        assert_eq!(config.website.port, 8443);
+        assert_eq!(config.website.url, "https://staging.example.com:8443");
+
+        create_config_file(
+            &dir.path().join("config/staging.toml"),
+            r#"
+            [website]
+            url = "https://${website.host}"
+        "#,
+        );
+
+        let result = GraftonConfigBuilder::new()
+            .dir(dir.path().join("config"))
+            .run_mode("staging")
+            .strict_tokens(true)
+            .load::<BuilderConfig>();
+        assert!(matches!(result, Err(Error::UnresolvedToken { .. })));
+    }
+
+    #[test]
+    fn test_custom_layers() {
+        let dir = tempdir().unwrap();
+
+        for (layer, run_mode) in [
+            ("base", "base"),
+            ("region", "region"),
+            ("prod", "prod"),
+            ("prod-secrets", "prod-secrets"),
+            ("default", "default"),
+        ] {
+            create_config_file(
+                &dir.path().join(format!("{layer}.toml")),
+                &format!("run_mode = \"{run_mode}\"\ntest_value = \"{run_mode}\""),
+            );
+        }
+        create_config_file(
+            &dir.path().join("override.toml"),
+            "test_value = \"override\"",
+        );
+
+        let builder = GraftonConfigBuilder::new().dir(dir.path()).layers([
+            "base".to_string(),
+            "region".to_string(),
+            RUN_MODE_PLACEHOLDER.to_string(),
+            format!("{RUN_MODE_PLACEHOLDER}-secrets"),
+            "override".to_string(),
+        ]);
+
+        let config: TestConfig = builder.load().unwrap();
+        assert_eq!(config.run_mode, Some("region".to_string()));
+        assert_eq!(config.test_value, Some("override".to_string()));
+
+        let config: TestConfig = builder.run_mode("prod").load().unwrap();
+        assert_eq!(config.run_mode, Some("prod-secrets".to_string()));
+        assert_eq!(config.test_value, Some("override".to_string()));
+    }
+
+    #[test]
+    fn test_drop_in_directory() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join(DROP_IN_DIR)).unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            run_mode = "default"
+            test_value = "default"
+        "#,
+        );
+        create_config_file(
+            &dir.path().join("local.toml"),
+            r#"
+            test_value = "local"
+        "#,
+        );
+        create_config_file(
+            &dir.path().join(DROP_IN_DIR).join("20-logging.toml"),
+            r#"
+            test_value = "20-logging"
+        "#,
+        );
+        create_config_file(
+            &dir.path().join(DROP_IN_DIR).join("10-network.yaml"),
+            "run_mode: \"10-network\"\ntest_value: \"10-network\"",
+        );
+        create_config_file(
+            &dir.path().join(DROP_IN_DIR).join("99-notes.txt"),
+            "ignored",
+        );
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.run_mode, Some("10-network".to_string()));
+        assert_eq!(config.test_value, Some("20-logging".to_string()));
+    }
+
+    #[test]
+    fn test_include_directive() {
+        #[derive(Debug, Serialize, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct IncludeConfig {
+            database: Value,
+            cache: Value,
+            name: String,
+        }
+
+        impl TokenExpandingConfig for IncludeConfig {}
+
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("subsystems")).unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            include = ["subsystems/database.toml", "subsystems/cache.yaml"]
+            name = "app"
+
+            [database]
+            pool = 20
+        "#,
+        );
+        create_config_file(
+            &dir.path().join("subsystems/database.toml"),
+            r#"
+            include = ["defaults.toml"]
+
+            [database]
+            host = "${name}.db"
+            pool = 5
+        "#,
+        );
+        create_config_file(
+            &dir.path().join("subsystems/defaults.toml"),
+            r"
+            [database]
+            port = 5432
+        ",
+        );
+        create_config_file(
+            &dir.path().join("subsystems/cache.yaml"),
+            "cache:\n  ttl: 60",
+        );
+
+        let config: IncludeConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(
+            config.database,
+            serde_json::json!({"host": "app.db", "pool": 20, "port": 5432})
+        );
+        assert_eq!(config.cache, serde_json::json!({"ttl": 60}));
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(&dir.path().join("default.toml"), r#"include = ["a.toml"]"#);
+        create_config_file(&dir.path().join("a.toml"), r#"include = ["b.toml"]"#);
+        create_config_file(&dir.path().join("b.toml"), r#"include = ["a.toml"]"#);
+
+        match load_config_from_dir::<TestConfig>(dir.path()) {
+            Err(Error::ConfigError(message)) => {
+                assert!(message.contains("Include cycle"), "{message}");
+            }
+            other => panic!("Expected an include cycle error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_include() {
+        let dir = tempdir().unwrap();
+        create_config_file(&dir.path().join("default.toml"), "include = 5");
+
+        match load_config_from_dir::<TestConfig>(dir.path()) {
+            Err(error @ Error::FigmentError { .. }) => {
+                assert!(
+                    error.to_string().starts_with("Invalid include in "),
+                    "{error}"
+                );
+                let source = std::error::Error::source(&error).unwrap();
+                assert!(source.to_string().contains("invalid type"), "{source}");
+            }
+            other => panic!("Expected an invalid include error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_load_config_with_explicit_run_mode() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "default"
+        "#,
+        );
+        create_config_file(
+            &dir.path().join("staging.toml"),
+            r#"
+            test_value = "staging"
+        "#,
+        );
+        create_config_file(
+            &dir.path().join("prod.toml"),
+            r#"
+            test_value = "prod"
+        "#,
+        );
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "prod");
+        }
+
+        let staging: TestConfig =
+            load_config_from_dir_with_mode(dir.path(), Some("staging")).unwrap();
+        let none: TestConfig = load_config_from_dir_with_mode(dir.path(), None).unwrap();
+        let from_env: TestConfig = load_config_from_dir(dir.path()).unwrap();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+        }
+
+        assert_eq!(staging.test_value, Some("staging".to_string()));
+        assert_eq!(none.test_value, Some("default".to_string()));
+        assert_eq!(from_env.test_value, Some("prod".to_string()));
+    }
+
+    #[test]
+    fn test_multiple_run_modes() {
+        let dir = tempdir().unwrap();
+
+        for (mode, content) in [
+            (
+                "default",
+                "run_mode = \"default\"\ntest_value = \"default\"",
+            ),
+            ("prod", "run_mode = \"prod\"\ntest_value = \"prod\""),
+            ("eu", "test_value = \"eu\""),
+            ("canary", "run_mode = \"canary\""),
+        ] {
+            create_config_file(&dir.path().join(format!("{mode}.toml")), content);
+        }
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "prod, eu,canary");
+        }
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+        }
+
+        assert_eq!(config.run_mode, Some("canary".to_string()));
+        assert_eq!(config.test_value, Some("eu".to_string()));
+    }
+
+    #[test]
+    fn test_local_run_mode_overrides() {
+        let dir = tempdir().unwrap();
+
+        for (layer, content) in [
+            (
+                "default",
+                "run_mode = \"default\"\ntest_value = \"default\"",
+            ),
+            ("local", "run_mode = \"local\"\ntest_value = \"local\""),
+            ("prod", "run_mode = \"prod\"\ntest_value = \"prod\""),
+            ("local.prod", "test_value = \"local.prod\""),
+            ("local.staging", "test_value = \"local.staging\""),
+        ] {
+            create_config_file(&dir.path().join(format!("{layer}.toml")), content);
+        }
+
+        let config: TestConfig = load_config_from_dir_with_mode(dir.path(), Some("prod")).unwrap();
+        assert_eq!(config.run_mode, Some("prod".to_string()));
+        assert_eq!(config.test_value, Some("local.prod".to_string()));
+
+        let config: TestConfig = load_config_from_dir_with_mode(dir.path(), None).unwrap();
+        assert_eq!(config.test_value, Some("local".to_string()));
+    }
+
+    #[test]
+    fn test_nested_env_vars() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Website {
+            port: u16,
+            bind_address: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct EnvConfig {
+            website: Website,
+        }
+
+        impl TokenExpandingConfig for EnvConfig {}
+
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            [website]
+            port = 80
+            bind_address = "127.0.0.1"
+        "#,
+        );
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("GRAFTON_NESTED_TEST__WEBSITE__BIND_ADDRESS", "0.0.0.0");
+            env::set_var("GRAFTON_DOTTED_TEST_WEBSITE.PORT", "8080");
+        }
+
+        let nested: Result<EnvConfig, Error> = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .env_prefix("GRAFTON_NESTED_TEST__")
+            .load();
+        let dotted: Result<EnvConfig, Error> = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .env_prefix("GRAFTON_DOTTED_TEST_")
+            .env_separator(".")
+            .load();
+        let unprefixed: Result<EnvConfig, Error> = load_config_from_dir(dir.path());
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("GRAFTON_NESTED_TEST__WEBSITE__BIND_ADDRESS");
+            env::remove_var("GRAFTON_DOTTED_TEST_WEBSITE.PORT");
+        }
+
+        let nested = nested.unwrap();
+        assert_eq!(nested.website.bind_address, "0.0.0.0");
+        assert_eq!(nested.website.port, 80);
+
+        let dotted = dotted.unwrap();
+        assert_eq!(dotted.website.bind_address, "127.0.0.1");
+        assert_eq!(dotted.website.port, 8080);
+
+        assert_eq!(unprefixed.unwrap().website.bind_address, "127.0.0.1");
+    }
+
+    #[test]
+    fn test_run_mode_env() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(&dir.path().join("default.toml"), "test_value = \"default\"");
+        create_config_file(&dir.path().join("staging.toml"), "test_value = \"staging\"");
+        create_config_file(&dir.path().join("prod.toml"), "test_value = \"prod\"");
+
+        let load = || -> TestConfig {
+            GraftonConfigBuilder::new()
+                .dir(dir.path())
+                .run_mode_env("GRAFTON_TEST_APP_ENV")
+                .default_run_mode("staging")
+                .load()
+                .unwrap()
+        };
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "prod");
+            env::remove_var("GRAFTON_TEST_APP_ENV");
+        }
+        let defaulted = load();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("GRAFTON_TEST_APP_ENV", "prod");
+        }
+        let selected = load();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+            env::remove_var("GRAFTON_TEST_APP_ENV");
+        }
+
+        assert_eq!(defaulted.test_value, Some("staging".to_string()));
+        assert_eq!(selected.test_value, Some("prod".to_string()));
+    }
+
+    #[test]
+    fn test_map_env_var() {
+        let mappings = vec![
+            ("WEBSITE_".to_string(), "website".to_string()),
+            ("DB_".to_string(), "services.database".to_string()),
+        ];
+
+        assert_eq!(
+            map_env_var(&mappings, "__", "WEBSITE_BIND_ADDRESS"),
+            Some("website.bind_address".to_string())
+        );
+        assert_eq!(
+            map_env_var(&mappings, "__", "db_pool__max_size"),
+            Some("services.database.pool.max_size".to_string())
+        );
+        assert_eq!(map_env_var(&mappings, "__", "WEBSITE_"), None);
+        assert_eq!(map_env_var(&mappings, "__", "LOGGER_LEVEL"), None);
+    }
+
+    #[test]
+    fn test_env_mappings() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Website {
+            port: u16,
+            bind_address: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct EnvConfig {
+            website: Website,
+            log_level: String,
+        }
+
+        impl TokenExpandingConfig for EnvConfig {}
+
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            log_level = "info"
+
+            [website]
+            port = 80
+            bind_address = "127.0.0.1"
+        "#,
+        );
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("GRAFTON_MAPPED_WEBSITE_BIND_ADDRESS", "0.0.0.0");
+            env::set_var("GRAFTON_MAPPED_LOG", "debug");
+        }
+
+        let config: Result<EnvConfig, Error> = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .env_mapping("GRAFTON_MAPPED_WEBSITE_", "website")
+            .env_mapper(|key| (key == "GRAFTON_MAPPED_LOG").then(|| "log_level".to_string()))
+            .load();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("GRAFTON_MAPPED_WEBSITE_BIND_ADDRESS");
+            env::remove_var("GRAFTON_MAPPED_LOG");
+        }
+
+        let config = config.unwrap();
+        assert_eq!(config.website.bind_address, "0.0.0.0");
+        assert_eq!(config.website.port, 80);
+        assert_eq!(config.log_level, "debug");
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(ToString::to_string).collect() };
+
+        assert_eq!(
+            parse_args(
+                &args(&[
+                    "--website.port=9000",
+                    "--website.url=http://host/?a=b",
+                    "--name",
+                    "app",
+                    "--",
+                    "--ignored=true",
+                ]),
+                None
+            )
+            .unwrap(),
+            serde_json::json!({
+                "website": { "port": "9000", "url": "http://host/?a=b" },
+                "name": "app",
+            })
+        );
+
+        for invalid in [
+            &["positional"][..],
+            &["--verbose"],
+            &["--verbose", "--name=app"],
+            &["--website..port=9000"],
+        ] {
+            assert!(
+                matches!(parse_args(&args(invalid), None), Err(Error::ConfigError(_))),
+                "{invalid:?}"
+            );
+        }
+
+        assert_eq!(
+            parse_args(
+                &args(&[
+                    "--verbose",
+                    "input.txt",
+                    "--config.website.port",
+                    "9000",
+                    "--config.name=app",
+                    "--other=1",
+                ]),
+                Some("config.")
+            )
+            .unwrap(),
+            serde_json::json!({
+                "website": { "port": "9000" },
+                "name": "app",
+            })
+        );
+    }
+
+    #[test]
+    fn test_args_override_env_vars() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(&dir.path().join("default.toml"), "test_value = \"default\"");
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("GRAFTON_ARGS_TEST_TEST_VALUE", "env");
+        }
+
+        let config: Result<TestConfig, Error> = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .env_prefix("GRAFTON_ARGS_TEST_")
+            .args(["--test_value=cli"])
+            .load();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("GRAFTON_ARGS_TEST_TEST_VALUE");
+        }
+
+        assert_eq!(config.unwrap().test_value, Some("cli".to_string()));
+    }
+
+    #[test]
+    fn test_env_vars_for_string_fields() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Names {
+            name: String,
+            flag: String,
+            ratio: Option<String>,
+        }
+
+        impl TokenExpandingConfig for Names {}
+
+        let dir = tempdir().unwrap();
+        create_config_file(&dir.path().join("default.toml"), "name = \"default\"");
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("GRAFTON_STRING_TEST__NAME", "123");
+            env::set_var("GRAFTON_STRING_TEST__FLAG", "true");
+            env::set_var("GRAFTON_STRING_TEST__RATIO", "0.5");
+        }
+
+        let config: Result<Names, Error> = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .env_prefix("GRAFTON_STRING_TEST__")
+            .load();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("GRAFTON_STRING_TEST__NAME");
+            env::remove_var("GRAFTON_STRING_TEST__FLAG");
+            env::remove_var("GRAFTON_STRING_TEST__RATIO");
+        }
+
+        let config = config.unwrap();
+        assert_eq!(config.name, "123");
+        assert_eq!(config.flag, "true");
+        assert_eq!(config.ratio.as_deref(), Some("0.5"));
+    }
+
+    #[test]
+    fn test_load_config_from_str() {
+        let config: TestConfig = load_config_from_str(
+            r#"
+            test_value = "${vars.name}"
+
+            [vars]
+            name = "embedded"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.test_value, Some("embedded".to_string()));
+
+        let config: TestConfig = load_config_from_str_layers(&[
+            (
+                "default",
+                "run_mode = \"default\"\ntest_value = \"default\"",
+            ),
+            ("local.yaml", "test_value: ${run_mode}-local"),
+            ("override.json", r#"{ "run_mode": "json" }"#),
+        ])
+        .unwrap();
+        assert_eq!(config.run_mode, Some("json".to_string()));
+        assert_eq!(config.test_value, Some("json-local".to_string()));
+
+        assert!(load_config_from_str_layers::<TestConfig>(&[("default.xml", "")]).is_err());
+    }
+
+    #[test]
+    fn test_tokens_reference_defaults() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct DefaultsConfig {
+            #[serde(default = "default_host")]
+            host: String,
+            #[serde(default)]
+            port: u16,
+            url: String,
+        }
+
+        impl TokenExpandingConfig for DefaultsConfig {}
+
+        fn default_host() -> String {
+            "defhost".to_string()
+        }
+
+        let config: DefaultsConfig =
+            load_config_from_str(r#"url = "http://${host}:${port}/""#).unwrap();
+        assert_eq!(config.host, "defhost");
+        assert_eq!(config.url, "http://defhost:0/");
+
+        let config: DefaultsConfig = load_config_from_str(
+            r#"
+            host = "example.com"
+            port = "${vars.port}"
+            url = "http://${host}:${port}/"
+
+            [vars]
+            port = 8080
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.url, "http://example.com:8080/");
+    }
+
+    #[test]
+    fn test_load_config_from_reader() {
+        let toml = "run_mode = \"piped\"\ntest_value = \"${run_mode}\"";
+        let config: TestConfig = load_config_from_reader(toml.as_bytes()).unwrap();
+        assert_eq!(config.test_value, Some("piped".to_string()));
+
+        assert!(matches!(
+            load_config_from_reader::<TestConfig>(&[0xff, 0xfe][..]),
+            Err(Error::IoError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_hermetic() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "test_value = \"${env:GRAFTON_HERMETIC_TEST_VALUE:-default}\"",
+        );
+        create_config_file(&dir.path().join("prod.toml"), "run_mode = \"prod\"");
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "prod");
+            env::set_var("GRAFTON_HERMETIC_TEST_VALUE", "env");
+        }
+
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .env_prefix("GRAFTON_HERMETIC_")
+            .hermetic(true);
+        let hermetic = builder.load::<TestConfig>();
+
+        create_config_file(&dir.path().join("default.toml"), "test_value = \"file\"");
+        let from_files = builder.load::<TestConfig>();
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+            env::remove_var("GRAFTON_HERMETIC_TEST_VALUE");
+        }
+
+        assert!(matches!(hermetic, Err(Error::TokenBuiltinError { .. })));
+
+        let from_files = from_files.unwrap();
+        assert_eq!(from_files.run_mode, None);
+        assert_eq!(from_files.test_value, Some("file".to_string()));
+    }
+
+    #[test]
+    fn test_docker_secrets() {
+        let dir = tempdir().unwrap();
+        let secrets = tempdir().unwrap();
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "test_value = \"postgres://app:${secrets.db_password}@db\"",
+        );
+        create_config_file(&secrets.path().join("db_password"), "hunter2");
+
+        let config: TestConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .docker_secrets_dir(secrets.path())
+            .load()
+            .unwrap();
+        assert_eq!(
+            config.test_value,
+            Some("postgres://app:hunter2@db".to_string())
+        );
+
+        let config: TestConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .docker_secrets_dir(secrets.path().join("missing"))
+            .load()
+            .unwrap();
+        assert_eq!(
+            config.test_value,
+            Some("postgres://app:${secrets.db_password}@db".to_string())
+        );
+    }
+
+    #[test]
+    fn test_config_dir_candidates() {
+        let vars = |name: &str| match name {
+            "MY_APP_CONFIG_DIR" => Some("/opt/my-app".into()),
+            "XDG_CONFIG_HOME" => Some("/home/me/.xdg".into()),
+            "HOME" => Some("/home/me".into()),
+            _ => None,
+        };
+
+        let candidates = config_dir_candidates("my-app", vars);
+        assert_eq!(candidates[0], PathBuf::from("/opt/my-app"));
+        assert_eq!(candidates[1], PathBuf::from("/home/me/.xdg/my-app"));
+        assert_eq!(candidates[2], PathBuf::from("/home/me/.config/my-app"));
+        #[cfg(not(windows))]
+        assert_eq!(candidates[3], PathBuf::from("/etc/my-app"));
+
+        let candidates = config_dir_candidates("my-app", |_| None);
+        #[cfg(not(windows))]
+        assert_eq!(candidates, vec![PathBuf::from("/etc/my-app")]);
+    }
+
+    #[test]
+    fn test_load_config_auto() {
+        let dir = tempdir().unwrap();
+        create_config_file(&dir.path().join("default.toml"), "test_value = \"found\"");
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("GRAFTON_AUTO_TEST_CONFIG_DIR", dir.path());
+        }
+
+        let config = load_config_auto::<TestConfig>("grafton-auto-test");
+
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("GRAFTON_AUTO_TEST_CONFIG_DIR");
+        }
+
+        assert_eq!(config.unwrap().test_value, Some("found".to_string()));
+        assert!(load_config_auto::<TestConfig>("grafton-auto-test-missing").is_err());
+    }
+
+    #[test]
+    fn test_find_upward() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("crates/app/src");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir(dir.path().join("config")).unwrap();
+        create_config_file(&dir.path().join("crates/app/Cargo.toml"), "");
+
+        assert_eq!(find_upward(&nested, Path::new("config")), Some(dir.path()));
+        assert_eq!(
+            find_upward(&nested, Path::new("Cargo.toml")),
+            Some(dir.path().join("crates/app").as_path())
+        );
+        assert_eq!(
+            find_upward(&nested, Path::new("grafton-missing-marker")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_host_and_user_layers() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("hosts")).unwrap();
+        fs::create_dir(dir.path().join("users")).unwrap();
+
+        let hostname = whoami::fallible::hostname().unwrap();
+        let username = whoami::fallible::username().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            run_mode = "default"
+            test_value = "default"
+        "#,
+        );
+        create_config_file(
+            &dir.path().join(format!("hosts/{hostname}.toml")),
+            "run_mode = \"host\"\ntest_value = \"host\"",
+        );
+        create_config_file(
+            &dir.path().join(format!("users/{username}.toml")),
+            "test_value = \"user\"",
+        );
+
+        let config: TestConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.test_value, Some("default".to_string()));
+
+        let config: TestConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .host_and_user_layers(true)
+            .load()
+            .unwrap();
+        assert_eq!(config.run_mode, Some("host".to_string()));
+        assert_eq!(config.test_value, Some("user".to_string()));
+    }
+
+    #[test]
+    fn test_load_config_preserves_skip_serializing_fields() {
+        #[derive(Debug, Serialize, Deserialize, Default)]
+        #[serde(default)]
+        struct SecretConfig {
+            #[serde(skip_serializing)]
+            secret: Option<String>,
+            greeting: Option<String>,
+        }
+
+        impl TokenExpandingConfig for SecretConfig {}
+
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            secret = "hunter2"
+            greeting = "password is ${secret}"
+        "#,
+        );
+
+        let config: SecretConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.secret, Some("hunter2".to_string()));
+        assert_eq!(config.greeting, Some("password is hunter2".to_string()));
+    }
+
+    #[test]
+    fn test_vars_section_is_removed_after_expansion() {
+        #[derive(Debug, Serialize, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct StrictConfig {
+            url: String,
+        }
+
+        impl TokenExpandingConfig for StrictConfig {}
+
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            url = "https://${vars.host}:${vars.port}"
+
+            [vars]
+            host = "example.com"
+            port = 8443
+        "#,
+        );
+
+        let config: StrictConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.url, "https://example.com:8443");
+    }
+
+    #[test]
+    fn test_expanded_strings_are_coerced() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct ServerConfig {
+            port: u16,
+            tls: bool,
+        }
+
+        impl TokenExpandingConfig for ServerConfig {}
+
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            port = "${vars.port}"
+            tls = "${vars.tls}"
+
+            [vars]
+            port = 8443
+            tls = true
+        "#,
+        );
 
-const DEFAULT_CONFIG_FILE: &str = "default.toml";
+        let config: ServerConfig = load_config_from_dir(dir.path()).unwrap();
+        assert_eq!(config.port, 8443);
+        assert!(config.tls);
+    }
 
-// Mutex to ensure thread safety when accessing/modifying environment variables
-lazy_static::lazy_static! {
-    static ref ENV_MUTEX: Mutex<()> = Mutex::new(());
-}
+    #[test]
+    fn test_meta_tokens() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct MetaConfig {
+            log_dir: String,
+            log_file: String,
+        }
 
-/// Load configuration from the given directory.
-///
-/// The configuration is loaded from the following files in the given directory:
-/// - `default.toml`
-/// - `local.toml`
-/// - `{run_mode}.toml`
-///
-/// # Errors
-///
-/// This function returns an error if any of the configuration files are not found or if there
-/// is an error parsing the configuration.
-pub fn load_config_from_dir<C: TokenExpandingConfig>(config_dir: &str) -> Result<C, Error> {
-    let run_mode = determine_run_mode();
-    let config_paths = setup_config_paths(config_dir, run_mode);
+        impl TokenExpandingConfig for MetaConfig {}
 
-    let mut figment = Figment::new();
-    for path in &config_paths {
-        if path.exists() {
-            let config = load_config_from_file(path)?;
-            figment = figment.merge(config);
-        } else if path.file_name() == Some(DEFAULT_CONFIG_FILE.as_ref()) {
-            let abs_path = path.canonicalize().unwrap_or_else(|_| path.clone());
-            eprintln!("Default configuration file not found: {abs_path:?}");
-        }
-    }
+        let dir = tempdir().unwrap();
 
-    handle_env_vars();
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            log_dir = "${__config_dir}/../logs"
+            log_file = "${log_dir}/${__run_mode}.log"
+        "#,
+        );
 
-    let config: C = figment
-        .extract()
-        .map_err(|e| Error::ConfigError(format!("Error extracting config: {e}")))?;
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::set_var("RUN_MODE", "staging");
+        }
 
-    let config_value: Value = serde_json::to_value(&config)
-        .map_err(|e| Error::SerializationError(format!("Error serializing config: {e}")))?;
+        let config: MetaConfig = load_config_from_dir(dir.path()).unwrap();
 
-    let replaced = expand_tokens(&config_value)?;
+        {
+            let _lock = ENV_MUTEX.lock().unwrap();
+            env::remove_var("RUN_MODE");
+        }
 
-    serde_json::from_value(replaced)
-        .map_err(|e| Error::DeserializationError(format!("Error deserializing config: {e}")))
-}
+        let config_dir = dir.path().canonicalize().unwrap();
+        let expected_log_dir = format!("{}/../logs", config_dir.display());
+        assert_eq!(config.log_dir, expected_log_dir);
+        assert_eq!(config.log_file, format!("{expected_log_dir}/staging.log"));
+    }
 
-fn determine_run_mode() -> Option<String> {
-    env::var("RUN_MODE").ok()
-}
+    #[test]
+    fn test_load_validated() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct PoolConfig {
+            workers: usize,
+            max_workers: usize,
+        }
 
-fn setup_config_paths(config_dir: &str, run_mode: Option<String>) -> Vec<PathBuf> {
-    let current_dir = env::current_dir().expect("Failed to get current directory");
-    let absolute_config_dir = current_dir.join(config_dir);
+        impl TokenExpandingConfig for PoolConfig {}
 
-    let mut paths = vec![
-        absolute_config_dir.join("default.toml"),
-        absolute_config_dir.join("local.toml"),
-    ];
+        impl ValidatableConfig for PoolConfig {
+            fn validate(&self) -> Result<(), Vec<ValidationError>> {
+                let mut errors = Vec::new();
+                if self.workers == 0 {
+                    errors.push(ValidationError::new("workers", "must be at least 1"));
+                }
+                if self.max_workers > 64 {
+                    errors.push(ValidationError::new("max_workers", "must be at most 64"));
+                }
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(errors)
+                }
+            }
+        }
 
-    if let Some(run_mode) = run_mode {
-        paths.push(absolute_config_dir.join(format!("{run_mode}.toml")));
-    }
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode();
 
-    paths
-}
+        create_config_file(&path, "workers = 4\nmax_workers = 8\n");
+        let config: PoolConfig = builder.load_validated().unwrap();
+        assert_eq!(config.workers, 4);
 
-fn load_config_from_file(path: &Path) -> Result<Figment, Error> {
-    if path.exists() {
-        Ok(Figment::new().merge(Toml::file(path)))
-    } else {
-        Err(Error::ConfigError(format!("File not found: {path:?}")))
+        create_config_file(&path, "workers = 0\nmax_workers = 128\n");
+        match builder.load_validated::<PoolConfig>() {
+            Err(Error::ValidationFailed(errors)) => assert_eq!(
+                errors,
+                [
+                    ValidationError::new("workers", "must be at least 1"),
+                    ValidationError::new("max_workers", "must be at most 64"),
+                ]
+            ),
+            other => panic!("expected a validation failure, got {other:?}"),
+        }
     }
-}
 
-fn handle_env_vars() {
-    let _lock = ENV_MUTEX.lock().unwrap();
-    env::vars().for_each(|(key, value)| {
-        env::set_var(map_env_var(&key), value);
-    });
-}
+    #[cfg(feature = "validator")]
+    #[test]
+    fn test_load_and_validate() {
+        #[derive(Debug, Serialize, Deserialize, validator::Validate)]
+        struct ContactConfig {
+            #[validate(email)]
+            admin: String,
+            #[validate(url)]
+            homepage: String,
+        }
 
-fn map_env_var(key: &str) -> String {
-    match key {
-        k if k.starts_with("WEBSITE_") => format!("WEBSITE.{}", &k[8..]),
-        k if k.starts_with("SESSION_") => format!("SESSION.{}", &k[8..]),
-        k if k.starts_with("LOGGER_") => format!("LOGGER.{}", &k[7..]),
-        _ => key.to_string(),
-    }
-}
+        impl TokenExpandingConfig for ContactConfig {}
 
-//Note:  RUST_TEST_THREADS = "1" has been set in .cargo/config.toml because these tests are still not entirely thread safe
-#[cfg(test)]
-mod tests {
-    use derivative::Derivative;
-    use serde::{Deserialize, Serialize};
-    use tempfile::tempdir;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode();
 
-    use super::*;
-    use std::fs::File;
-    use std::io::Write;
+        create_config_file(
+            &path,
+            "admin = \"ops@example.com\"\nhomepage = \"https://example.com\"\n",
+        );
+        let config: ContactConfig = builder.load_and_validate().unwrap();
+        assert_eq!(config.admin, "ops@example.com");
 
-    #[derive(Debug, Serialize, Deserialize, Derivative, Clone)]
-    #[derivative(Default)]
-    #[serde(default)]
-    struct TestConfig {
-        #[serde(skip_serializing_if = "Option::is_none")]
-        #[derivative(Default(value = "None"))]
-        pub run_mode: Option<String>,
-        pub test_value: Option<String>,
+        create_config_file(&path, "admin = \"ops\"\nhomepage = \"example\"\n");
+        let error = builder
+            .load_and_validate::<ContactConfig>()
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "Config failed validation: admin: failed email check; homepage: failed url check"
+        );
     }
 
-    impl TokenExpandingConfig for TestConfig {}
+    #[cfg(feature = "json-schema")]
+    #[test]
+    fn test_json_schema() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .json_schema(serde_json::json!({
+                "type": "object",
+                "properties": { "test_value": { "type": "string" } },
+                "additionalProperties": false,
+            }));
 
-    fn setup_test_env(config_dir: &std::path::Path) {
-        let _lock = ENV_MUTEX.lock().unwrap();
-        env::set_current_dir(config_dir).unwrap();
-    }
+        create_config_file(
+            &path,
+            "test_value = \"${vars.name}\"\n\n[vars]\nname = \"grafton\"\n",
+        );
+        let config: TestConfig = builder.load().unwrap();
+        assert_eq!(config.test_value, Some("grafton".to_string()));
 
-    fn create_config_file(path: &std::path::Path, content: &str) {
-        let mut file = File::create(path).unwrap();
-        writeln!(file, "{content}").unwrap();
+        create_config_file(&path, "test_value = \"grafton\"\ntest_valeu = \"typo\"\n");
+        match builder.load::<TestConfig>() {
+            Err(Error::ValidationFailed(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert!(errors[0].message.contains("test_valeu"), "{}", errors[0]);
+            }
+            other => panic!("expected a validation failure, got {other:?}"),
+        }
     }
 
     #[test]
-    fn test_load_config_with_default_run_mode() {
+    fn test_deny_unknown_keys() {
         let dir = tempdir().unwrap();
-        setup_test_env(dir.path());
-
         create_config_file(
             &dir.path().join("default.toml"),
             r#"
-            test_value = "default"
+            test_value = "known"
+            test_valeu = "misspelled"
+
+            [vars]
+            unused = "vars are not configuration"
         "#,
         );
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode();
 
-        let config: TestConfig = load_config_from_dir(".").unwrap();
-        assert_eq!(config.test_value, Some("default".to_string()));
+        let config: TestConfig = builder.load().unwrap();
+        assert_eq!(config.test_value, Some("known".to_string()));
+
+        match builder.deny_unknown_keys(true).load::<TestConfig>() {
+            Err(error @ Error::UnknownKeys(_)) => assert_eq!(
+                error.to_string(),
+                "Unknown config keys: test_valeu (did you mean test_value?)"
+            ),
+            other => panic!("expected unknown keys, got {other:?}"),
+        }
     }
 
     #[test]
-    fn test_load_config_with_specific_run_mode() {
+    fn test_unused_key_warnings() {
         let dir = tempdir().unwrap();
-        setup_test_env(dir.path());
-
         create_config_file(
             &dir.path().join("default.toml"),
             r#"
-            test_value = "default"
+            test_value = "known"
+            test_valeu = "misspelled"
         "#,
         );
 
+        let (config, report): (TestConfig, _) = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .load_with_report()
+            .unwrap();
+        assert_eq!(config.test_value, Some("known".to_string()));
+        assert_eq!(
+            report.warnings,
+            [LoadWarning::UnusedKey {
+                path: "test_valeu".to_string(),
+                suggestion: Some("test_value".to_string()),
+            }]
+        );
+        assert_eq!(
+            report.warnings[0].to_string(),
+            "unused config key: test_valeu (did you mean test_value?)"
+        );
+    }
+
+    /// Collects the events emitted while it is the default subscriber, with their fields.
+    #[derive(Clone, Default)]
+    struct EventCollector(Arc<Mutex<Vec<String>>>);
+
+    impl tracing::Subscriber for EventCollector {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut fields = Vec::new();
+            event.record(
+                &mut |field: &tracing::field::Field, value: &dyn std::fmt::Debug| {
+                    fields.push(format!("{field}={value:?}"));
+                },
+            );
+            let level = event.metadata().level();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{level} {}", fields.join(" ")));
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[test]
+    fn test_emits_events() {
+        let dir = tempdir().unwrap();
         create_config_file(
-            &dir.path().join("prod.toml"),
+            &dir.path().join("default.toml"),
             r#"
-            test_value = "prod"
+            test_value = "${vars.name}"
+            test_valeu = "misspelled"
+
+            [vars]
+            name = "app"
         "#,
         );
-
         {
             let _lock = ENV_MUTEX.lock().unwrap();
-            env::set_var("RUN_MODE", "prod");
+            env::set_var("GRAFTON_EVENTS_TEST_VARS__NAME", "env-app");
         }
 
-        let config: TestConfig = load_config_from_dir(".").unwrap();
-        assert_eq!(config.test_value, Some("prod".to_string()));
+        let collector = EventCollector::default();
+        let config: TestConfig = tracing::subscriber::with_default(collector.clone(), || {
+            GraftonConfigBuilder::new()
+                .dir(dir.path())
+                .without_run_mode()
+                .env_prefix("GRAFTON_EVENTS_TEST_")
+                .load()
+                .unwrap()
+        });
 
         {
             let _lock = ENV_MUTEX.lock().unwrap();
-            env::remove_var("RUN_MODE");
+            env::remove_var("GRAFTON_EVENTS_TEST_VARS__NAME");
         }
+
+        assert_eq!(config.test_value, Some("env-app".to_string()));
+        let dir = dir.path().display();
+        assert_eq!(
+            *collector.0.lock().unwrap(),
+            [
+                format!("DEBUG message=config layer missing path={dir}/local.toml"),
+                "DEBUG message=environment override applied key=\"vars.name\"".to_string(),
+                format!(
+                    "DEBUG message=config layer loaded source=default layer ({dir}/default.toml) \
+                     keys=3"
+                ),
+                "DEBUG message=config layer loaded source=`GRAFTON_EVENTS_TEST_` environment \
+                 variable(s) keys=1"
+                    .to_string(),
+                "TRACE message=token expanded path=\"test_value\" token=\"vars.name\" \
+                 source=\"vars.name\""
+                    .to_string(),
+                "WARN message=config warning raised warning=unused config key: test_valeu (did \
+                 you mean test_value?)"
+                    .to_string(),
+            ]
+        );
     }
 
     #[test]
-    fn test_load_config_with_null_run_mode() {
+    fn test_load_report() {
         let dir = tempdir().unwrap();
-        setup_test_env(dir.path());
-
         create_config_file(
             &dir.path().join("default.toml"),
             r#"
-            test_value = "default"
+            test_value = "${vars.greeting}, ${vars.name}"
+
+            [vars]
+            greeting = "hello"
+            name = "world"
         "#,
         );
+        create_config_file(&dir.path().join("prod.toml"), r#"run_mode = "prod""#);
 
-        let config: TestConfig = load_config_from_dir(".").unwrap();
-        assert_eq!(config.test_value, Some("default".to_string()));
+        let (config, report): (TestConfig, _) = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .run_mode("prod")
+            .load_with_report()
+            .unwrap();
+        assert_eq!(config.test_value, Some("hello, world".to_string()));
+
+        let config_dir = dir.path().canonicalize().unwrap();
+        assert_eq!(report.run_mode, Some("prod".to_string()));
+        assert_eq!(
+            report.files_found,
+            [
+                config_dir.join("default.toml"),
+                config_dir.join("prod.toml")
+            ]
+        );
+        assert_eq!(
+            report.files_missing,
+            [
+                config_dir.join("local.toml"),
+                config_dir.join("local.prod.toml")
+            ]
+        );
+        let file_layers: Vec<(Option<&str>, usize)> = report
+            .layers
+            .iter()
+            .filter(|layer| layer.source.file.is_some())
+            .map(|layer| (layer.source.layer.as_deref(), layer.keys))
+            .collect();
+        assert_eq!(file_layers, [(Some("default"), 3), (Some("prod"), 1)]);
+        assert_eq!(report.tokens_expanded, 2);
+        assert!(report.warnings.is_empty());
+        assert!(report
+            .to_string()
+            .starts_with("loaded 2 config files for run mode prod in "));
     }
 
     #[test]
-    fn test_load_config_with_nonexistent_run_mode_file() {
+    fn test_layer_policies() {
         let dir = tempdir().unwrap();
-        setup_test_env(dir.path());
+        create_config_file(&dir.path().join("local.toml"), r#"test_value = "local""#);
+        let builder = GraftonConfigBuilder::new().dir(dir.path()).run_mode("prod");
+
+        let (config, report): (TestConfig, _) = builder.load_with_report().unwrap();
+        assert_eq!(config.test_value, Some("local".to_string()));
+        let default_path = dir.path().canonicalize().unwrap().join("default.toml");
+        assert_eq!(
+            report.warnings,
+            [LoadWarning::MissingLayer {
+                layer: "default".to_string(),
+                path: default_path.display().to_string(),
+            }]
+        );
+
+        let (_, report) = builder
+            .layer_policy("default", LayerPolicy::Optional)
+            .layer_policy("local", LayerPolicy::Required)
+            .load_with_report::<TestConfig>()
+            .unwrap();
+        assert!(report.warnings.is_empty());
+
+        let result = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .run_mode("prod")
+            .layer_policy("{run_mode}", LayerPolicy::Required)
+            .load::<TestConfig>();
+        match result {
+            Err(Error::MissingLayer { layer, .. }) => assert_eq!(layer, "prod"),
+            other => panic!("expected a missing layer, got {other:?}"),
+        }
+
+        assert!(GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .layer_policy("{run_mode}", LayerPolicy::Required)
+            .load::<TestConfig>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_renamed_keys() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct WebsiteConfig {
+            bind_address: String,
+            url: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct RenamedConfig {
+            website: WebsiteConfig,
+        }
+
+        impl TokenExpandingConfig for RenamedConfig {}
 
+        let dir = tempdir().unwrap();
+        let default_path = dir.path().join("default.toml");
         create_config_file(
-            &dir.path().join("default.toml"),
+            &default_path,
             r#"
-            test_value = "default"
+            [server]
+            addr = "0.0.0.0"
+
+            [website]
+            url = "http://${website.bind_address}"
         "#,
         );
 
-        {
-            let _lock = ENV_MUTEX.lock().unwrap();
-            env::set_var("RUN_MODE", "nonexistent");
+        let (config, report): (RenamedConfig, _) = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .renamed_key("server.addr", "website.bind_address")
+            .renamed_key("server.port", "website.port")
+            .load_with_report()
+            .unwrap();
+
+        assert_eq!(config.website.bind_address, "0.0.0.0");
+        assert_eq!(config.website.url, "http://0.0.0.0");
+        let source = dir.path().canonicalize().unwrap().join("default.toml");
+        assert_eq!(
+            report.warnings,
+            [LoadWarning::DeprecatedKey {
+                old: "server.addr".to_string(),
+                new: "website.bind_address".to_string(),
+                source: Some(source.display().to_string()),
+            }]
+        );
+        assert_eq!(
+            report.warnings[0].to_string(),
+            format!(
+                "config key server.addr is deprecated, use website.bind_address instead (set in {})",
+                source.display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_migrations() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct VersionedConfig {
+            listen: String,
+            workers: u32,
         }
 
-        let config: TestConfig = load_config_from_dir(".").unwrap();
-        assert_eq!(config.test_value, Some("default".to_string()));
+        impl TokenExpandingConfig for VersionedConfig {}
 
-        {
-            let _lock = ENV_MUTEX.lock().unwrap();
-            env::remove_var("RUN_MODE");
+        fn rename(mut value: Value, old: &str, new: &str) -> Value {
+            if let Some(map) = value.as_object_mut() {
+                if let Some(moved) = map.remove(old) {
+                    map.insert(new.to_string(), moved);
+                }
+            }
+            value
+        }
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .deny_unknown_keys(true)
+            .migration(1, |value| rename(value, "address", "host"))
+            .migration(2, |value| rename(value, "host", "listen"))
+            .migration(3, |value| rename(value, "threads", "workers"));
+
+        create_config_file(&path, "address = \"0.0.0.0\"\nthreads = 4\n");
+        let config: VersionedConfig = builder.load().unwrap();
+        assert_eq!(config.listen, "0.0.0.0");
+        assert_eq!(config.workers, 4);
+
+        create_config_file(
+            &path,
+            "config_version = 3\nlisten = \"${vars.host}\"\nthreads = 2\n\n[vars]\nhost = \"::\"\n",
+        );
+        let config: VersionedConfig = builder.load().unwrap();
+        assert_eq!(config.listen, "::");
+        assert_eq!(config.workers, 2);
+
+        create_config_file(&path, "config_version = 5\nlisten = \"::\"\nworkers = 2\n");
+        match builder.load::<VersionedConfig>() {
+            Err(Error::UnsupportedConfigVersion { version, latest }) => {
+                assert_eq!((version, latest), (5, 4));
+            }
+            other => panic!("expected an unsupported version, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_check_config_dir() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct CheckedConfig {
+            name: String,
+            port: u16,
+        }
+
+        impl TokenExpandingConfig for CheckedConfig {}
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct ManyFields {
+            a: String,
+            b: String,
+            p: u16,
+            q: u16,
+        }
+
+        impl TokenExpandingConfig for ManyFields {}
+
+        let dir = tempdir().unwrap();
+        let _lock = ENV_MUTEX.lock().unwrap();
+        env::remove_var("RUN_MODE");
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "name = \"app\"\nport = 80\n",
+        );
+        let report = check_config_dir::<CheckedConfig>(dir.path());
+        assert!(report.is_ok(), "{report:?}");
+        assert!(report.warnings.is_empty());
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "name = \"app\nport = 80\n",
+        );
+        create_config_file(&dir.path().join("local.toml"), "port = [80\n");
+        let report = check_config_dir::<CheckedConfig>(dir.path());
+        assert_eq!(report.errors.len(), 2, "{report:?}");
+        assert!(
+            report.errors.iter().all(|error| matches!(
+                error,
+                Error::ParseError { location, .. } if location.line.is_some()
+            )),
+            "{report:?}"
+        );
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "name = \"${vars.nmae}\"\nport = \"eighty\"\nprot = 80\n\n[vars]\nname = \"app\"\n",
+        );
+        create_config_file(&dir.path().join("local.toml"), "");
+        let report = check_config_dir::<CheckedConfig>(dir.path());
+        assert!(!report.is_ok());
+        assert!(
+            matches!(&report.errors[0], Error::UnresolvedToken { token, .. } if token.contains("vars.nmae")),
+            "{report:?}"
+        );
+        assert!(
+            matches!(
+                &report.errors[1],
+                Error::InvalidValue { path, location: Some(location), .. }
+                    if path == "port" && location.line == Some(2)
+            ),
+            "{report:?}"
+        );
+        assert_eq!(report.errors.len(), 2);
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "a = \"${x.one}\"\nb = \"${x.two}\"\np = \"eighty\"\nq = \"ninety\"\n",
+        );
+        let report = check_config_dir::<ManyFields>(dir.path());
+        let found: Vec<String> = report
+            .errors
+            .iter()
+            .map(|error| match error {
+                Error::UnresolvedToken { token, .. } => token.clone(),
+                Error::InvalidValue { path, .. } => path.clone(),
+                other => panic!("unexpected error {other:?}"),
+            })
+            .collect();
+        assert_eq!(found, ["${x.one}", "${x.two}", "p", "q"], "{report:?}");
+    }
+
+    #[test]
+    fn test_accumulate_errors() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct AccumulatedConfig {
+            name: String,
+            port: u16,
+            workers: u8,
+            url: String,
+        }
+
+        impl TokenExpandingConfig for AccumulatedConfig {}
+
+        let dir = tempdir().unwrap();
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "name = \"app\nport = 80\n",
+        );
+        create_config_file(&dir.path().join("local.toml"), "port = [80\n");
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .strict_tokens(true)
+            .accumulate_errors(true);
+        match builder.load::<AccumulatedConfig>() {
+            Err(Error::Multiple(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert!(
+                    errors
+                        .iter()
+                        .all(|error| matches!(error, Error::ParseError { .. })),
+                    "{errors:?}"
+                );
+            }
+            other => panic!("expected multiple errors, got {other:?}"),
+        }
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "name = \"${vars.nmae}\"\nport = \"eighty\"\nworkers = 300\nurl = \"${vars.host}\"\n",
+        );
+        create_config_file(&dir.path().join("local.toml"), "");
+        let error = builder.load::<AccumulatedConfig>().unwrap_err();
+        let Error::Multiple(errors) = &error else {
+            panic!("expected multiple errors, got {error:?}");
+        };
+        let paths: Vec<&str> = errors
+            .iter()
+            .map(|error| match error {
+                Error::UnresolvedToken { path, .. } | Error::InvalidValue { path, .. } => {
+                    path.as_str()
+                }
+                other => panic!("unexpected error {other:?}"),
+            })
+            .collect();
+        assert_eq!(paths, ["name", "url", "port", "workers"]);
+        assert!(
+            error.to_string().starts_with("4 config errors: "),
+            "{error}"
+        );
+
+        let builder = builder.accumulate_errors(false);
+        assert!(matches!(
+            builder.load::<AccumulatedConfig>(),
+            Err(Error::UnresolvedToken { path, .. }) if path == "name"
+        ));
+    }
+
+    #[test]
+    fn test_config_with_provenance() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct ProvenanceConfig {
+            host: String,
+            port: u16,
+            ports: Vec<u16>,
+            name: String,
         }
+
+        impl TokenExpandingConfig for ProvenanceConfig {}
+
+        let dir = tempdir().unwrap();
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "host = \"localhost\"\nport = 80\nports = [80, 443]\nname = \"app\"\n",
+        );
+        create_config_file(&dir.path().join("local.toml"), "port = 8080\n");
+
+        let (config, provenance): (ProvenanceConfig, _) = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .args(["--name=cli"])
+            .config_with_provenance()
+            .unwrap();
+        assert_eq!(config.port, 8080);
+
+        let config_dir = dir.path().canonicalize().unwrap();
+        let host = &provenance["host"];
+        assert_eq!(host.layer.as_deref(), Some("default"));
+        assert_eq!(host.file, Some(config_dir.join("default.toml")));
+        assert_eq!(provenance["port"].layer.as_deref(), Some("local"));
+        assert_eq!(provenance["ports[1]"].layer.as_deref(), Some("default"));
+        assert_eq!(
+            provenance["name"],
+            ValueSource {
+                provider: "command line arguments".to_string(),
+                file: None,
+                layer: None,
+            }
+        );
+        assert_eq!(
+            provenance["port"].to_string(),
+            format!("local layer ({})", config_dir.join("local.toml").display())
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let dir = tempdir().unwrap();
+        create_config_file(
+            &dir.path().join("default.toml"),
+            "port = 80\nhost = \"localhost\"\n",
+        );
+        create_config_file(
+            &dir.path().join("local.toml"),
+            "port = \"${vars.port}\"\n\n[vars]\nport = 8080\n",
+        );
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode();
+
+        let explanation = builder.explain("port").unwrap();
+        let config_dir = dir.path().canonicalize().unwrap();
+        assert_eq!(explanation.value, Some(Value::String("8080".to_string())));
+        assert_eq!(
+            explanation.source.as_ref().and_then(|s| s.layer.as_deref()),
+            Some("local")
+        );
+        assert_eq!(explanation.overridden.len(), 1);
+        assert_eq!(explanation.overridden[0].1, Value::from(80));
+        assert_eq!(
+            explanation.to_string(),
+            format!(
+                "port = \"8080\"\n  set by local layer ({})\n  overrides 80 from default layer ({})\n  expanded from \"${{vars.port}}\"",
+                config_dir.join("local.toml").display(),
+                config_dir.join("default.toml").display(),
+            )
+        );
+
+        let explanation = builder.explain("host").unwrap();
+        assert!(explanation.overridden.is_empty());
+        assert_eq!(explanation.unexpanded, None);
+        assert_eq!(
+            builder.explain("missing").unwrap().to_string(),
+            "missing is not set"
+        );
+    }
+
+    #[cfg(feature = "exec-tokens")]
+    #[test]
+    fn test_exec_tokens_require_opt_in() {
+        let dir = tempdir().unwrap();
+
+        create_config_file(
+            &dir.path().join("default.toml"),
+            r#"
+            test_value = "${exec:echo from a command}"
+        "#,
+        );
+
+        assert!(load_config_from_dir::<TestConfig>(dir.path()).is_err());
+
+        let options = ExpansionOptions::new().allow_exec(true);
+        let config: TestConfig = load_config_from_dir_with_options(dir.path(), &options).unwrap();
+        assert_eq!(config.test_value, Some("from a command".to_string()));
     }
 }
@@ -0,0 +1,263 @@
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::Duration,
+};
+
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider,
+};
+use serde_json::Value;
+
+use crate::Error;
+
+/// A boxed future, as returned by [`ConfigSource::load`].
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A source of configuration that is loaded asynchronously, such as a remote backend, added
+/// with [`GraftonConfigBuilder::source`](crate::GraftonConfigBuilder::source).
+///
+/// Sources are merged above the configuration files and providers, and below environment
+/// variables, in order of priority, so a source with a higher priority overrides one with a
+/// lower priority. Their values take part in token expansion like any other layer.
+///
+/// [`GraftonConfigBuilder::load`](crate::GraftonConfigBuilder::load) and reloads of a
+/// `SharedConfig` drive the futures of sources on the calling thread
+/// with a minimal executor that provides no async runtime. A source whose future needs a tokio
+/// reactor or timer, as tokio based database and HTTP clients do, then panics or never
+/// completes. Such sources should either be loaded with
+/// [`GraftonConfigBuilder::load_async`](crate::GraftonConfigBuilder::load_async) within the
+/// runtime, or hold a `tokio::runtime::Handle` and spawn their work onto it, awaiting the
+/// result. Clients with their own runtime, such as sqlx with async-std, need neither.
+///
+/// `load` returns a boxed future, so the trait can be implemented with an `async` block, or
+/// with the `async_trait` attribute:
+///
+/// ```
+/// use grafton_config::{BoxFuture, ConfigSource, Error};
+/// use serde_json::{json, Value};
+///
+/// struct Defaults;
+///
+/// impl ConfigSource for Defaults {
+///     fn name(&self) -> &str {
+///         "defaults"
+///     }
+///
+///     fn load(&self) -> BoxFuture<'_, Result<Value, Error>> {
+///         Box::pin(async { Ok(json!({ "website": { "port": 8080 } })) })
+///     }
+/// }
+/// ```
+pub trait ConfigSource: Send + Sync {
+    /// The name the source's values are attributed to.
+    fn name(&self) -> &str;
+
+    /// The source's priority among sources. Defaults to 0.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// How often the source should be refetched by
+    /// [`SharedConfig::refresh_periodically`](crate::SharedConfig::refresh_periodically), for
+    /// sources whose values change remotely. Defaults to `None`, for sources that are only
+    /// loaded when the configuration is.
+    fn refresh_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Loads the source's values, a table of configuration keys.
+    fn load(&self) -> BoxFuture<'_, Result<Value, Error>>;
+}
+
+/// The values loaded from a [`ConfigSource`], as a layer attributed to its name.
+#[derive(Clone)]
+pub struct SourceLayer {
+    pub name: String,
+    pub value: Value,
+}
+
+impl Provider for SourceLayer {
+    fn metadata(&self) -> Metadata {
+        Metadata::named(self.name.clone())
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        Serialized::defaults(&self.value).data()
+    }
+}
+
+/// Loads `sources` in order of priority, sources of equal priority in the order given.
+pub async fn load_sources(sources: &[Arc<dyn ConfigSource>]) -> Result<Vec<SourceLayer>, Error> {
+    let mut sources = sources.to_vec();
+    sources.sort_by_key(|source| source.priority());
+
+    let mut layers = Vec::with_capacity(sources.len());
+    for source in sources {
+        let value = source.load().await?;
+        if !value.is_object() {
+            return Err(Error::ConfigError(format!(
+                "configuration source {} did not return a table",
+                source.name()
+            )));
+        }
+        layers.push(SourceLayer {
+            name: source.name().to_string(),
+            value,
+        });
+    }
+    Ok(layers)
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion on the current thread, parking it while the future waits. No
+/// async runtime is entered, so futures that need one, such as tokio's, cannot be run.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{GraftonConfigBuilder, TokenExpandingConfig};
+
+    /// A source that waits on another thread before returning its values.
+    struct DelayedSource {
+        name: &'static str,
+        priority: i32,
+        value: Value,
+    }
+
+    impl ConfigSource for DelayedSource {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+
+        fn load(&self) -> BoxFuture<'_, Result<Value, Error>> {
+            Box::pin(async {
+                Delay(None).await;
+                Ok(self.value.clone())
+            })
+        }
+    }
+
+    /// A future that is pending until a thread it spawns wakes it.
+    struct Delay(Option<thread::JoinHandle<()>>);
+
+    impl Future for Delay {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+            if self.0.is_some() {
+                return Poll::Ready(());
+            }
+            let waker = context.waker().clone();
+            self.0 = Some(thread::spawn(move || waker.wake()));
+            Poll::Pending
+        }
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct SourceConfig {
+        name: String,
+        port: u16,
+        url: String,
+    }
+
+    impl TokenExpandingConfig for SourceConfig {}
+
+    fn builder(dir: &std::path::Path) -> GraftonConfigBuilder {
+        GraftonConfigBuilder::new()
+            .dir(dir)
+            .source(DelayedSource {
+                name: "high",
+                priority: 10,
+                value: json!({ "port": 9000 }),
+            })
+            .boxed_source(Box::new(DelayedSource {
+                name: "low",
+                priority: -1,
+                value: json!({ "name": "remote", "port": 8000 }),
+            }))
+    }
+
+    #[test]
+    fn test_config_sources() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\nport = 80\nurl = \"http://${name}:${port}\"\n",
+        )
+        .unwrap();
+
+        let config: SourceConfig = builder(dir.path()).load().unwrap();
+        assert_eq!(config.name, "remote");
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.url, "http://remote:9000");
+
+        let config: SourceConfig = block_on(builder(dir.path()).load_async()).unwrap();
+        assert_eq!(config.url, "http://remote:9000");
+    }
+
+    #[test]
+    fn test_load_sources() {
+        let sources: Vec<Arc<dyn ConfigSource>> = vec![
+            Arc::new(DelayedSource {
+                name: "b",
+                priority: 1,
+                value: json!({}),
+            }),
+            Arc::new(DelayedSource {
+                name: "a",
+                priority: 0,
+                value: json!({}),
+            }),
+            Arc::new(DelayedSource {
+                name: "c",
+                priority: 1,
+                value: json!({}),
+            }),
+        ];
+        let names: Vec<String> = block_on(load_sources(&sources))
+            .unwrap()
+            .into_iter()
+            .map(|layer| layer.name)
+            .collect();
+        assert_eq!(names, ["a", "b", "c"]);
+
+        let invalid: Vec<Arc<dyn ConfigSource>> = vec![Arc::new(DelayedSource {
+            name: "invalid",
+            priority: 0,
+            value: json!([1, 2]),
+        })];
+        assert!(block_on(load_sources(&invalid)).is_err());
+    }
+}
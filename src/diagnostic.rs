@@ -0,0 +1,183 @@
+use std::fmt::Display;
+
+use miette::{
+    Diagnostic, LabeledSpan, MietteError, MietteSpanContents, SourceCode, SourceSpan, SpanContents,
+};
+
+use crate::{Error, SourceLocation};
+
+/// Renders [`Error`]s with `miette`, pointing into the offending configuration file for parse
+/// errors and values that do not fit their fields, and listing each of
+/// [`Error::Multiple`] as a related diagnostic.
+impl Diagnostic for Error {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let code = match self {
+            Self::ParseError { .. } => "grafton_config::parse_error",
+            Self::InvalidValue { .. } => "grafton_config::invalid_value",
+            Self::UnresolvedToken { .. } => "grafton_config::unresolved_token",
+            Self::UnknownKeys(_) => "grafton_config::unknown_keys",
+            Self::MissingLayer { .. } => "grafton_config::missing_layer",
+            Self::ValidationFailed(_) => "grafton_config::validation_failed",
+            _ => return None,
+        };
+        Some(Box::new(code))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let help = match self {
+            Self::UnresolvedToken { .. } => {
+                "define the value the token refers to, or give the token a fallback after `:-`"
+                    .to_string()
+            }
+            Self::UnknownKeys(_) => {
+                "check the spelling of the keys, or remove them if they are no longer used"
+                    .to_string()
+            }
+            Self::MissingLayer { path, .. } => format!("create {path}"),
+            _ => return None,
+        };
+        Some(Box::new(help))
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.location()
+            .filter(|location| location.contents.is_some())
+            .map(|location| location as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let (Self::ParseError { message, .. } | Self::InvalidValue { message, .. }) = self else {
+            return None;
+        };
+        let span = self.location()?.span.clone()?;
+        Some(Box::new(std::iter::once(LabeledSpan::new_with_span(
+            Some(message.clone()),
+            span,
+        ))))
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        match self {
+            Self::Multiple(errors) => Some(Box::new(
+                errors.iter().map(|error| error as &dyn Diagnostic),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    fn location(&self) -> Option<&SourceLocation> {
+        match self {
+            Self::ParseError { location, .. }
+            | Self::InvalidValue {
+                location: Some(location),
+                ..
+            } => Some(location.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// The contents of the file, named by its path.
+impl SourceCode for SourceLocation {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let contents = self.contents.as_deref().ok_or(MietteError::OutOfBounds)?;
+        let inner = contents.read_span(span, context_lines_before, context_lines_after)?;
+        Ok(Box::new(MietteSpanContents::new_named(
+            self.file.display().to_string(),
+            inner.data(),
+            *inner.span(),
+            inner.line(),
+            inner.column(),
+            inner.line_count(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use miette::NarratableReportHandler;
+    use serde::Deserialize;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{GraftonConfigBuilder, TokenExpandingConfig, UnknownKey};
+
+    #[derive(Debug, serde::Serialize, Deserialize)]
+    struct DiagnosticConfig {
+        name: String,
+        port: u16,
+    }
+
+    impl TokenExpandingConfig for DiagnosticConfig {}
+
+    fn render(error: &Error) -> String {
+        let mut rendered = String::new();
+        NarratableReportHandler::new()
+            .render_report(&mut rendered, error)
+            .unwrap();
+        rendered
+    }
+
+    #[test]
+    fn test_labels_offending_values() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("default.toml");
+        fs::File::create(&file)
+            .unwrap()
+            .write_all(b"name = \"app\"\nport = \"eighty\"\nworkers = = 2\n")
+            .unwrap();
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode();
+
+        let error = builder.load::<DiagnosticConfig>().unwrap_err();
+        assert_eq!(
+            error.code().unwrap().to_string(),
+            "grafton_config::parse_error"
+        );
+        let label = error.labels().unwrap().next().unwrap();
+        assert_eq!(label.offset(), 39);
+        let rendered = render(&error);
+        assert!(rendered.contains("default.toml:3:11"), "{rendered}");
+        assert!(rendered.contains("workers = = 2"), "{rendered}");
+
+        fs::write(&file, "name = \"app\"\nport = \"eighty\"\n").unwrap();
+        let error = builder.load::<DiagnosticConfig>().unwrap_err();
+        let label = error.labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (20, 8));
+        let contents = error
+            .source_code()
+            .unwrap()
+            .read_span(label.inner(), 0, 0)
+            .unwrap();
+        assert_eq!(contents.data(), b"\"eighty\"");
+        assert!(contents.name().unwrap().ends_with("default.toml"));
+    }
+
+    #[test]
+    fn test_relates_multiple_errors() {
+        let error = Error::Multiple(vec![
+            Error::UnknownKeys(vec![UnknownKey {
+                path: "prot".to_string(),
+                suggestion: Some("port".to_string()),
+            }]),
+            Error::ConfigError("unreadable".to_string()),
+        ]);
+        assert_eq!(error.related().unwrap().count(), 2);
+        let rendered = render(&error);
+        assert!(
+            rendered.contains("Unknown config keys: prot (did you mean port?)"),
+            "{rendered}"
+        );
+        assert!(rendered.contains("check the spelling"), "{rendered}");
+    }
+}
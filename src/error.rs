@@ -1,15 +1,139 @@
-use {serde_json::Value, thiserror::Error};
+#[cfg(feature = "miette")]
+use std::sync::Arc;
+use {
+    serde_json::Value,
+    std::{ops::Range, path::PathBuf},
+    strum::Display,
+    thiserror::Error,
+};
+
+/// The token expansion limit that was exceeded, see [`Error::ExpansionLimitExceeded`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ExpansionLimit {
+    #[strum(serialize = "output size")]
+    OutputSize,
+    #[strum(serialize = "substitution count")]
+    Substitutions,
+}
+
+/// A problem found by [`ValidatableConfig::validate`](crate::ValidatableConfig::validate), see
+/// [`Error::ValidationFailed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Path of the offending value, e.g. `servers[0].port`.
+    pub path: String,
+    /// What is wrong with the value.
+    pub message: String,
+}
+
+impl ValidationError {
+    #[must_use]
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// A key that no field of the configuration struct consumed, see [`Error::UnknownKeys`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    /// Path of the key, e.g. `servers[0].prot`.
+    pub path: String,
+    /// Path of the field the key is probably a misspelling of, if any is close enough.
+    pub suggestion: Option<String>,
+}
+
+impl std::fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            self.path,
+            did_you_mean(self.suggestion.as_deref())
+        )
+    }
+}
+
+/// Where in a configuration file a problem was found, see [`Error::ParseError`] and
+/// [`Error::InvalidValue`].
+///
+/// Displayed as `file:line:column`, the form editors and terminals link to, or just the file
+/// when its format does not report positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: PathBuf,
+    /// The line, counting from 1.
+    pub line: Option<usize>,
+    /// The column, counting characters from 1.
+    pub column: Option<usize>,
+    /// The byte range of the problem within the file.
+    pub span: Option<Range<usize>>,
+    /// The contents of the file, to show the problem in context.
+    #[cfg(feature = "miette")]
+    pub(crate) contents: Option<Arc<str>>,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+            if let Some(column) = self.column {
+                write!(f, ":{column}")?;
+            }
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Invalid config: {0}")]
     ConfigError(String),
 
-    #[error("Error serializing config: {0}")]
-    SerializationError(String),
+    /// An error reported by figment while merging or extracting the configuration, such as a
+    /// provider that failed to produce its values.
+    #[error("{context}: {source}")]
+    FigmentError {
+        context: String,
+        /// Boxed, as it is several times the size of the other variants.
+        #[source]
+        source: Box<figment::Error>,
+    },
+
+    /// An error reported by a library the configuration is read or watched with, such as a
+    /// `.env` parser, database or Redis client, or file watcher.
+    #[error("{context}: {source}")]
+    ExternalError {
+        context: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
 
     #[error("Error deserializing config: {0}")]
-    DeserializationError(String),
+    DeserializationError(#[source] serde_json::Error),
+
+    #[error("Error parsing {location}: {message}")]
+    ParseError {
+        location: Box<SourceLocation>,
+        message: String,
+    },
+
+    #[error("Invalid value at {path}{}: {message}", located(.location.as_deref()))]
+    InvalidValue {
+        path: String,
+        message: String,
+        /// The file that set the value, and where in it, if it came from a file.
+        location: Option<Box<SourceLocation>>,
+    },
 
     #[error("Token recursion limit exceeded at depth {depth}. Current path: {path}, Current value: {value:?}")]
     TokenRecursionLimitExceeded {
@@ -17,4 +141,90 @@ pub enum Error {
         path: String,
         value: Value,
     },
+
+    #[error("Object key {key:?} at {path} is defined more than once after token expansion")]
+    DuplicateExpandedKey { path: String, key: String },
+
+    #[error("Error applying filter in token {token}: {message}")]
+    TokenFilterError { token: String, message: String },
+
+    #[error("Error evaluating expression in token {token}: {message}")]
+    TokenExpressionError { token: String, message: String },
+
+    #[error("Error resolving built-in token {token}: {message}")]
+    TokenBuiltinError { token: String, message: String },
+
+    #[error("Token expansion exceeded the {limit} limit of {max}")]
+    ExpansionLimitExceeded { limit: ExpansionLimit, max: usize },
+
+    #[error("Error rendering template at {path}: {message}")]
+    TemplateError { path: String, message: String },
+
+    #[error("Token {token} at {path} could not be resolved{}", did_you_mean(.suggestion.as_deref()))]
+    UnresolvedToken {
+        token: String,
+        path: String,
+        /// A token for a path that does exist, if one is close enough to be what was meant.
+        suggestion: Option<String>,
+    },
+
+    #[error("I/O error at {path}: {source}")]
+    IoError {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("No value found at path: {path}")]
+    PathNotFound { path: String },
+
+    #[error("Config failed validation: {}", list(.0))]
+    ValidationFailed(Vec<ValidationError>),
+
+    #[error("Unknown config keys: {}", list(.0))]
+    UnknownKeys(Vec<UnknownKey>),
+
+    #[error("Required config layer {layer} not found: {path}")]
+    MissingLayer { layer: String, path: String },
+
+    #[error("Config version {version} is newer than the latest supported version, {latest}")]
+    UnsupportedConfigVersion { version: u64, latest: u64 },
+
+    #[error("{} config errors: {}", .0.len(), list(.0))]
+    Multiple(Vec<Self>),
+}
+
+impl Error {
+    /// Combines the problems found in one load attempt, returning a single one as it is.
+    ///
+    /// # Panics
+    ///
+    /// If `errors` is empty.
+    pub(crate) fn from_many(mut errors: Vec<Self>) -> Self {
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            assert!(!errors.is_empty(), "no errors to combine");
+            Self::Multiple(errors)
+        }
+    }
+}
+
+fn located(location: Option<&SourceLocation>) -> String {
+    location.map_or_else(String::new, |location| format!(" ({location})"))
+}
+
+/// Formats a suggestion for what was meant, as appended to messages about misspellings.
+pub fn did_you_mean(suggestion: Option<&str>) -> String {
+    suggestion.map_or_else(String::new, |suggestion| {
+        format!(" (did you mean {suggestion}?)")
+    })
+}
+
+fn list<T: std::fmt::Display>(errors: &[T]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
 }
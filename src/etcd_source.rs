@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use serde_json::{json, Value};
+
+use crate::config_loader::insert_nested;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A configuration layer read from the keys under a prefix in etcd v3, merged with
+/// [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// The prefix is removed from each key and the rest split on `/` into a path, so with a prefix
+/// of `/myapp/`, the key `/myapp/website/port` sets `website.port`. Values are strings, which
+/// are coerced into typed fields when the configuration is deserialized.
+///
+/// etcd is queried through its JSON gateway, e.g. `http://127.0.0.1:2379`.
+pub struct EtcdSource {
+    endpoint: String,
+    prefix: String,
+    credentials: Option<(String, String)>,
+    timeout: Duration,
+}
+
+impl EtcdSource {
+    #[must_use]
+    pub fn new(endpoint: impl Into<String>, prefix: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            prefix: prefix.into(),
+            credentials: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Authenticates as `user` before reading, for clusters with authentication enabled.
+    #[must_use]
+    pub fn credentials(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((user.into(), password.into()));
+        self
+    }
+
+    /// Limits how long each request to etcd may take. Defaults to 30 seconds.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Posts a JSON request to the gateway, returning the JSON response.
+    fn post(&self, path: &str, body: &Value, token: Option<&str>) -> Result<Value, String> {
+        let url = format!("{}{path}", self.endpoint);
+        let mut request = ureq::post(&url)
+            .timeout(self.timeout)
+            .set("Content-Type", "application/json");
+        if let Some(token) = token {
+            request = request.set("Authorization", token);
+        }
+
+        request
+            .send_string(&body.to_string())
+            .map_err(|e| format!("etcd request to {url} failed: {e}"))?
+            .into_string()
+            .map_err(|e| format!("unable to read etcd response from {url}: {e}"))
+            .and_then(|body| {
+                serde_json::from_str(&body)
+                    .map_err(|e| format!("invalid etcd response from {url}: {e}"))
+            })
+    }
+
+    fn authenticate(&self) -> Result<Option<String>, String> {
+        let Some((user, password)) = &self.credentials else {
+            return Ok(None);
+        };
+
+        let response = self.post(
+            "/v3/auth/authenticate",
+            &json!({ "name": user, "password": password }),
+            None,
+        )?;
+        response["token"]
+            .as_str()
+            .map(|token| Some(token.to_string()))
+            .ok_or_else(|| "etcd authentication returned no token".to_string())
+    }
+
+    /// Reads the keys under the prefix into a table.
+    fn read(&self) -> Result<Value, String> {
+        let token = self.authenticate()?;
+        let response = self.post(
+            "/v3/kv/range",
+            &json!({
+                "key": STANDARD.encode(&self.prefix),
+                "range_end": STANDARD.encode(prefix_end(self.prefix.as_bytes())),
+            }),
+            token.as_deref(),
+        )?;
+
+        let mut root = serde_json::Map::new();
+        for kv in response["kvs"].as_array().into_iter().flatten() {
+            let key = decode(&kv["key"])?;
+            let value = decode(&kv["value"])?;
+            let segments: Vec<String> = key
+                .strip_prefix(&self.prefix)
+                .unwrap_or(&key)
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(ToString::to_string)
+                .collect();
+            insert_nested(&mut root, &segments, Value::String(value));
+        }
+        Ok(Value::Object(root))
+    }
+}
+
+impl Provider for EtcdSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("etcd").source(Source::Custom(format!("{}{}", self.endpoint, self.prefix)))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        Serialized::defaults(self.read()?).data()
+    }
+}
+
+/// The end of the key range covering every key that starts with `prefix`: the prefix with its
+/// last byte that can be incremented, incremented. An empty or all `0xff` prefix covers all keys.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    vec![0]
+}
+
+fn decode(value: &Value) -> Result<String, String> {
+    let encoded = value.as_str().unwrap_or_default();
+    STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("invalid base64 in etcd response: {e}"))
+        .and_then(|bytes| {
+            String::from_utf8(bytes).map_err(|e| format!("invalid UTF-8 in etcd value: {e}"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        test_server::{ok, serve, status},
+        GraftonConfigBuilder, TokenExpandingConfig,
+    };
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"/app/"), b"/app0".to_vec());
+        assert_eq!(prefix_end(b"a\xff"), b"b".to_vec());
+        assert_eq!(prefix_end(b"\xff\xff"), vec![0]);
+        assert_eq!(prefix_end(b""), vec![0]);
+    }
+
+    #[test]
+    fn test_etcd_source() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Website {
+            host: String,
+            port: u16,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct EtcdConfig {
+            name: String,
+            website: Website,
+        }
+
+        impl TokenExpandingConfig for EtcdConfig {}
+
+        let kv = |key: &str, value: &str| json!({ "key": STANDARD.encode(key), "value": STANDARD.encode(value) });
+        let range = json!({
+            "kvs": [kv("/app/website/port", "8080"), kv("/app/name", "${website.host}")],
+        });
+        let (url, server) = serve(vec![
+            ok("application/json", r#"{ "token": "abc" }"#),
+            ok("application/json", &range.to_string()),
+        ]);
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\n\n[website]\nhost = \"localhost\"\nport = 80\n",
+        )
+        .unwrap();
+
+        let config: EtcdConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(EtcdSource::new(url, "/app/").credentials("root", "pass"))
+            .load()
+            .unwrap();
+
+        assert_eq!(config.website.port, 8080);
+        assert_eq!(config.name, "localhost");
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].line, "POST /v3/auth/authenticate HTTP/1.1");
+        assert_eq!(requests[1].line, "POST /v3/kv/range HTTP/1.1");
+        assert!(requests[1].has_header("Authorization: abc"));
+        let body: Value = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(body["key"], STANDARD.encode("/app/"));
+        assert_eq!(body["range_end"], STANDARD.encode("/app0"));
+    }
+
+    #[test]
+    fn test_etcd_authentication_failure() {
+        let (url, server) = serve(vec![status("401 Unauthorized")]);
+
+        let result = EtcdSource::new(url, "/app/")
+            .credentials("root", "wrong")
+            .read();
+
+        assert!(result.unwrap_err().contains("etcd request"));
+        server.join().unwrap();
+    }
+}
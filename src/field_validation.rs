@@ -0,0 +1,105 @@
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::{
+    token_expander::{format_new_array_path, format_new_path},
+    Error, ValidationError,
+};
+
+/// Key under which `validator` reports the errors of struct-level checks.
+const STRUCT_ERRORS: &str = "__all__";
+
+/// Flattens the errors reported by [`validator::Validate::validate`] into
+/// [`ValidationError`]s, in order of their paths.
+///
+/// Nested fields are addressed with the same dotted paths as other errors, e.g.
+/// `servers[0].port`.
+#[must_use]
+pub fn validation_errors(errors: &ValidationErrors) -> Vec<ValidationError> {
+    let mut flattened = Vec::new();
+    flatten("", errors, &mut flattened);
+    flattened.sort_by(|a, b| a.path.cmp(&b.path));
+    flattened
+}
+
+fn flatten(path: &str, errors: &ValidationErrors, flattened: &mut Vec<ValidationError>) {
+    for (field, kind) in errors.errors() {
+        let path = if field == STRUCT_ERRORS {
+            path.to_string()
+        } else {
+            format_new_path(path, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                flattened.extend(errors.iter().map(|error| {
+                    let message = error.message.as_ref().map_or_else(
+                        || format!("failed {} check", error.code),
+                        ToString::to_string,
+                    );
+                    ValidationError::new(path.clone(), message)
+                }));
+            }
+            ValidationErrorsKind::Struct(errors) => flatten(&path, errors, flattened),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    flatten(&format_new_array_path(&path, *index), errors, flattened);
+                }
+            }
+        }
+    }
+}
+
+impl From<ValidationErrors> for Error {
+    fn from(errors: ValidationErrors) -> Self {
+        Self::ValidationFailed(validation_errors(&errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use validator::Validate;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, Validate)]
+    struct Server {
+        #[validate(range(min = 1024, message = "must be unprivileged"))]
+        port: u16,
+        #[validate(email)]
+        admin: String,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, Validate)]
+    struct Cluster {
+        #[validate(length(min = 1))]
+        name: String,
+        #[validate(nested)]
+        servers: Vec<Server>,
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let cluster = Cluster {
+            name: String::new(),
+            servers: vec![
+                Server {
+                    port: 8080,
+                    admin: "ops@example.com".to_string(),
+                },
+                Server {
+                    port: 80,
+                    admin: "ops".to_string(),
+                },
+            ],
+        };
+        let errors = validation_errors(&cluster.validate().unwrap_err());
+        assert_eq!(
+            errors,
+            [
+                ValidationError::new("name", "failed length check"),
+                ValidationError::new("servers[1].admin", "failed email check"),
+                ValidationError::new("servers[1].port", "must be unprivileged"),
+            ]
+        );
+    }
+}
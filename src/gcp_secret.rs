@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use serde_json::Value;
+
+use crate::{config_loader::insert_nested, TokenResolver};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const SECRET_MANAGER_ENDPOINT: &str = "https://secretmanager.googleapis.com";
+const METADATA_ENDPOINT: &str = "http://metadata.google.internal";
+
+/// A client for secrets held in Google Cloud Secret Manager.
+///
+/// Secrets are addressed by the full name of a version, e.g.
+/// `projects/my-project/secrets/db-password/versions/latest`. The client resolves
+/// `${gcp-secret:<version>}` tokens when registered with
+/// [`GraftonConfigBuilder::resolver`](crate::GraftonConfigBuilder::resolver), and maps a set of
+/// secrets into the configuration with [`GcpSecretManager::source`].
+///
+/// Each secret is fetched once and cached, shared by clones.
+#[derive(Clone)]
+pub struct GcpSecretManager {
+    endpoint: String,
+    auth: GcpAuth,
+    timeout: Duration,
+    token: Arc<Mutex<Option<(String, Instant)>>>,
+    cache: Arc<Mutex<HashMap<String, String>>>,
+}
+
+#[derive(Clone)]
+enum GcpAuth {
+    Token(String),
+    MetadataServer(String),
+}
+
+impl GcpSecretManager {
+    /// Creates a client that authenticates with an OAuth 2.0 access token, e.g. from
+    /// `gcloud auth print-access-token`.
+    #[must_use]
+    pub fn with_token(token: impl Into<String>) -> Self {
+        Self::new(GcpAuth::Token(token.into()))
+    }
+
+    /// Creates a client that obtains access tokens for the attached service account from the
+    /// metadata server, as on Compute Engine, GKE and Cloud Run.
+    #[must_use]
+    pub fn from_metadata_server() -> Self {
+        Self::new(GcpAuth::MetadataServer(METADATA_ENDPOINT.to_string()))
+    }
+
+    fn new(auth: GcpAuth) -> Self {
+        Self {
+            endpoint: SECRET_MANAGER_ENDPOINT.to_string(),
+            auth,
+            timeout: DEFAULT_TIMEOUT,
+            token: Arc::new(Mutex::new(None)),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sends requests to `endpoint` rather than `https://secretmanager.googleapis.com`, e.g. for
+    /// a regional endpoint.
+    #[must_use]
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Limits how long each request may take. Defaults to 30 seconds.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// A configuration layer holding secrets placed under the dotted `config_path`, or at the
+    /// top level when it is empty, added with [`GcpSecretSource::secret`].
+    #[must_use]
+    pub fn source(&self, config_path: &str) -> GcpSecretSource {
+        GcpSecretSource {
+            client: self.clone(),
+            config_path: split_path(config_path),
+            secrets: Vec::new(),
+        }
+    }
+
+    /// Forgets every secret fetched, so they are fetched again when next resolved.
+    pub fn clear_cache(&self) {
+        self.cache().clear();
+    }
+
+    /// Reads the payload of the secret version `name`.
+    fn access(&self, name: &str) -> Result<String, String> {
+        let cached = self.cache().get(name).cloned();
+        if let Some(payload) = cached {
+            return Ok(payload);
+        }
+
+        let token = self.token()?;
+        let response = self
+            .get(
+                &format!("{}/v1/{name}:access", self.endpoint),
+                ("Authorization", &format!("Bearer {token}")),
+            )
+            .map_err(|e| format!("unable to access GCP secret {name}: {e}"))?;
+
+        let payload = response["payload"]["data"]
+            .as_str()
+            .and_then(|data| STANDARD.decode(data).ok())
+            .and_then(|data| String::from_utf8(data).ok())
+            .ok_or_else(|| format!("GCP secret {name} has no text payload"))?;
+        self.cache().insert(name.to_string(), payload.clone());
+        Ok(payload)
+    }
+
+    fn token(&self) -> Result<String, String> {
+        let metadata_endpoint = match &self.auth {
+            GcpAuth::Token(token) => return Ok(token.clone()),
+            GcpAuth::MetadataServer(endpoint) => endpoint,
+        };
+
+        let cached = self.cached_token().clone();
+        if let Some((token, expires)) = cached {
+            if Instant::now() < expires {
+                return Ok(token);
+            }
+        }
+
+        let url = format!(
+            "{metadata_endpoint}/computeMetadata/v1/instance/service-accounts/default/token"
+        );
+        let response = self
+            .get(&url, ("Metadata-Flavor", "Google"))
+            .map_err(|e| format!("unable to get an access token from the metadata server: {e}"))?;
+        let token = response["access_token"]
+            .as_str()
+            .ok_or("the metadata server returned no access token")?
+            .to_string();
+        // Renew the token a minute before it expires.
+        let lifetime = response["expires_in"].as_u64().unwrap_or_default();
+        let expires = Instant::now() + Duration::from_secs(lifetime.saturating_sub(60));
+        *self.cached_token() = Some((token.clone(), expires));
+        Ok(token)
+    }
+
+    fn get(&self, url: &str, (name, value): (&str, &str)) -> Result<Value, String> {
+        let response = ureq::get(url)
+            .timeout(self.timeout)
+            .set(name, value)
+            .call()
+            .map_err(|e| match e {
+                ureq::Error::Status(403, _) => "permission denied".to_string(),
+                e => e.to_string(),
+            })?;
+        response
+            .into_string()
+            .map_err(|e| e.to_string())
+            .and_then(|body| serde_json::from_str(&body).map_err(|e| e.to_string()))
+    }
+
+    fn cached_token(&self) -> MutexGuard<'_, Option<(String, Instant)>> {
+        self.token.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<String, String>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl TokenResolver for GcpSecretManager {
+    /// Resolves `projects/p/secrets/s/versions/latest` to the payload of that secret version,
+    /// or, with `#key` appended, to the `key` of a payload holding a JSON object. A key that
+    /// the payload does not have leaves the token unresolved.
+    fn resolve(&self, key: &str) -> Result<Option<Value>, String> {
+        let (name, field) = match key.split_once('#') {
+            Some((name, field)) => (name, Some(field)),
+            None => (key, None),
+        };
+        let payload = self.access(name)?;
+
+        let Some(field) = field else {
+            return Ok(Some(Value::String(payload)));
+        };
+        match serde_json::from_str::<Value>(&payload) {
+            Ok(Value::Object(mut fields)) => Ok(fields.remove(field)),
+            _ => Err(format!("GCP secret {name} is not a JSON object")),
+        }
+    }
+}
+
+/// A configuration layer holding a set of Google Cloud secrets, see
+/// [`GcpSecretManager::source`].
+pub struct GcpSecretSource {
+    client: GcpSecretManager,
+    config_path: Vec<String>,
+    secrets: Vec<(Vec<String>, String)>,
+}
+
+impl GcpSecretSource {
+    /// Sets the dotted `key`, beneath the source's path, to the payload of the secret version
+    /// `name`.
+    #[must_use]
+    pub fn secret(mut self, key: &str, name: impl Into<String>) -> Self {
+        self.secrets.push((split_path(key), name.into()));
+        self
+    }
+}
+
+impl Provider for GcpSecretSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("GCP Secret Manager").source(Source::Custom(self.client.endpoint.clone()))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        let mut root = serde_json::Map::new();
+        for (key, name) in &self.secrets {
+            let payload = self.client.access(name)?;
+            let path: Vec<String> = self.config_path.iter().chain(key).cloned().collect();
+            insert_nested(&mut root, &path, Value::String(payload));
+        }
+        Serialized::defaults(Value::Object(root)).data()
+    }
+}
+
+fn split_path(path: &str) -> Vec<String> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        test_server::{ok, serve, status},
+        GraftonConfigBuilder, TokenExpandingConfig,
+    };
+
+    fn secret(payload: &str) -> String {
+        ok(
+            "application/json",
+            &json!({
+                "name": "projects/p/secrets/s/versions/1",
+                "payload": { "data": STANDARD.encode(payload) },
+            })
+            .to_string(),
+        )
+    }
+
+    #[test]
+    fn test_gcp_secrets() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Database {
+            user: String,
+            password: String,
+            url: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct GcpConfig {
+            database: Database,
+        }
+
+        impl TokenExpandingConfig for GcpConfig {}
+
+        let (url, server) = serve(vec![
+            ok(
+                "application/json",
+                r#"{ "access_token": "ya29.token", "expires_in": 3599 }"#,
+            ),
+            secret("hunter2"),
+            secret(r#"{ "host": "db.internal" }"#),
+        ]);
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            r#"
+            [database]
+            user = "app"
+            password = ""
+            url = "postgres://${database.user}@${gcp-secret:projects/p/secrets/db/versions/latest#host}"
+            "#,
+        )
+        .unwrap();
+
+        let mut gcp = GcpSecretManager::from_metadata_server().endpoint(url.clone());
+        gcp.auth = GcpAuth::MetadataServer(url);
+        let config: GcpConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(
+                gcp.source("database")
+                    .secret("password", "projects/p/secrets/db-password/versions/3"),
+            )
+            .resolver("gcp-secret", gcp)
+            .load()
+            .unwrap();
+
+        assert_eq!(config.database.password, "hunter2");
+        assert_eq!(config.database.url, "postgres://app@db.internal");
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[0].line,
+            "GET /computeMetadata/v1/instance/service-accounts/default/token HTTP/1.1"
+        );
+        assert!(requests[0].has_header("Metadata-Flavor: Google"));
+        assert_eq!(
+            requests[1].line,
+            "GET /v1/projects/p/secrets/db-password/versions/3:access HTTP/1.1"
+        );
+        assert!(requests[1].has_header("Authorization: Bearer ya29.token"));
+        assert!(requests[1].body.is_empty());
+        assert!(requests[2].has_header("Authorization: Bearer ya29.token"));
+    }
+
+    #[test]
+    fn test_gcp_secret_errors() {
+        let (url, server) = serve(vec![status("403 Forbidden"), secret("plain")]);
+
+        let gcp = GcpSecretManager::with_token("token").endpoint(url);
+        assert!(gcp
+            .resolve("projects/p/secrets/a/versions/1")
+            .unwrap_err()
+            .contains("permission denied"));
+        assert!(gcp
+            .resolve("projects/p/secrets/b/versions/1#key")
+            .unwrap_err()
+            .contains("not a JSON object"));
+
+        server.join().unwrap();
+    }
+}
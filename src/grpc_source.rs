@@ -0,0 +1,525 @@
+use std::{
+    env,
+    future::Future,
+    sync::{mpsc, Arc},
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+use figment::{
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use tonic::{
+    codec::ProstCodec,
+    codegen::{empty_body, http, tokio_stream::Stream, Body, BoxFuture, Service, StdError},
+    server::{Grpc, NamedService, ServerStreamingService, UnaryService},
+    transport::{Channel, Endpoint},
+    Code, Request, Response, Status, Streaming,
+};
+
+use crate::{config_loader::load_config_from_string, ChangeWatcher, Error};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SERVICE_NAME: &str = "grafton.config.v1.ConfigService";
+const GET_CONFIG_PATH: &str = "/grafton.config.v1.ConfigService/GetConfig";
+const WATCH_CONFIG_PATH: &str = "/grafton.config.v1.ConfigService/WatchConfig";
+
+/// A request for an application's configuration, as defined in `proto/grafton_config.proto`.
+#[derive(Clone, PartialEq, Eq, prost::Message)]
+pub struct GetConfigRequest {
+    /// The application whose configuration is requested.
+    #[prost(string, tag = "1")]
+    pub application: String,
+    /// The run modes of the application, from most general to most specific.
+    #[prost(string, repeated, tag = "2")]
+    pub profiles: Vec<String>,
+}
+
+/// A configuration document, as defined in `proto/grafton_config.proto`.
+#[derive(Clone, PartialEq, Eq, prost::Message)]
+pub struct ConfigDocument {
+    /// The format of `content`, such as `toml`, `yaml` or `json`.
+    #[prost(string, tag = "1")]
+    pub format: String,
+    /// The configuration document.
+    #[prost(string, tag = "2")]
+    pub content: String,
+    /// An identifier of this version of the document.
+    #[prost(string, tag = "3")]
+    pub version: String,
+}
+
+/// A configuration layer fetched from a gRPC configuration service, merged with
+/// [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// The service implements the `ConfigService` protocol in `proto/grafton_config.proto`, and
+/// returns a document in any format this crate reads for the application and its profiles,
+/// which are the run modes in `RUN_MODE` unless given to [`profile`](GrpcSource::profile).
+/// [`watch`](GrpcSource::watch) streams the service's updates, so the configuration can be
+/// reloaded when a new version is published.
+///
+/// Requests run on a Tokio runtime of their own, so the source can be loaded from synchronous
+/// code and from within another runtime alike.
+#[derive(Clone)]
+pub struct GrpcSource {
+    endpoint: String,
+    application: String,
+    profiles: Option<Vec<String>>,
+    timeout: Duration,
+}
+
+impl GrpcSource {
+    /// Creates a source for `application` from the service at `endpoint`, e.g.
+    /// `http://config:50051`.
+    #[must_use]
+    pub fn new(endpoint: impl Into<String>, application: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            application: application.into(),
+            profiles: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Requests `profile` rather than the run modes. Later profiles take precedence over
+    /// earlier ones.
+    #[must_use]
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.profiles
+            .get_or_insert_with(Vec::new)
+            .push(profile.into());
+        self
+    }
+
+    /// Limits how long connecting and each request may take. Defaults to 30 seconds.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Streams updates from the service, returning a [`ChangeWatcher`] that reports each new
+    /// version of the document.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ConfigError`] if the service cannot be reached or refuses the request.
+    pub fn watch(&self) -> Result<ChangeWatcher, Error> {
+        let (sender, watcher) = ChangeWatcher::channel("gRPC configuration updates");
+        let (started, result) = mpsc::channel();
+        let source = self.clone();
+        thread::spawn(move || {
+            let runtime = match runtime() {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    let _ = started.send(Err(e));
+                    return;
+                }
+            };
+            runtime.block_on(async {
+                let mut stream = match source.subscribe().await {
+                    Ok(stream) => {
+                        let _ = started.send(Ok(()));
+                        stream
+                    }
+                    Err(e) => {
+                        let _ = started.send(Err(e));
+                        return;
+                    }
+                };
+                loop {
+                    let update = match stream.message().await {
+                        Ok(Some(_)) => Ok(()),
+                        Ok(None) => Err("the service ended the stream".to_string()),
+                        Err(status) => Err(status.to_string()),
+                    };
+                    let failed = update.is_err();
+                    if sender.send(update).is_err() || failed {
+                        return;
+                    }
+                }
+            });
+        });
+
+        result
+            .recv()
+            .unwrap_or_else(|_| Err("the watch thread stopped".to_string()))
+            .map_err(|e| {
+                Error::ConfigError(format!(
+                    "unable to watch {} configuration at {}: {e}",
+                    self.application, self.endpoint
+                ))
+            })?;
+        Ok(watcher)
+    }
+
+    fn request(&self) -> Request<GetConfigRequest> {
+        let profiles = self.profiles.clone().unwrap_or_else(|| {
+            env::var("RUN_MODE")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|mode| !mode.is_empty())
+                .map(ToString::to_string)
+                .collect()
+        });
+        Request::new(GetConfigRequest {
+            application: self.application.clone(),
+            profiles,
+        })
+    }
+
+    async fn client(&self) -> Result<tonic::client::Grpc<Channel>, String> {
+        let channel = Endpoint::from_shared(self.endpoint.clone())
+            .map_err(|e| format!("invalid endpoint: {e}"))?
+            .connect_timeout(self.timeout)
+            .timeout(self.timeout)
+            .connect()
+            .await
+            .map_err(|e| format!("unable to connect: {e}"))?;
+        let mut client = tonic::client::Grpc::new(channel);
+        client
+            .ready()
+            .await
+            .map_err(|e| format!("service not ready: {e}"))?;
+        Ok(client)
+    }
+
+    /// Fetches the current document.
+    async fn get(&self) -> Result<ConfigDocument, String> {
+        let mut client = self.client().await?;
+        let response = client
+            .unary(
+                self.request(),
+                http::uri::PathAndQuery::from_static(GET_CONFIG_PATH),
+                ProstCodec::default(),
+            )
+            .await;
+        drop(client);
+        response
+            .map(Response::into_inner)
+            .map_err(|status| status.to_string())
+    }
+
+    /// Opens the stream of documents, skipping the first, which is the current one and was
+    /// loaded already.
+    async fn subscribe(&self) -> Result<Streaming<ConfigDocument>, String> {
+        let mut client = self.client().await?;
+        let response = client
+            .server_streaming(
+                self.request(),
+                http::uri::PathAndQuery::from_static(WATCH_CONFIG_PATH),
+                ProstCodec::default(),
+            )
+            .await;
+        drop(client);
+        let mut stream = response.map_err(|status| status.to_string())?.into_inner();
+        stream
+            .message()
+            .await
+            .map_err(|status| status.to_string())?;
+        Ok(stream)
+    }
+
+    /// Fetches the current document on a runtime of its own.
+    fn fetch(&self) -> Result<ConfigDocument, String> {
+        let source = self.clone();
+        thread::spawn(move || runtime()?.block_on(source.get()))
+            .join()
+            .unwrap_or_else(|_| Err("the request thread panicked".to_string()))
+    }
+}
+
+fn runtime() -> Result<tokio::runtime::Runtime, String> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("unable to start a Tokio runtime: {e}"))
+}
+
+impl Provider for GrpcSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("gRPC").source(Source::Custom(format!(
+            "{}/{}",
+            self.endpoint, self.application
+        )))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        let document = self.fetch().map_err(|e| {
+            format!(
+                "unable to fetch {} configuration from {}: {e}",
+                self.application, self.endpoint
+            )
+        })?;
+        load_config_from_string(&format!("grpc.{}", document.format), &document.content)
+            .map_err(|e| format!("unable to parse {} configuration: {e}", self.application))?
+            .data()
+    }
+}
+
+/// The server side of the `ConfigService` gRPC protocol, for configuration services written in
+/// Rust. Serve it with [`ConfigServiceServer`].
+#[tonic::async_trait]
+pub trait ConfigService: Send + Sync + 'static {
+    /// The stream of documents returned by [`watch_config`](ConfigService::watch_config).
+    type WatchConfigStream: Stream<Item = Result<ConfigDocument, Status>> + Send + 'static;
+
+    /// Returns the current configuration document for an application.
+    async fn get_config(
+        &self,
+        request: Request<GetConfigRequest>,
+    ) -> Result<Response<ConfigDocument>, Status>;
+
+    /// Streams the current configuration document for an application, then each new version
+    /// of it as it changes.
+    async fn watch_config(
+        &self,
+        request: Request<GetConfigRequest>,
+    ) -> Result<Response<Self::WatchConfigStream>, Status>;
+}
+
+/// A [`ConfigService`] as a service that a `tonic` server can route requests to.
+pub struct ConfigServiceServer<T> {
+    inner: Arc<T>,
+}
+
+impl<T> ConfigServiceServer<T> {
+    pub fn new(service: T) -> Self {
+        Self {
+            inner: Arc::new(service),
+        }
+    }
+}
+
+impl<T> Clone for ConfigServiceServer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<T> NamedService for ConfigServiceServer<T> {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+struct GetConfigMethod<T>(Arc<T>);
+
+impl<T: ConfigService> UnaryService<GetConfigRequest> for GetConfigMethod<T> {
+    type Response = ConfigDocument;
+    type Future = BoxFuture<Response<ConfigDocument>, Status>;
+
+    fn call(&mut self, request: Request<GetConfigRequest>) -> Self::Future {
+        let service = Arc::clone(&self.0);
+        Box::pin(async move { service.get_config(request).await })
+    }
+}
+
+struct WatchConfigMethod<T>(Arc<T>);
+
+impl<T: ConfigService> ServerStreamingService<GetConfigRequest> for WatchConfigMethod<T> {
+    type Response = ConfigDocument;
+    type ResponseStream = T::WatchConfigStream;
+    type Future = BoxFuture<Response<T::WatchConfigStream>, Status>;
+
+    fn call(&mut self, request: Request<GetConfigRequest>) -> Self::Future {
+        let service = Arc::clone(&self.0);
+        Box::pin(async move { service.watch_config(request).await })
+    }
+}
+
+impl<T, B> Service<http::Request<B>> for ConfigServiceServer<T>
+where
+    T: ConfigService,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let service = Arc::clone(&self.inner);
+        match request.uri().path() {
+            GET_CONFIG_PATH => Box::pin(async move {
+                let mut grpc = Grpc::new(ProstCodec::default());
+                Ok(grpc.unary(GetConfigMethod(service), request).await)
+            }),
+            WATCH_CONFIG_PATH => Box::pin(async move {
+                let mut grpc = Grpc::new(ProstCodec::default());
+                Ok(grpc
+                    .server_streaming(WatchConfigMethod(service), request)
+                    .await)
+            }),
+            _ => Box::pin(unimplemented()),
+        }
+    }
+}
+
+fn unimplemented(
+) -> impl Future<Output = Result<http::Response<tonic::body::BoxBody>, std::convert::Infallible>> {
+    let mut response = http::Response::new(empty_body());
+    let headers = response.headers_mut();
+    headers.insert("grpc-status", (Code::Unimplemented as i32).into());
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("application/grpc"),
+    );
+    std::future::ready(Ok(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::Pin, sync::Mutex};
+
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+    use tonic::{codegen::tokio_stream::wrappers::TcpListenerStream, transport::Server};
+
+    use super::*;
+    use crate::{GraftonConfigBuilder, TokenExpandingConfig};
+
+    type DocumentStream = Pin<Box<dyn Stream<Item = Result<ConfigDocument, Status>> + Send>>;
+
+    type Watchers = Arc<Mutex<Vec<UnboundedSender<Result<ConfigDocument, Status>>>>>;
+
+    /// A service returning `document` to `myapp` in `prod`, keeping the sender of each watch
+    /// stream so the test can publish updates.
+    struct TestService {
+        document: ConfigDocument,
+        watchers: Watchers,
+    }
+
+    #[tonic::async_trait]
+    impl ConfigService for TestService {
+        type WatchConfigStream = DocumentStream;
+
+        async fn get_config(
+            &self,
+            request: Request<GetConfigRequest>,
+        ) -> Result<Response<ConfigDocument>, Status> {
+            let request = request.into_inner();
+            if request.application != "myapp" {
+                return Err(Status::not_found(request.application));
+            }
+            if request.profiles != ["prod"] {
+                return Err(Status::invalid_argument(request.profiles.join(",")));
+            }
+            Ok(Response::new(self.document.clone()))
+        }
+
+        async fn watch_config(
+            &self,
+            _request: Request<GetConfigRequest>,
+        ) -> Result<Response<DocumentStream>, Status> {
+            let (sender, mut receiver) = unbounded_channel();
+            sender.send(Ok(self.document.clone())).unwrap();
+            self.watchers.lock().unwrap().push(sender);
+            let stream = futures_stream(move |context| receiver.poll_recv(context));
+            Ok(Response::new(Box::pin(stream)))
+        }
+    }
+
+    /// A stream polling `poll` for its items.
+    fn futures_stream<T, F>(poll: F) -> impl Stream<Item = T> + Send
+    where
+        F: FnMut(&mut Context<'_>) -> Poll<Option<T>> + Send + Unpin,
+    {
+        struct PollStream<F>(F);
+
+        impl<T, F: FnMut(&mut Context<'_>) -> Poll<Option<T>> + Unpin> Stream for PollStream<F> {
+            type Item = T;
+
+            fn poll_next(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<T>> {
+                (self.0)(context)
+            }
+        }
+
+        PollStream(poll)
+    }
+
+    /// Serves `service` on a local port, returning its URL.
+    fn serve(service: TestService) -> String {
+        let (address, listener) = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            (listener.local_addr().unwrap(), listener)
+        };
+        thread::spawn(move || {
+            runtime().unwrap().block_on(async {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                Server::builder()
+                    .add_service(ConfigServiceServer::new(service))
+                    .serve_with_incoming(TcpListenerStream::new(listener))
+                    .await
+                    .unwrap();
+            });
+        });
+        format!("http://{address}")
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct GrpcConfig {
+        name: String,
+        port: u16,
+    }
+
+    impl TokenExpandingConfig for GrpcConfig {}
+
+    #[test]
+    fn test_grpc_source() {
+        let watchers = Arc::new(Mutex::new(Vec::new()));
+        let url = serve(TestService {
+            document: ConfigDocument {
+                format: "yaml".to_string(),
+                content: "name: remote\nport: 9000\n".to_string(),
+                version: "1".to_string(),
+            },
+            watchers: Arc::clone(&watchers),
+        });
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\nport = 80\n",
+        )
+        .unwrap();
+
+        let source = GrpcSource::new(&url, "myapp")
+            .profile("prod")
+            .timeout(Duration::from_secs(5));
+        let config: GrpcConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(source.clone())
+            .load()
+            .unwrap();
+        assert_eq!(config.name, "remote");
+        assert_eq!(config.port, 9000);
+
+        assert!(GrpcSource::new(&url, "other").fetch().is_err());
+
+        let watcher = source.watch().unwrap();
+        assert!(!watcher.wait(Some(Duration::from_millis(50))).unwrap());
+        let update = ConfigDocument {
+            format: "json".to_string(),
+            content: r#"{ "port": 9001 }"#.to_string(),
+            version: "2".to_string(),
+        };
+        for sender in watchers.lock().unwrap().iter() {
+            sender.send(Ok(update.clone())).unwrap();
+        }
+        assert!(watcher.wait(Some(Duration::from_secs(5))).unwrap());
+
+        watchers.lock().unwrap().clear();
+        assert!(watcher.wait(Some(Duration::from_secs(5))).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    path::Path,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use figment::{
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+
+use crate::config_loader::load_config_from_string;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A configuration layer fetched from a URL, merged with
+/// [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// The document's format is taken from its `Content-Type`, then the extension of the URL's
+/// path, and is otherwise JSON. When the server sends an `ETag`, later loads revalidate the
+/// document with `If-None-Match` and reuse it if it has not changed.
+pub struct HttpSource {
+    url: String,
+    bearer_token: Option<String>,
+    format: Option<String>,
+    timeout: Duration,
+    cache: Mutex<Option<CachedDocument>>,
+}
+
+/// The last document fetched, along with the `ETag` it was served with.
+struct CachedDocument {
+    etag: String,
+    format: String,
+    body: String,
+}
+
+impl HttpSource {
+    #[must_use]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            bearer_token: None,
+            format: None,
+            timeout: DEFAULT_TIMEOUT,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Sends `token` as a bearer token in the `Authorization` header.
+    #[must_use]
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    /// Sets the format of the document by its file extension, e.g. `toml`, rather than
+    /// detecting it from the response.
+    #[must_use]
+    pub fn format(mut self, extension: impl Into<String>) -> Self {
+        self.format = Some(extension.into());
+        self
+    }
+
+    /// Limits how long fetching the document may take. Defaults to 30 seconds.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Fetches the document, returning its format and contents.
+    fn fetch(&self) -> Result<(String, String), String> {
+        let mut request = ureq::get(&self.url).timeout(self.timeout);
+        if let Some(token) = &self.bearer_token {
+            request = request.set("Authorization", &format!("Bearer {token}"));
+        }
+        let etag = self.cache().as_ref().map(|cached| cached.etag.clone());
+        if let Some(etag) = etag {
+            request = request.set("If-None-Match", &etag);
+        }
+
+        let response = request
+            .call()
+            .map_err(|e| format!("unable to fetch {}: {e}", self.url))?;
+
+        if response.status() == 304 {
+            let cached = self
+                .cache()
+                .as_ref()
+                .map(|cached| (cached.format.clone(), cached.body.clone()));
+            if let Some(cached) = cached {
+                return Ok(cached);
+            }
+        }
+
+        let format = self
+            .format
+            .clone()
+            .unwrap_or_else(|| self.detect_format(response.content_type()));
+        let etag = response.header("ETag").map(ToString::to_string);
+        let body = response
+            .into_string()
+            .map_err(|e| format!("unable to read {}: {e}", self.url))?;
+
+        *self.cache() = etag.map(|etag| CachedDocument {
+            etag,
+            format: format.clone(),
+            body: body.clone(),
+        });
+        Ok((format, body))
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Option<CachedDocument>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn detect_format(&self, content_type: &str) -> String {
+        let content_type = content_type.to_ascii_lowercase();
+        if content_type.contains("json") {
+            return "json".to_string();
+        }
+        if content_type.contains("yaml") {
+            return "yaml".to_string();
+        }
+        if content_type.contains("toml") {
+            return "toml".to_string();
+        }
+
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map_or_else(|| "json".to_string(), str::to_ascii_lowercase)
+    }
+}
+
+impl Provider for HttpSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("remote configuration").source(Source::Custom(self.url.clone()))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        let (format, body) = self.fetch()?;
+        load_config_from_string(&format!("remote.{format}"), &body)
+            .map_err(|e| format!("unable to parse {}: {e}", self.url))?
+            .data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        test_server::{ok, serve, status},
+        GraftonConfigBuilder, TokenExpandingConfig,
+    };
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RemoteConfig {
+        name: String,
+        port: u16,
+    }
+
+    impl TokenExpandingConfig for RemoteConfig {}
+
+    #[test]
+    fn test_http_source() {
+        let (url, server) = serve(vec![
+            ok("application/json", r#"{ "port": 9000 }"#).replacen(
+                "Connection",
+                "ETag: \"v1\"\r\nConnection",
+                1,
+            ),
+            status("304 Not Modified"),
+        ]);
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\nport = 80\n",
+        )
+        .unwrap();
+
+        let builder = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(HttpSource::new(format!("{url}/config")).bearer_token("secret"));
+
+        let first: RemoteConfig = builder.load().unwrap();
+        let second: RemoteConfig = builder.load().unwrap();
+
+        assert_eq!(first.name, "app");
+        assert_eq!(first.port, 9000);
+        assert_eq!(second.port, 9000);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].line, "GET /config HTTP/1.1");
+        assert!(requests[0].body.is_empty());
+        assert!(requests[0].has_header("Authorization: Bearer secret"));
+        assert!(!requests[0]
+            .headers
+            .iter()
+            .any(|h| h.starts_with("If-None-Match")));
+        assert!(requests[1].has_header("If-None-Match: \"v1\""));
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let source = HttpSource::new("https://config.example.com/app.toml?rev=2");
+        assert_eq!(source.detect_format("application/yaml"), "yaml");
+        assert_eq!(source.detect_format("text/plain"), "toml");
+        assert_eq!(
+            HttpSource::new("https://config.example.com/app").detect_format(""),
+            "json"
+        );
+    }
+
+    #[test]
+    fn test_http_source_error() {
+        let (url, server) = serve(vec![status("500 Internal Server Error")]);
+
+        let dir = tempdir().unwrap();
+        let result = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(HttpSource::new(url))
+            .load::<RemoteConfig>();
+
+        assert!(result.is_err());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+use keyring::Entry;
+use serde_json::Value;
+
+use crate::TokenResolver;
+
+/// Resolves `${keyring:<service>/<account>}` tokens from the operating system's credential
+/// store when registered with
+/// [`GraftonConfigBuilder::resolver`](crate::GraftonConfigBuilder::resolver).
+///
+/// The store is the macOS Keychain, the Windows Credential Manager or the Secret Service on
+/// Linux, so desktop applications can keep secrets out of their configuration files. The
+/// service is everything before the first `/` and the account everything after it. A
+/// credential that is not in the store leaves the token unresolved, so a fallback can apply.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeyringResolver;
+
+impl KeyringResolver {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl TokenResolver for KeyringResolver {
+    fn resolve(&self, key: &str) -> Result<Option<Value>, String> {
+        let Some((service, account)) = key
+            .split_once('/')
+            .filter(|(service, account)| !service.is_empty() && !account.is_empty())
+        else {
+            return Err(format!(
+                "invalid keyring token {key}, expected <service>/<account>"
+            ));
+        };
+
+        let password = Entry::new(service, account).and_then(|entry| entry.get_password());
+        match password {
+            Ok(password) => Ok(Some(Value::String(password))),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(format!("unable to read keyring entry {key}: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{GraftonConfigBuilder, TokenExpandingConfig};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct KeyringConfig {
+        password: String,
+    }
+
+    impl TokenExpandingConfig for KeyringConfig {}
+
+    #[test]
+    fn test_keyring_resolver() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "password = \"${keyring:grafton-config-test/admin:-unset}\"\n",
+        )
+        .unwrap();
+
+        let config: KeyringConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .resolver("keyring", KeyringResolver::new())
+            .load()
+            .unwrap();
+        assert_eq!(config.password, "unset");
+
+        assert!(KeyringResolver.resolve("grafton-config-test").is_err());
+        assert!(KeyringResolver.resolve("/admin").is_err());
+    }
+}
@@ -6,14 +6,228 @@ mod config;
 
 mod config_loader;
 
+mod config_format;
+
+mod config_source;
+
+mod mounted_dir_source;
+
+mod load_report;
+
+mod provenance;
+
+mod source_location;
+
+#[cfg(feature = "miette")]
+mod diagnostic;
+
+mod coerce;
+
+mod suggestion;
+
 mod token_expander;
 
+mod token_builtins;
+
+mod token_expression;
+
+mod token_filter;
+
+#[cfg(feature = "minijinja")]
+mod token_template;
+
+#[cfg(feature = "http")]
+mod http_source;
+
+#[cfg(feature = "etcd")]
+mod etcd_source;
+
+#[cfg(feature = "vault")]
+mod vault_source;
+
+#[cfg(any(
+    feature = "aws-ssm",
+    feature = "aws-secrets-manager",
+    feature = "aws-appconfig"
+))]
+mod aws;
+
+#[cfg(feature = "aws-ssm")]
+mod ssm_source;
+
+#[cfg(feature = "aws-secrets-manager")]
+mod secrets_manager;
+
+#[cfg(feature = "aws-appconfig")]
+mod appconfig_source;
+
+#[cfg(feature = "gcp")]
+mod gcp_secret;
+
+#[cfg(feature = "azure")]
+mod azure_source;
+
+#[cfg(all(windows, feature = "windows-registry"))]
+mod registry_source;
+
+#[cfg(feature = "keyring")]
+mod keyring_resolver;
+
+#[cfg(feature = "sql")]
+mod sql_source;
+
+#[cfg(feature = "redis")]
+mod redis_source;
+
+#[cfg(feature = "zookeeper")]
+mod zookeeper_source;
+
+#[cfg(feature = "spring-cloud-config")]
+mod spring_config_source;
+
+#[cfg(feature = "grpc")]
+mod grpc_source;
+
+#[cfg(any(
+    feature = "redis",
+    feature = "zookeeper",
+    feature = "grpc",
+    feature = "reload"
+))]
+mod change_watcher;
+
+#[cfg(feature = "reload")]
+mod config_change;
+
+#[cfg(feature = "reload")]
+mod shared_config;
+
+#[cfg(feature = "validator")]
+mod field_validation;
+
+#[cfg(feature = "schema")]
+mod schema;
+
+#[cfg(feature = "json-schema")]
+mod schema_validation;
+
+#[cfg(all(
+    test,
+    any(
+        feature = "http",
+        feature = "etcd",
+        feature = "vault",
+        feature = "aws-ssm",
+        feature = "aws-secrets-manager",
+        feature = "aws-appconfig",
+        feature = "gcp",
+        feature = "azure",
+        feature = "spring-cloud-config"
+    )
+))]
+mod test_server;
+
 mod error;
-pub use error::Error;
+pub use error::{Error, ExpansionLimit, SourceLocation, UnknownKey, ValidationError};
 
 use serde::{de::DeserializeOwned, Serialize};
 
-pub use {config::GraftonConfig, config_loader::load_config_from_dir};
+#[cfg(feature = "http")]
+pub use http_source::HttpSource;
+
+#[cfg(feature = "etcd")]
+pub use etcd_source::EtcdSource;
+
+#[cfg(feature = "vault")]
+pub use vault_source::{VaultClient, VaultSource};
+
+#[cfg(any(
+    feature = "aws-ssm",
+    feature = "aws-secrets-manager",
+    feature = "aws-appconfig"
+))]
+pub use aws::{AwsClient, AwsCredentials, AwsError};
+
+#[cfg(feature = "aws-ssm")]
+pub use ssm_source::SsmSource;
+
+#[cfg(feature = "aws-secrets-manager")]
+pub use secrets_manager::SecretsManager;
+
+#[cfg(feature = "aws-appconfig")]
+pub use appconfig_source::AppConfigSource;
+
+#[cfg(feature = "gcp")]
+pub use gcp_secret::{GcpSecretManager, GcpSecretSource};
+
+#[cfg(feature = "azure")]
+pub use azure_source::{AzureAppConfigSource, AzureCredential};
+
+#[cfg(all(windows, feature = "windows-registry"))]
+pub use registry_source::RegistrySource;
+
+#[cfg(feature = "keyring")]
+pub use keyring_resolver::KeyringResolver;
+
+#[cfg(feature = "sql")]
+pub use sql_source::{SqlSource, DEFAULT_SQL_QUERY};
+
+#[cfg(feature = "redis")]
+pub use redis_source::RedisSource;
+
+#[cfg(feature = "zookeeper")]
+pub use zookeeper_source::ZooKeeperSource;
+
+#[cfg(feature = "spring-cloud-config")]
+pub use spring_config_source::SpringConfigSource;
+
+#[cfg(feature = "grpc")]
+pub use grpc_source::{
+    ConfigDocument, ConfigService, ConfigServiceServer, GetConfigRequest, GrpcSource,
+};
+
+#[cfg(any(
+    feature = "redis",
+    feature = "zookeeper",
+    feature = "grpc",
+    feature = "reload"
+))]
+pub use change_watcher::ChangeWatcher;
+
+#[cfg(feature = "reload")]
+pub use config_change::{ConfigChange, REDACTED};
+
+#[cfg(feature = "reload")]
+pub use shared_config::{SharedConfig, Snapshot, Subscription};
+
+#[cfg(feature = "validator")]
+pub use field_validation::validation_errors;
+
+#[cfg(feature = "schema")]
+pub use schema::generate_schema;
+
+#[cfg(feature = "json-schema")]
+pub use schema_validation::validate_against_schema;
+
+pub use {
+    config::GraftonConfig,
+    config_format::ConfigFormat,
+    config_loader::{
+        check_config_dir, load_config_auto, load_config_from_dir, load_config_from_dir_with_mode,
+        load_config_from_dir_with_options, load_config_from_reader, load_config_from_str,
+        load_config_from_str_layers, GraftonConfigBuilder, LayerPolicy,
+    },
+    config_source::{BoxFuture, ConfigSource},
+    load_report::{ConfigReport, LayerSummary, LoadReport, LoadWarning},
+    mounted_dir_source::MountedDirSource,
+    provenance::{Explanation, Provenance, ValueSource},
+    token_builtins::TokenResolver,
+    token_expander::{
+        expand_path, expand_tokens, expand_tokens_traced, expand_tokens_with_context,
+        expand_tokens_with_options, ExpansionBackend, ExpansionOptions, ExpansionReport,
+        Substitution,
+    },
+};
 
 pub trait GraftonConfigProvider: TokenExpandingConfig {
     fn get_grafton_config(&self) -> &GraftonConfig;
@@ -23,3 +237,44 @@ pub trait TokenExpandingConfig:
     'static + Send + Sync + DeserializeOwned + Serialize + std::fmt::Debug
 {
 }
+
+/// A configuration that checks itself once loaded, for constraints that deserialization
+/// cannot express, such as ranges or fields that depend on each other.
+///
+/// [`GraftonConfigBuilder::load_validated`] calls [`validate`](Self::validate) after
+/// deserializing the configuration, and, with the `reload` feature, `SharedConfig::validated`
+/// before each reload takes effect. Every problem found is reported at once, in [`Error::ValidationFailed`].
+///
+/// ```
+/// use grafton_config::{TokenExpandingConfig, ValidatableConfig, ValidationError};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct AppConfig {
+///     workers: usize,
+///     max_workers: usize,
+/// }
+///
+/// impl TokenExpandingConfig for AppConfig {}
+///
+/// impl ValidatableConfig for AppConfig {
+///     fn validate(&self) -> Result<(), Vec<ValidationError>> {
+///         let mut errors = Vec::new();
+///         if self.workers == 0 {
+///             errors.push(ValidationError::new("workers", "must be at least 1"));
+///         }
+///         if self.workers > self.max_workers {
+///             errors.push(ValidationError::new("workers", "must not exceed max_workers"));
+///         }
+///         if errors.is_empty() { Ok(()) } else { Err(errors) }
+///     }
+/// }
+/// ```
+pub trait ValidatableConfig: TokenExpandingConfig {
+    /// Checks the configuration, returning every problem found.
+    ///
+    /// # Errors
+    ///
+    /// Returns the problems found, each with the path of the offending value.
+    fn validate(&self) -> Result<(), Vec<ValidationError>>;
+}
@@ -0,0 +1,117 @@
+use std::{fmt, path::PathBuf, time::Duration};
+
+use crate::{error::did_you_mean, Error, ValueSource};
+
+/// What happened while loading a configuration, as returned by
+/// [`GraftonConfigBuilder::load_with_report`](crate::GraftonConfigBuilder::load_with_report).
+///
+/// Its [`Display`](fmt::Display) form is a one-line summary meant to be logged at startup,
+/// e.g. `loaded 2 config files for run mode production in 3ms: 14 keys from 3 layers, 4 tokens
+/// expanded, 0 warnings`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    /// The run mode the layers were resolved for, if any.
+    pub run_mode: Option<String>,
+    /// The configuration, drop-in and `.env` files that were read, in the order they were
+    /// merged.
+    pub files_found: Vec<PathBuf>,
+    /// The files expected for layers that were not found, in the first of the configuration
+    /// formats.
+    pub files_missing: Vec<PathBuf>,
+    /// Every layer merged, in order, with the number of values it set.
+    pub layers: Vec<LayerSummary>,
+    /// The number of tokens substituted during expansion.
+    pub tokens_expanded: usize,
+    /// Problems that did not stop the configuration from loading.
+    pub warnings: Vec<LoadWarning>,
+    /// How long loading took, including fetching the configuration sources.
+    pub duration: Duration,
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "loaded {} config files", self.files_found.len())?;
+        if let Some(run_mode) = &self.run_mode {
+            write!(f, " for run mode {run_mode}")?;
+        }
+        write!(
+            f,
+            " in {}ms: {} keys from {} layers, {} tokens expanded, {} warnings",
+            self.duration.as_millis(),
+            self.layers.iter().map(|layer| layer.keys).sum::<usize>(),
+            self.layers.len(),
+            self.tokens_expanded,
+            self.warnings.len()
+        )
+    }
+}
+
+/// A layer merged into a configuration, as listed in [`LoadReport::layers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerSummary {
+    /// The file, environment variables or provider the layer was read from.
+    pub source: ValueSource,
+    /// The number of values the layer set, counting each element of an array.
+    pub keys: usize,
+}
+
+/// A problem that did not stop a configuration from loading, but probably deserves attention.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadWarning {
+    /// A key that no field of the configuration struct consumed, such as a misspelled one, and
+    /// the field it was probably meant for, if any is close enough.
+    UnusedKey {
+        path: String,
+        suggestion: Option<String>,
+    },
+    /// A layer with [`LayerPolicy::WarnIfMissing`](crate::LayerPolicy::WarnIfMissing) for which
+    /// no file was found, and the path of the file expected.
+    MissingLayer { layer: String, path: String },
+    /// A key that was moved to its new path, see
+    /// [`GraftonConfigBuilder::renamed_key`](crate::GraftonConfigBuilder::renamed_key), and the
+    /// file or other source that set it.
+    DeprecatedKey {
+        old: String,
+        new: String,
+        source: Option<String>,
+    },
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnusedKey { path, suggestion } => write!(
+                f,
+                "unused config key: {path}{}",
+                did_you_mean(suggestion.as_deref())
+            ),
+            Self::MissingLayer { layer, path } => {
+                write!(f, "config layer {layer} not found: {path}")
+            }
+            Self::DeprecatedKey { old, new, source } => {
+                write!(f, "config key {old} is deprecated, use {new} instead")?;
+                source
+                    .as_ref()
+                    .map_or(Ok(()), |source| write!(f, " (set in {source})"))
+            }
+        }
+    }
+}
+
+/// Every problem found by [`check_config_dir`](crate::check_config_dir) or
+/// [`GraftonConfigBuilder::check`](crate::GraftonConfigBuilder::check).
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    /// Problems that would stop the configuration from loading.
+    pub errors: Vec<Error>,
+    /// Problems that would not stop the configuration from loading.
+    pub warnings: Vec<LoadWarning>,
+}
+
+impl ConfigReport {
+    /// Whether the configuration would load.
+    #[must_use]
+    pub const fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use serde_json::Value;
+
+use crate::{config_loader::insert_nested, Error};
+
+/// A configuration layer read from a directory holding one file per key.
+///
+/// This is the shape of Kubernetes `ConfigMap` and `Secret` volumes, merged with
+/// [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider). Each file's name
+/// is its key and its contents, without a trailing newline, its value. With
+/// [`key_separator`](MountedDirSource::key_separator), file names are split into paths, so with
+/// `__`, the file `database__password` sets `database.password`. Hidden entries, such as the
+/// `..data` links Kubernetes maintains, and subdirectories are skipped.
+pub struct MountedDirSource {
+    dir: PathBuf,
+    config_path: Vec<String>,
+    key_separator: Option<String>,
+    optional: bool,
+}
+
+impl MountedDirSource {
+    #[must_use]
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            config_path: Vec::new(),
+            key_separator: None,
+            optional: false,
+        }
+    }
+
+    /// Places the keys under the dotted `config_path` rather than at the top level.
+    #[must_use]
+    pub fn config_path(mut self, config_path: &str) -> Self {
+        self.config_path = config_path
+            .split('.')
+            .filter(|segment| !segment.is_empty())
+            .map(ToString::to_string)
+            .collect();
+        self
+    }
+
+    /// Splits file names on `separator` into paths, e.g. `__` or `.`.
+    #[must_use]
+    pub fn key_separator(mut self, separator: impl Into<String>) -> Self {
+        self.key_separator = Some(separator.into());
+        self
+    }
+
+    /// Treats a missing directory as empty rather than as an error, for volumes that are only
+    /// mounted in some deployments.
+    #[must_use]
+    pub const fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    /// Reads the files in the directory into a table.
+    pub(crate) fn read(&self) -> Result<Value, Error> {
+        let io_error = |path: &Path| {
+            let path = path.display().to_string();
+            move |source| Error::IoError { path, source }
+        };
+
+        let mut root = serde_json::Map::new();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if self.optional && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Value::Object(root));
+            }
+            Err(e) => return Err(io_error(&self.dir)(e)),
+        };
+
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(io_error(&self.dir))?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if !name.starts_with('.') && path.is_file() {
+                files.push((name.to_string(), path));
+            }
+        }
+        files.sort();
+
+        for (name, path) in files {
+            let contents = fs::read_to_string(&path).map_err(io_error(&path))?;
+            let value = contents
+                .strip_suffix('\n')
+                .map_or(contents.as_str(), |value| {
+                    value.strip_suffix('\r').unwrap_or(value)
+                });
+
+            let key: Vec<String> = match &self.key_separator {
+                Some(separator) => name
+                    .split(separator.as_str())
+                    .filter(|segment| !segment.is_empty())
+                    .map(ToString::to_string)
+                    .collect(),
+                None => vec![name],
+            };
+            let segments: Vec<String> = self.config_path.iter().cloned().chain(key).collect();
+            insert_nested(&mut root, &segments, Value::String(value.to_string()));
+        }
+        Ok(Value::Object(root))
+    }
+}
+
+impl Provider for MountedDirSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("mounted directory").source(Source::File(self.dir.clone()))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        let value = self.read().map_err(|e| e.to_string())?;
+        Serialized::defaults(value).data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{GraftonConfigBuilder, TokenExpandingConfig};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Database {
+        host: String,
+        port: u16,
+        password: String,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct MountedConfig {
+        name: String,
+        database: Database,
+    }
+
+    impl TokenExpandingConfig for MountedConfig {}
+
+    #[test]
+    fn test_mounted_dir_source() {
+        let config_dir = tempdir().unwrap();
+        std::fs::write(
+            config_dir.path().join("default.toml"),
+            "name = \"app\"\n\n[database]\nhost = \"localhost\"\nport = 5432\npassword = \"\"\n",
+        )
+        .unwrap();
+
+        let configmap = tempdir().unwrap();
+        std::fs::write(configmap.path().join("database__host"), "db.internal\n").unwrap();
+        std::fs::write(configmap.path().join("name"), "${database.host}").unwrap();
+        std::fs::create_dir(configmap.path().join("..data")).unwrap();
+        std::fs::write(configmap.path().join("..data").join("name"), "hidden").unwrap();
+
+        let secret = tempdir().unwrap();
+        std::fs::write(secret.path().join("password"), "hunter2\r\n").unwrap();
+        std::fs::write(secret.path().join(".hidden"), "ignored").unwrap();
+
+        let config: MountedConfig = GraftonConfigBuilder::new()
+            .dir(config_dir.path())
+            .provider(MountedDirSource::new(configmap.path()).key_separator("__"))
+            .provider(MountedDirSource::new(secret.path()).config_path("database"))
+            .provider(MountedDirSource::new(secret.path().join("missing")).optional(true))
+            .load()
+            .unwrap();
+
+        assert_eq!(config.name, "db.internal");
+        assert_eq!(config.database.host, "db.internal");
+        assert_eq!(config.database.port, 5432);
+        assert_eq!(config.database.password, "hunter2");
+    }
+
+    #[test]
+    fn test_missing_mounted_dir() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(
+            MountedDirSource::new(dir.path().join("missing")).read(),
+            Err(Error::IoError { .. })
+        ));
+    }
+}
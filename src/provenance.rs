@@ -0,0 +1,168 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use figment::{value::Value as FigmentValue, Figment, Metadata, Profile, Provider};
+use serde_json::Value;
+
+use crate::token_expander::{format_new_array_path, format_new_path};
+
+/// Where each value of a configuration came from, by path, e.g. `servers[0].port`, as
+/// returned by [`GraftonConfigBuilder::config_with_provenance`].
+///
+/// [`GraftonConfigBuilder::config_with_provenance`]:
+/// crate::GraftonConfigBuilder::config_with_provenance
+pub type Provenance = BTreeMap<String, ValueSource>;
+
+/// The file, environment variables or provider that set a configuration value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueSource {
+    /// Name of the provider, e.g. `TOML file`, `` `APP__` environment variable(s) `` or the name
+    /// of a configuration source.
+    pub provider: String,
+    /// The file the value was read from, if any.
+    pub file: Option<PathBuf>,
+    /// The layer of that file, named relative to the configuration directory and without its
+    /// extension, e.g. `default`, `hosts/web-1` or `conf.d/10-logging`.
+    pub layer: Option<String>,
+}
+
+impl std::fmt::Display for ValueSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.file, &self.layer) {
+            (Some(file), Some(layer)) => write!(f, "{layer} layer ({})", file.display()),
+            (Some(file), None) => write!(f, "{}", file.display()),
+            _ => f.write_str(&self.provider),
+        }
+    }
+}
+
+/// How a configuration value came to be, as returned by
+/// [`GraftonConfigBuilder::explain`](crate::GraftonConfigBuilder::explain).
+///
+/// Its [`Display`](std::fmt::Display) form is meant for people, e.g.
+///
+/// ```text
+/// website.port = 8080
+///   set by local layer (/srv/app/config/local.toml)
+///   overrides 80 from default layer (/srv/app/config/default.toml)
+///   expanded from "${vars.port}"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// The path explained.
+    pub path: String,
+    /// The final value, after token expansion, or `None` if nothing sets it.
+    pub value: Option<Value>,
+    /// The layer whose value won.
+    pub source: Option<ValueSource>,
+    /// The layers whose values were overridden, in the order they were merged, with those
+    /// values.
+    pub overridden: Vec<(ValueSource, Value)>,
+    /// The value before token expansion, if expansion changed it.
+    pub unexpanded: Option<Value>,
+}
+
+impl std::fmt::Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{} = {value}", self.path)?,
+            None => write!(f, "{} is not set", self.path)?,
+        }
+        if let Some(source) = &self.source {
+            write!(f, "\n  set by {source}")?;
+        }
+        for (source, value) in self.overridden.iter().rev() {
+            write!(f, "\n  overrides {value} from {source}")?;
+        }
+        if let Some(unexpanded) = &self.unexpanded {
+            write!(f, "\n  expanded from {unexpanded}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps every leaf of the merged `figment` to the source that set it, naming the layers of
+/// files within `config_dir`.
+pub fn provenance(figment: &Figment, config_dir: &Path) -> Provenance {
+    let mut provenance = Provenance::new();
+    if let Some(dict) = figment
+        .data()
+        .ok()
+        .and_then(|mut data| data.remove(&Profile::Default))
+    {
+        for (key, value) in &dict {
+            record(
+                figment,
+                config_dir,
+                &format_new_path("", key),
+                value,
+                &mut provenance,
+            );
+        }
+    }
+    provenance
+}
+
+fn record(
+    figment: &Figment,
+    config_dir: &Path,
+    path: &str,
+    value: &FigmentValue,
+    provenance: &mut Provenance,
+) {
+    match value {
+        FigmentValue::Dict(_, dict) if !dict.is_empty() => {
+            for (key, value) in dict {
+                record(
+                    figment,
+                    config_dir,
+                    &format_new_path(path, key),
+                    value,
+                    provenance,
+                );
+            }
+        }
+        FigmentValue::Array(_, items) if !items.is_empty() => {
+            for (index, item) in items.iter().enumerate() {
+                let path = format_new_array_path(path, index);
+                record(figment, config_dir, &path, item, provenance);
+            }
+        }
+        value => {
+            if let Some(metadata) = figment.get_metadata(value.tag()) {
+                provenance.insert(path.to_string(), value_source(metadata, config_dir));
+            }
+        }
+    }
+}
+
+/// Describes the source a provider's `metadata` names, naming the layers of files within
+/// `config_dir`.
+pub fn value_source(metadata: &Metadata, config_dir: &Path) -> ValueSource {
+    let file = metadata
+        .source
+        .as_ref()
+        .and_then(|source| source.file_path())
+        .map(Path::to_path_buf);
+    let layer = file
+        .as_deref()
+        .and_then(|file| layer_name(file, config_dir));
+    ValueSource {
+        provider: metadata.name.to_string(),
+        file,
+        layer,
+    }
+}
+
+/// Names the layer of a file within `config_dir`, e.g. `hosts/web-1` for
+/// `{config_dir}/hosts/web-1.toml`.
+fn layer_name(file: &Path, config_dir: &Path) -> Option<String> {
+    let relative = file.strip_prefix(config_dir).ok()?.with_extension("");
+    let segments: Vec<String> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Some(segments.join("/"))
+}
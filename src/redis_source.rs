@@ -0,0 +1,429 @@
+use std::{sync::mpsc, thread, time::Duration};
+
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use redis::{Client, Connection};
+use serde_json::Value;
+
+use crate::{config_loader::insert_nested, ChangeWatcher, Error};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many keys each `SCAN` of a key prefix asks for.
+const SCAN_COUNT: usize = 100;
+
+enum Target {
+    Hash(String),
+    Prefix(String),
+}
+
+/// A configuration layer read from Redis, merged with
+/// [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// The layer is either the fields of a hash, or the string keys under a key prefix with the
+/// prefix removed. Field names and keys are split on `:` into paths, so the field
+/// `database:port` sets `database.port`. Values are strings, which are coerced into typed
+/// fields when the configuration is deserialized.
+///
+/// [`watch`](RedisSource::watch) listens for keyspace notifications, so the configuration can be
+/// reloaded when the tunables change. The server must have them enabled, e.g. with
+/// `notify-keyspace-events Kh` for a hash or `K$` for string keys.
+pub struct RedisSource {
+    client: Client,
+    target: Target,
+    key_separator: String,
+    timeout: Duration,
+}
+
+impl RedisSource {
+    /// Creates a source for the fields of the hash `key` on the server at `url`, such as
+    /// `redis://127.0.0.1/0`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ExternalError`] if `url` is invalid.
+    pub fn hash(url: &str, key: impl Into<String>) -> Result<Self, Error> {
+        Self::new(url, Target::Hash(key.into()))
+    }
+
+    /// Creates a source for the string keys starting with `prefix`, such as `myapp:`, on the
+    /// server at `url`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ExternalError`] if `url` is invalid.
+    pub fn prefix(url: &str, prefix: impl Into<String>) -> Result<Self, Error> {
+        Self::new(url, Target::Prefix(prefix.into()))
+    }
+
+    fn new(url: &str, target: Target) -> Result<Self, Error> {
+        let client = Client::open(url).map_err(|e| Error::ExternalError {
+            context: format!("invalid Redis URL {url}"),
+            source: Box::new(e),
+        })?;
+        Ok(Self {
+            client,
+            target,
+            key_separator: ":".to_string(),
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Splits field names and keys on `separator` into paths, e.g. `.` or `/`, rather than on
+    /// `:`.
+    #[must_use]
+    pub fn key_separator(mut self, separator: impl Into<String>) -> Self {
+        self.key_separator = separator.into();
+        self
+    }
+
+    /// Limits how long connecting to Redis and each command may take. Defaults to 30 seconds.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Subscribes to the keyspace notifications for the hash or key prefix, returning a
+    /// [`ChangeWatcher`] that reports when it changes.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ExternalError`] if the server cannot be reached.
+    pub fn watch(&self) -> Result<ChangeWatcher, Error> {
+        let pattern = format!(
+            "__keyspace@{}__:{}",
+            self.client.get_connection_info().redis.db,
+            match &self.target {
+                Target::Hash(key) => escape_pattern(key),
+                Target::Prefix(prefix) => format!("{}*", escape_pattern(prefix)),
+            }
+        );
+        let watch_error = |e: redis::RedisError| Error::ExternalError {
+            context: "unable to watch Redis".to_string(),
+            source: Box::new(e),
+        };
+        let mut connection = self.connect().map_err(watch_error)?;
+
+        let (sender, watcher) = ChangeWatcher::channel("Redis keyspace notifications");
+        let (subscribed, result) = mpsc::channel();
+        thread::spawn(move || {
+            let mut pubsub = connection.as_pubsub();
+            let subscription = pubsub
+                .set_read_timeout(None)
+                .and_then(|()| pubsub.psubscribe(&pattern));
+            let failed = subscription.is_err();
+            if subscribed.send(subscription).is_err() || failed {
+                return;
+            }
+            loop {
+                let message = pubsub.get_message().map(drop).map_err(|e| e.to_string());
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        result
+            .recv()
+            .map_err(|_| Error::ConfigError("unable to watch Redis".to_string()))?
+            .map_err(watch_error)?;
+        Ok(watcher)
+    }
+
+    fn connect(&self) -> redis::RedisResult<Connection> {
+        let connection = self.client.get_connection_with_timeout(self.timeout)?;
+        connection.set_read_timeout(Some(self.timeout))?;
+        connection.set_write_timeout(Some(self.timeout))?;
+        Ok(connection)
+    }
+
+    /// Reads the hash or the keys under the prefix into a table.
+    fn read(&self) -> Result<Value, String> {
+        let mut connection = self
+            .connect()
+            .map_err(|e| format!("unable to connect to Redis: {e}"))?;
+
+        let entries: Vec<(String, Option<String>)> = match &self.target {
+            Target::Hash(key) => redis::cmd("HGETALL")
+                .arg(key)
+                .query::<Vec<(String, String)>>(&mut connection)
+                .map(|fields| {
+                    fields
+                        .into_iter()
+                        .map(|(field, value)| (field, Some(value)))
+                        .collect()
+                })
+                .map_err(|e| format!("unable to read Redis hash {key}: {e}"))?,
+            Target::Prefix(prefix) => read_prefix(&mut connection, prefix)
+                .map_err(|e| format!("unable to read Redis keys under {prefix}: {e}"))?,
+        };
+
+        let mut root = serde_json::Map::new();
+        for (key, value) in entries {
+            let Some(value) = value else {
+                continue;
+            };
+            let key = match &self.target {
+                Target::Prefix(prefix) => key.strip_prefix(prefix.as_str()).unwrap_or(&key),
+                Target::Hash(_) => &key,
+            };
+            let segments: Vec<String> = key
+                .split(self.key_separator.as_str())
+                .filter(|segment| !segment.is_empty())
+                .map(ToString::to_string)
+                .collect();
+            if !segments.is_empty() {
+                insert_nested(&mut root, &segments, Value::String(value));
+            }
+        }
+        Ok(Value::Object(root))
+    }
+}
+
+/// Scans for the keys starting with `prefix`, then fetches their values. Keys that are not
+/// strings have no value.
+fn read_prefix(
+    connection: &mut Connection,
+    prefix: &str,
+) -> redis::RedisResult<Vec<(String, Option<String>)>> {
+    let pattern = format!("{}*", escape_pattern(prefix));
+    let mut keys = Vec::new();
+    let mut cursor = 0u64;
+    loop {
+        let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(&pattern)
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .query(connection)?;
+        keys.extend(batch);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    keys.sort();
+    keys.dedup();
+
+    let mut entries = Vec::with_capacity(keys.len());
+    for chunk in keys.chunks(SCAN_COUNT) {
+        let values: Vec<Option<String>> = redis::cmd("MGET").arg(chunk).query(connection)?;
+        entries.extend(chunk.iter().cloned().zip(values));
+    }
+    Ok(entries)
+}
+
+/// Escapes the glob characters in `literal` for a `MATCH` or `PSUBSCRIBE` pattern.
+fn escape_pattern(literal: &str) -> String {
+    let mut pattern = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+impl Provider for RedisSource {
+    fn metadata(&self) -> Metadata {
+        let target = match &self.target {
+            Target::Hash(key) | Target::Prefix(key) => key,
+        };
+        Metadata::named("Redis").source(Source::Custom(format!(
+            "{}/{target}",
+            self.client.get_connection_info().addr
+        )))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        Serialized::defaults(self.read()?).data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{GraftonConfigBuilder, TokenExpandingConfig};
+
+    /// Serves a connection per entry on a local port, answering each command received with
+    /// the next canned reply, and returns its URL and a handle yielding the commands.
+    fn serve(connections: Vec<Vec<String>>) -> (String, thread::JoinHandle<Vec<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}/2", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut commands = Vec::new();
+            for replies in connections {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                for reply in replies {
+                    commands.push(read_command(&mut reader));
+                    stream.write_all(reply.as_bytes()).unwrap();
+                }
+            }
+            commands
+        });
+        (url, handle)
+    }
+
+    fn read_command(reader: &mut impl BufRead) -> Vec<String> {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let count: usize = line.trim_end()[1..].parse().unwrap();
+        (0..count)
+            .map(|_| {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                let length: usize = line.trim_end()[1..].parse().unwrap();
+                let mut arg = vec![0; length + 2];
+                reader.read_exact(&mut arg).unwrap();
+                arg.truncate(length);
+                String::from_utf8(arg).unwrap()
+            })
+            .collect()
+    }
+
+    /// A RESP array of bulk strings, with `None` as nil.
+    fn array(items: &[Option<&str>]) -> String {
+        items
+            .iter()
+            .fold(format!("*{}\r\n", items.len()), |reply, item| {
+                item.map_or_else(
+                    || format!("{reply}$-1\r\n"),
+                    |item| format!("{reply}${}\r\n{item}\r\n", item.len()),
+                )
+            })
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Database {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RedisConfig {
+        name: String,
+        database: Database,
+    }
+
+    impl TokenExpandingConfig for RedisConfig {}
+
+    #[test]
+    fn test_redis_hash_source() {
+        let (url, server) = serve(vec![vec![
+            "+OK\r\n".to_string(),
+            array(&[
+                Some("database:port"),
+                Some("6432"),
+                Some("name"),
+                Some("${database.host}"),
+            ]),
+        ]]);
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\n\n[database]\nhost = \"db\"\nport = 5432\n",
+        )
+        .unwrap();
+
+        let config: RedisConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(RedisSource::hash(&url, "myapp:config").unwrap())
+            .load()
+            .unwrap();
+        assert_eq!(config.name, "db");
+        assert_eq!(config.database.port, 6432);
+
+        let commands = server.join().unwrap();
+        assert_eq!(commands, [["SELECT", "2"], ["HGETALL", "myapp:config"]]);
+    }
+
+    #[test]
+    fn test_redis_prefix_source() {
+        let scan = |cursor: &str, keys: &[&str]| {
+            let keys: Vec<Option<&str>> = keys.iter().copied().map(Some).collect();
+            format!("*2\r\n${}\r\n{cursor}\r\n{}", cursor.len(), array(&keys))
+        };
+        let (url, server) = serve(vec![vec![
+            "+OK\r\n".to_string(),
+            scan("7", &["app*:database.host"]),
+            scan("0", &["app*:database.port", "app*:queue"]),
+            array(&[Some("db.internal"), Some("6432"), None]),
+        ]]);
+
+        let value = RedisSource::prefix(&url, "app*:")
+            .unwrap()
+            .key_separator(".")
+            .read()
+            .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "database": { "host": "db.internal", "port": "6432" } })
+        );
+
+        let commands = server.join().unwrap();
+        assert_eq!(commands[0], ["SELECT", "2"]);
+        assert_eq!(
+            commands[1],
+            ["SCAN", "0", "MATCH", r"app\*:*", "COUNT", "100"]
+        );
+        assert_eq!(commands[2][1], "7");
+        assert_eq!(
+            commands[3],
+            [
+                "MGET",
+                "app*:database.host",
+                "app*:database.port",
+                "app*:queue"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redis_watch() {
+        let channel = "__keyspace@2__:myapp:config";
+        let subscribed = format!(
+            "*3\r\n$10\r\npsubscribe\r\n${}\r\n{channel}\r\n:1\r\n",
+            channel.len()
+        );
+        let message = array(&[Some("pmessage"), Some(channel), Some(channel), Some("hset")]);
+        let (url, server) = serve(vec![vec![
+            "+OK\r\n".to_string(),
+            format!("{subscribed}{message}{message}"),
+        ]]);
+
+        let watcher = RedisSource::hash(&url, "myapp:config")
+            .unwrap()
+            .watch()
+            .unwrap();
+        let commands = server.join().unwrap();
+        assert_eq!(commands[1], ["PSUBSCRIBE", "__keyspace@2__:myapp:config"]);
+
+        assert!(watcher.wait(Some(Duration::from_secs(5))).unwrap());
+        let lost = (0..3).find_map(|_| watcher.wait(Some(Duration::from_secs(5))).err());
+        assert!(matches!(lost, Some(Error::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_escape_pattern() {
+        assert_eq!(escape_pattern("app:"), "app:");
+        assert_eq!(escape_pattern(r"a*b?[c]\d"), r"a\*b\?\[c\]\\d");
+    }
+}
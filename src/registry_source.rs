@@ -0,0 +1,166 @@
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use serde_json::Value;
+use winreg::{
+    enums::{RegType, HKEY_CLASSES_ROOT, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS},
+    types::FromRegValue,
+    RegKey, RegValue,
+};
+
+/// A configuration layer read from the values under a Windows registry key, such as
+/// `HKLM\Software\MyApp`, merged with
+/// [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// Subkeys become tables and values become keys, both lowercased since registry names are
+/// case-insensitive, so `HKLM\Software\MyApp\Database` with the value `Port` sets
+/// `database.port`. String values are read as strings, `DWORD` and `QWORD` values as numbers
+/// and multi-string values as arrays; other types and default values are skipped.
+pub struct RegistrySource {
+    key: String,
+    optional: bool,
+}
+
+impl RegistrySource {
+    /// Creates a source for `key`, starting with its hive, e.g. `HKLM`, `HKCU`,
+    /// `HKEY_LOCAL_MACHINE` or `HKEY_CURRENT_USER`.
+    #[must_use]
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            optional: false,
+        }
+    }
+
+    /// Treats a missing key as empty rather than as an error, e.g. for policies that are only
+    /// set on some machines.
+    #[must_use]
+    pub const fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    fn read(&self) -> Result<Value, String> {
+        let (hive, path) = self.key.split_once('\\').unwrap_or((&self.key, ""));
+        let hive = match hive.to_ascii_uppercase().as_str() {
+            "HKLM" | "HKEY_LOCAL_MACHINE" => HKEY_LOCAL_MACHINE,
+            "HKCU" | "HKEY_CURRENT_USER" => HKEY_CURRENT_USER,
+            "HKCR" | "HKEY_CLASSES_ROOT" => HKEY_CLASSES_ROOT,
+            "HKU" | "HKEY_USERS" => HKEY_USERS,
+            _ => return Err(format!("unknown registry hive in {}", self.key)),
+        };
+
+        match RegKey::predef(hive).open_subkey(path) {
+            Ok(key) => read_key(&key).map_err(|e| format!("unable to read {}: {e}", self.key)),
+            Err(e) if self.optional && e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Value::Object(serde_json::Map::new()))
+            }
+            Err(e) => Err(format!("unable to open {}: {e}", self.key)),
+        }
+    }
+}
+
+/// Reads the values and subkeys of `key` into a table.
+fn read_key(key: &RegKey) -> std::io::Result<Value> {
+    let mut table = serde_json::Map::new();
+    for value in key.enum_values() {
+        let (name, value) = value?;
+        if name.is_empty() {
+            continue;
+        }
+        if let Some(value) = convert(&value) {
+            table.insert(name.to_lowercase(), value);
+        }
+    }
+    for name in key.enum_keys() {
+        let name = name?;
+        let subkey = key.open_subkey(&name)?;
+        table.insert(name.to_lowercase(), read_key(&subkey)?);
+    }
+    Ok(Value::Object(table))
+}
+
+fn convert(value: &RegValue) -> Option<Value> {
+    match value.vtype {
+        RegType::REG_SZ | RegType::REG_EXPAND_SZ => {
+            String::from_reg_value(value).ok().map(Value::String)
+        }
+        RegType::REG_DWORD => u32::from_reg_value(value).ok().map(Value::from),
+        RegType::REG_QWORD => u64::from_reg_value(value).ok().map(Value::from),
+        RegType::REG_MULTI_SZ => Vec::<String>::from_reg_value(value).ok().map(Value::from),
+        _ => None,
+    }
+}
+
+impl Provider for RegistrySource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("Windows registry").source(Source::Custom(self.key.clone()))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        Serialized::defaults(self.read()?).data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{GraftonConfigBuilder, TokenExpandingConfig};
+
+    const TEST_KEY: &str = r"Software\grafton-config-test";
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Database {
+        host: String,
+        port: u16,
+        replicas: Vec<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RegistryConfig {
+        name: String,
+        database: Database,
+    }
+
+    impl TokenExpandingConfig for RegistryConfig {}
+
+    #[test]
+    fn test_registry_source() {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let (key, _) = hkcu.create_subkey(TEST_KEY).unwrap();
+        key.set_value("Name", &"${database.host}").unwrap();
+        let (database, _) = key.create_subkey("Database").unwrap();
+        database.set_value("Port", &6432u32).unwrap();
+        database
+            .set_value("Replicas", &vec!["a".to_string(), "b".to_string()])
+            .unwrap();
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\n\n[database]\nhost = \"db\"\nport = 5432\nreplicas = []\n",
+        )
+        .unwrap();
+
+        let config = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(RegistrySource::new(format!(r"HKCU\{TEST_KEY}")))
+            .provider(RegistrySource::new(format!(r"HKCU\{TEST_KEY}\Missing")).optional(true))
+            .load::<RegistryConfig>();
+        let missing = RegistrySource::new(format!(r"HKCU\{TEST_KEY}\Missing")).read();
+        let unknown_hive = RegistrySource::new(r"HKXX\Software").read();
+        hkcu.delete_subkey_all(TEST_KEY).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.name, "db");
+        assert_eq!(config.database.port, 6432);
+        assert_eq!(config.database.replicas, ["a", "b"]);
+        assert!(missing.is_err());
+        assert!(unknown_hive.is_err());
+    }
+}
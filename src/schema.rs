@@ -0,0 +1,45 @@
+use schemars::{generate::SchemaSettings, JsonSchema};
+use serde_json::Value;
+
+/// Generates a JSON Schema describing the configuration files that `C` is loaded from.
+///
+/// Configuration structs derive [`JsonSchema`] alongside `Deserialize`, so doc comments,
+/// defaults and `serde` attributes are reflected in the schema. Publishing it lets editors
+/// complete and check configuration files, e.g. with a `#:schema` directive in TOML, and lets
+/// CI validate them before they are deployed.
+#[must_use]
+pub fn generate_schema<C: JsonSchema>() -> Value {
+    SchemaSettings::draft2020_12()
+        .into_generator()
+        .into_root_schema_for::<C>()
+        .to_value()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct SchemaConfig {
+        /// Address the server listens on.
+        bind_address: String,
+        #[serde(default)]
+        port: Option<u16>,
+    }
+
+    #[test]
+    fn test_generate_schema() {
+        let schema = generate_schema::<SchemaConfig>();
+        assert_eq!(schema["title"], "SchemaConfig");
+        assert_eq!(schema["required"], json!(["bind_address"]));
+        assert_eq!(
+            schema["properties"]["bind_address"]["description"],
+            "Address the server listens on."
+        );
+        assert_eq!(schema["properties"]["port"]["maximum"], 65535);
+    }
+}
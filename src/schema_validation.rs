@@ -0,0 +1,81 @@
+use jsonschema::paths::LocationSegment;
+use serde_json::Value;
+
+use crate::{
+    token_expander::{format_new_array_path, format_new_path},
+    Error, ValidationError,
+};
+
+/// Checks the merged and expanded configuration `value` against the JSON Schema `schema`.
+///
+/// # Errors
+///
+/// Returns [`Error::ValidationFailed`], listing every violation in order of the paths of the
+/// offending values, if `value` does not conform, or [`Error::ExternalError`] if `schema` is not
+/// a valid JSON Schema.
+pub fn validate_against_schema(schema: &Value, value: &Value) -> Result<(), Error> {
+    let validator = jsonschema::validator_for(schema).map_err(|e| Error::ExternalError {
+        context: "Invalid JSON Schema".to_string(),
+        source: Box::new(e.to_owned()),
+    })?;
+    let mut errors: Vec<ValidationError> = validator
+        .iter_errors(value)
+        .map(|error| {
+            let path = error
+                .instance_path
+                .into_iter()
+                .fold(String::new(), |path, segment| match segment {
+                    LocationSegment::Property(key) => format_new_path(&path, key),
+                    LocationSegment::Index(index) => format_new_array_path(&path, index),
+                });
+            ValidationError::new(path, error.to_string())
+        })
+        .collect();
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ValidationFailed(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_validate_against_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "servers": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": { "port": { "type": "integer", "maximum": 65535 } },
+                        "additionalProperties": false,
+                    },
+                },
+            },
+        });
+        assert!(validate_against_schema(&schema, &json!({ "servers": [{ "port": 80 }] })).is_ok());
+
+        let value = json!({ "servers": [{ "port": 80 }, { "port": 70000, "prot": 1 }] });
+        match validate_against_schema(&schema, &value) {
+            Err(Error::ValidationFailed(errors)) => {
+                let paths: Vec<&str> = errors.iter().map(|error| error.path.as_str()).collect();
+                assert_eq!(paths, ["servers[1]", "servers[1].port"]);
+                assert!(errors[0].message.contains("prot"), "{}", errors[0]);
+            }
+            other => panic!("expected a validation failure, got {other:?}"),
+        }
+
+        let invalid = json!({ "type": "no-such-type" });
+        assert!(matches!(
+            validate_against_schema(&invalid, &value),
+            Err(Error::ExternalError { .. })
+        ));
+    }
+}
@@ -0,0 +1,875 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+#[cfg(feature = "reload-tokio")]
+use tokio::sync::watch;
+
+use crate::{
+    config_change::{diff, ConfigChange},
+    token_expander::{get_value_from_path, split_path},
+    ChangeWatcher, Error, GraftonConfigBuilder, TokenExpandingConfig, ValidatableConfig,
+};
+
+type PathCallback = Box<dyn Fn(Option<&Value>) + Send + Sync>;
+type ChangesCallback = Box<dyn Fn(&[ConfigChange]) + Send + Sync>;
+
+/// A callback registered with [`SharedConfig::subscribe`] or [`SharedConfig::on_change`].
+enum Callback {
+    /// Given the new value at the path.
+    Path(Vec<String>, PathCallback),
+    /// Given the changes a reload made.
+    Changes(ChangesCallback),
+}
+
+struct Subscriber {
+    id: u64,
+    callback: Callback,
+}
+
+type Subscribers = Mutex<Vec<Arc<Subscriber>>>;
+
+/// How often a thread started by [`SharedConfig::reload_on`] checks whether every handle to the
+/// configuration it reloads has been dropped.
+const DROPPED_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A check registered with [`SharedConfig::with_validator`].
+type Validator<C> = Box<dyn Fn(&C) -> Result<(), Error> + Send + Sync>;
+
+/// A handle to the current configuration, returned by
+/// [`GraftonConfigBuilder::load_shared`], that reloads replace atomically.
+///
+/// Clones share the configuration, so a handle can be given to each component that reads it.
+/// [`current`](SharedConfig::current) is a lock-free read returning a snapshot, which stays
+/// unchanged for as long as it is held, while [`reload`](SharedConfig::reload) loads the
+/// configuration again with the builder it was first loaded with and swaps it in.
+/// Components that only care about part of the configuration can
+/// [`subscribe`](SharedConfig::subscribe) to it, to be called when a reload changes it, and
+/// [`on_change`](SharedConfig::on_change) lists every change a reload makes. Asynchronous code
+/// can instead [`watch`](SharedConfig::watch) for new configurations, with the `reload-tokio`
+/// feature.
+///
+/// A reload only takes effect once the new configuration has been read, expanded,
+/// deserialized and accepted by every [validator](SharedConfig::with_validator). Until then,
+/// and if any of these fail, the last good configuration stays current.
+pub struct SharedConfig<C> {
+    inner: Arc<Inner<C>>,
+}
+
+struct Inner<C> {
+    snapshot: ArcSwap<Snapshot<C>>,
+    /// The expanded value the current configuration was deserialized from, held while a reload
+    /// compares and swaps in its configuration.
+    expanded: Mutex<Value>,
+    builder: GraftonConfigBuilder,
+    subscribers: Arc<Subscribers>,
+    next_id: AtomicU64,
+    validators: Mutex<Vec<Validator<C>>>,
+    redactions: Mutex<Vec<String>>,
+    #[cfg(feature = "reload-tokio")]
+    sender: watch::Sender<Arc<C>>,
+}
+
+impl<C> Clone for SharedConfig<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<C: TokenExpandingConfig> SharedConfig<C> {
+    pub(crate) fn new(builder: GraftonConfigBuilder, config: C, expanded: Value) -> Self {
+        let config = Arc::new(config);
+        Self {
+            inner: Arc::new(Inner {
+                #[cfg(feature = "reload-tokio")]
+                sender: watch::Sender::new(Arc::clone(&config)),
+                snapshot: ArcSwap::from_pointee(Snapshot {
+                    config,
+                    generation: 1,
+                    loaded_at: SystemTime::now(),
+                }),
+                expanded: Mutex::new(expanded),
+                builder,
+                subscribers: Arc::default(),
+                next_id: AtomicU64::new(0),
+                validators: Mutex::default(),
+                redactions: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Adds a check that each reloaded configuration must pass to become current, such as a
+    /// cross-field constraint, returning a message describing the problem if it fails. The
+    /// current configuration is checked too.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ConfigError`] with the message if the current configuration fails the
+    /// check.
+    pub fn with_validator<F>(self, validator: F) -> Result<Self, Error>
+    where
+        F: Fn(&C) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.add_validator(Box::new(move |config| validator(config).map_err(rejected)))
+    }
+
+    /// Checks each reloaded configuration with [`ValidatableConfig::validate`] before it
+    /// becomes current, as [`with_validator`](Self::with_validator) does.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ValidationFailed`] if the current configuration is invalid.
+    pub fn validated(self) -> Result<Self, Error>
+    where
+        C: ValidatableConfig,
+    {
+        self.add_validator(Box::new(|config: &C| {
+            config.validate().map_err(Error::ValidationFailed)
+        }))
+    }
+
+    /// Enforces the constraints declared on the configuration's fields with
+    /// `#[derive(validator::Validate)]` on each reload, before it becomes current.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ValidationFailed`] if the current configuration is invalid.
+    #[cfg(feature = "validator")]
+    pub fn validated_fields(self) -> Result<Self, Error>
+    where
+        C: validator::Validate,
+    {
+        self.add_validator(Box::new(|config: &C| Ok(config.validate()?)))
+    }
+
+    fn add_validator(self, validator: Validator<C>) -> Result<Self, Error> {
+        validator(&self.current())?;
+        self.inner
+            .validators
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(validator);
+        Ok(self)
+    }
+
+    /// Returns the current configuration along with its generation and when it was loaded,
+    /// read together so they always describe the same configuration.
+    #[must_use]
+    pub fn snapshot(&self) -> Arc<Snapshot<C>> {
+        self.inner.snapshot.load_full()
+    }
+
+    /// Returns the generation of the current configuration, see [`Snapshot::generation`].
+    #[must_use]
+    pub fn generation(&self) -> u64 {
+        self.inner.snapshot.load().generation
+    }
+
+    /// Returns the current configuration.
+    #[must_use]
+    pub fn current(&self) -> Arc<C> {
+        Arc::clone(&self.inner.snapshot.load().config)
+    }
+
+    /// Loads the configuration again and makes it current, returning it. Configuration
+    /// sources are loaded on the current thread, as by [`GraftonConfigBuilder::load`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration cannot be loaded or fails a validator, in which
+    /// case the current configuration is kept.
+    pub fn reload(&self) -> Result<Arc<C>, Error> {
+        let (config, expanded) = self.inner.builder.load_expanded()?;
+        self.accept(config, expanded)
+    }
+
+    /// Reloads the configuration as [`reload`](Self::reload) does, awaiting the configuration
+    /// sources.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration cannot be loaded or fails a validator, in which
+    /// case the current configuration is kept.
+    pub async fn reload_async(&self) -> Result<Arc<C>, Error> {
+        let (config, expanded) = self.inner.builder.load_expanded_async().await?;
+        self.accept(config, expanded)
+    }
+
+    /// Reloads the configuration on a background thread each time `watcher` reports a change,
+    /// such as a `SIGHUP` from [`ChangeWatcher::sighup`] or an update to a remote source.
+    ///
+    /// Failed reloads keep the current configuration and are reported on the returned channel,
+    /// as is the loss of the watcher, after which the thread stops and the channel closes. The
+    /// thread also stops, dropping the watcher, once every handle to the configuration has been
+    /// dropped, or once a failed reload finds the channel's receiver dropped.
+    #[must_use]
+    pub fn reload_on(&self, watcher: ChangeWatcher) -> Receiver<Error> {
+        let (errors, receiver) = mpsc::channel();
+        let inner = Arc::downgrade(&self.inner);
+        thread::spawn(move || loop {
+            let changed = watcher.wait(Some(DROPPED_CHECK_INTERVAL));
+            let Some(inner) = inner.upgrade() else {
+                return;
+            };
+            let error = match changed {
+                Ok(false) => continue,
+                Ok(true) => match (Self { inner }).reload() {
+                    Ok(_) => continue,
+                    Err(e) => e,
+                },
+                Err(e) => {
+                    let _ = errors.send(e);
+                    return;
+                }
+            };
+            if errors.send(error).is_err() {
+                return;
+            }
+        });
+        receiver
+    }
+
+    /// Calls `callback` with the value at `path`, such as `website.port` or `servers[0].host`,
+    /// each time a reload changes it, or with `None` when a reload removes it. The callback is
+    /// called on the thread performing the reload, until the returned [`Subscription`] is
+    /// dropped.
+    pub fn subscribe<F>(&self, path: &str, callback: F) -> Subscription
+    where
+        F: Fn(Option<&Value>) + Send + Sync + 'static,
+    {
+        let path = split_path(path)
+            .into_iter()
+            .map(ToString::to_string)
+            .collect();
+        self.add_subscriber(Callback::Path(path, Box::new(callback)))
+    }
+
+    /// Calls `callback` with the values each reload adds, removes or modifies, so they can be
+    /// logged or reacted to, until the returned [`Subscription`] is dropped. The values of
+    /// secrets are [redacted](Self::redact).
+    pub fn on_change<F>(&self, callback: F) -> Subscription
+    where
+        F: Fn(&[ConfigChange]) + Send + Sync + 'static,
+    {
+        self.add_subscriber(Callback::Changes(Box::new(callback)))
+    }
+
+    /// Redacts the values at `path`, and everything beneath it, in the changes reported by
+    /// [`on_change`](Self::on_change), on top of the keys that name secrets, such as
+    /// `password`. Paths use the same dotted form as the changes, and a `*` matches any run of
+    /// characters within a segment, e.g. `database.*_url`.
+    #[must_use]
+    pub fn redact(self, path: impl Into<String>) -> Self {
+        self.inner
+            .redactions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(path.into());
+        self
+    }
+
+    fn add_subscriber(&self, callback: Callback) -> Subscription {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let subscriber = Subscriber { id, callback };
+        self.inner
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Arc::new(subscriber));
+        Subscription {
+            subscribers: Arc::downgrade(&self.inner.subscribers),
+            id,
+        }
+    }
+
+    /// Calls `callback` with the section at `path`, such as `logger`, deserialized into `T`,
+    /// each time a reload changes it, as [`subscribe`](Self::subscribe) does. A removed section
+    /// is deserialized from `null`, so `T` may be an `Option`. Changes that `T` cannot be
+    /// deserialized from are not reported.
+    pub fn subscribe_section<T, F>(&self, path: &str, callback: F) -> Subscription
+    where
+        T: DeserializeOwned,
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        self.subscribe(path, move |value| {
+            if let Ok(section) = T::deserialize(value.unwrap_or(&Value::Null)) {
+                callback(section);
+            }
+        })
+    }
+
+    /// Returns a receiver that is marked changed each time a reload swaps in a new
+    /// configuration, so asynchronous code can `select!` on reloads alongside its other work.
+    /// Wrap it in `tokio_stream::wrappers::WatchStream` for a `Stream` of configurations.
+    #[cfg(feature = "reload-tokio")]
+    #[must_use]
+    pub fn watch(&self) -> watch::Receiver<Arc<C>> {
+        self.inner.sender.subscribe()
+    }
+
+    /// Makes `config` current if it passes the validators and was loaded from a different
+    /// `expanded` value than the current configuration, returning the configuration that is
+    /// current afterwards. Subscribers are only notified of changes that show when the
+    /// configurations are serialized.
+    fn accept(&self, config: C, expanded: Value) -> Result<Arc<C>, Error> {
+        for validator in self
+            .inner
+            .validators
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            validator(&config)?;
+        }
+        let mut current_expanded = self
+            .inner
+            .expanded
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current = self.snapshot();
+        if *current_expanded == expanded {
+            return Ok(Arc::clone(&current.config));
+        }
+        let values = serde_json::to_value(&*current.config)
+            .and_then(|current| Ok((current, serde_json::to_value(&config)?)))
+            .ok()
+            .filter(|(previous, config)| previous != config);
+        let config = Arc::new(config);
+        self.inner.snapshot.store(Arc::new(Snapshot {
+            config: Arc::clone(&config),
+            generation: current.generation + 1,
+            loaded_at: SystemTime::now(),
+        }));
+        #[cfg(feature = "reload-tokio")]
+        self.inner.sender.send_replace(Arc::clone(&config));
+        *current_expanded = expanded;
+        drop(current_expanded);
+        if let Some((previous, config)) = values {
+            self.notify(&previous, &config);
+        }
+        Ok(config)
+    }
+
+    /// Notifies the subscribers of the differences between `previous` and `config`.
+    fn notify(&self, previous: &Value, config: &Value) {
+        let subscribers = self
+            .inner
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let mut changes = None;
+        for subscriber in subscribers {
+            match &subscriber.callback {
+                Callback::Path(path, callback) => {
+                    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+                    let value = get_value_from_path(&path, config);
+                    if get_value_from_path(&path, previous) != value {
+                        callback(value);
+                    }
+                }
+                Callback::Changes(callback) => {
+                    let changes = changes.get_or_insert_with(|| {
+                        let redactions = self
+                            .inner
+                            .redactions
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner);
+                        diff(previous, config, &redactions)
+                    });
+                    callback(changes);
+                }
+            }
+        }
+    }
+
+    /// Reloads the configuration when the files in its directory change, as
+    /// [`reload_on`](Self::reload_on) does. Changes are [debounced](ChangeWatcher::debounce)
+    /// over `debounce`, so several files written in quick succession cause one reload.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration directory cannot be found or watched.
+    #[cfg(feature = "file-watch")]
+    pub fn reload_on_file_changes(&self, debounce: Duration) -> Result<Receiver<Error>, Error> {
+        let dir = self.inner.builder.resolve_config_dir()?;
+        Ok(self.reload_on(ChangeWatcher::files(&dir)?.debounce(debounce)))
+    }
+
+    /// Reloads the configuration every
+    /// [`refresh_interval`](GraftonConfigBuilder::refresh_interval), or more often if a source
+    /// declares a shorter [`refresh_interval`](crate::ConfigSource::refresh_interval), as
+    /// [`reload_on`](Self::reload_on) does. Reloads that fetch the same content as before leave
+    /// the configuration as it is, so subscribers only hear of actual changes. The timer stops
+    /// along with the reloading thread.
+    ///
+    /// Returns `None` if neither the builder nor its sources set an interval.
+    #[must_use]
+    pub fn refresh_periodically(&self) -> Option<Receiver<Error>> {
+        let interval = self.inner.builder.shortest_refresh_interval()?;
+        Some(self.reload_on(ChangeWatcher::every(interval)))
+    }
+}
+
+/// A configuration held by a [`SharedConfig`], as returned by [`SharedConfig::snapshot`], so
+/// that logs and metrics can name the configuration in effect.
+#[derive(Debug)]
+pub struct Snapshot<C> {
+    /// The configuration.
+    pub config: Arc<C>,
+    /// Numbers the configurations the handle has held, from 1 for the one first loaded. Each
+    /// reload that changes the configuration increments it.
+    pub generation: u64,
+    /// When the configuration was loaded.
+    pub loaded_at: SystemTime,
+}
+
+fn rejected(message: impl std::fmt::Display) -> Error {
+    Error::ConfigError(format!("configuration rejected by validator: {message}"))
+}
+
+/// A subscription to part of a [`SharedConfig`], which ends when it is dropped.
+#[must_use = "the subscription ends when it is dropped"]
+pub struct Subscription {
+    subscribers: Weak<Subscribers>,
+    id: u64,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            subscribers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|subscriber| subscriber.id != self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::ValidationError;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct SharedTestConfig {
+        port: u16,
+        #[serde(default)]
+        logger: Option<LoggerConfig>,
+        #[serde(default)]
+        hosts: Vec<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct LoggerConfig {
+        level: String,
+    }
+
+    impl TokenExpandingConfig for SharedTestConfig {}
+
+    impl ValidatableConfig for SharedTestConfig {
+        fn validate(&self) -> Result<(), Vec<ValidationError>> {
+            if self.port == 0 {
+                Err(vec![ValidationError::new("port", "must not be 0")])
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn load(dir: &std::path::Path) -> SharedConfig<SharedTestConfig> {
+        GraftonConfigBuilder::new()
+            .dir(dir)
+            .without_run_mode()
+            .load_shared()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_shared_config_reload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+
+        let shared = load(dir.path());
+        let snapshot = shared.current();
+        assert_eq!(snapshot.port, 80);
+
+        fs::write(&path, "port = 8080\n").unwrap();
+        let reader = shared.clone();
+        thread::spawn(move || reader.reload().unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(shared.current().port, 8080);
+        assert_eq!(snapshot.port, 80);
+
+        fs::write(&path, "port = \"not a port\"\n").unwrap();
+        assert!(shared.reload().is_err());
+        assert_eq!(shared.current().port, 8080);
+    }
+
+    #[test]
+    fn test_shared_config_generations() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+        let shared = load(dir.path());
+        let first = shared.snapshot();
+        assert_eq!(first.generation, 1);
+
+        shared.reload().unwrap();
+        fs::write(&path, "port = \"not a port\"\n").unwrap();
+        assert!(shared.reload().is_err());
+        assert_eq!(shared.generation(), 1);
+
+        fs::write(&path, "port = 81\n").unwrap();
+        shared.reload().unwrap();
+        let second = shared.snapshot();
+        assert_eq!((second.generation, second.config.port), (2, 81));
+        assert!(second.loaded_at >= first.loaded_at);
+    }
+
+    #[test]
+    fn test_shared_config_refresh_periodically() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+        assert!(load(dir.path()).refresh_periodically().is_none());
+
+        let shared: SharedConfig<SharedTestConfig> = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .refresh_interval(std::time::Duration::from_millis(10))
+            .load_shared()
+            .unwrap();
+        let unchanged = shared.current();
+        assert!(Arc::ptr_eq(&shared.reload().unwrap(), &unchanged));
+
+        let errors = shared.refresh_periodically();
+        assert!(errors.is_some());
+        fs::write(&path, "port = 81\n").unwrap();
+        for _ in 0..100 {
+            if shared.current().port == 81 {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(shared.current().port, 81);
+        drop(errors);
+
+        let shared: SharedConfig<SharedTestConfig> = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .refresh_interval(std::time::Duration::from_secs(3600))
+            .load_shared()
+            .unwrap();
+        let inner = Arc::downgrade(&shared.inner);
+        let errors = shared.refresh_periodically().unwrap();
+        drop(shared);
+        assert!(errors.recv().is_err());
+        assert!(inner.upgrade().is_none());
+    }
+
+    #[cfg(feature = "file-watch")]
+    #[test]
+    fn test_shared_config_reload_on_file_changes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+        let shared = load(dir.path());
+        let reloads = Arc::new(AtomicU64::new(0));
+        let _subscription = shared.subscribe("port", {
+            let reloads = Arc::clone(&reloads);
+            move |_| {
+                reloads.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let _errors = shared
+            .reload_on_file_changes(Duration::from_millis(200))
+            .unwrap();
+
+        fs::create_dir(dir.path().join("conf.d")).unwrap();
+        fs::write(dir.path().join("conf.d/10-port.toml"), "port = 81\n").unwrap();
+        fs::write(&path, "port = 8080\n").unwrap();
+        fs::write(dir.path().join("conf.d/20-port.toml"), "port = 82\n").unwrap();
+        for _ in 0..200 {
+            if shared.current().port == 82 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(shared.current().port, 82);
+        assert_eq!(reloads.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_shared_config_on_change() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n[logger]\nlevel = \"info\"\n").unwrap();
+        let shared = load(dir.path()).redact("logger.level");
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let _subscription = shared.on_change({
+            let changes = Arc::clone(&changes);
+            move |reloaded| changes.lock().unwrap().push(reloaded.to_vec())
+        });
+        shared.reload().unwrap();
+        fs::write(&path, "port = 81\n[logger]\nlevel = \"debug\"\n").unwrap();
+        shared.reload().unwrap();
+
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [[
+                ConfigChange::Modified {
+                    path: "logger.level".to_string(),
+                    old: Value::from(crate::REDACTED),
+                    new: Value::from(crate::REDACTED),
+                },
+                ConfigChange::Modified {
+                    path: "port".to_string(),
+                    old: Value::from(80),
+                    new: Value::from(81),
+                },
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_shared_config_validators() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+        let privileged = |config: &SharedTestConfig| {
+            if config.port < 1024 {
+                Ok(())
+            } else {
+                Err(format!("port {} is not privileged", config.port))
+            }
+        };
+        let shared = load(dir.path()).with_validator(privileged).unwrap();
+
+        fs::write(&path, "port = 8080\n").unwrap();
+        let error = shared.reload().unwrap_err().to_string();
+        assert!(error.contains("port 8080 is not privileged"), "{error}");
+        assert_eq!(shared.current().port, 80);
+
+        fs::write(&path, "port = 443\n").unwrap();
+        assert_eq!(shared.reload().unwrap().port, 443);
+        assert!(shared
+            .with_validator(|config| if config.port == 80 {
+                Ok(())
+            } else {
+                Err("not 80".to_string())
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_shared_config_validated() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+        let shared = load(dir.path()).validated().unwrap();
+
+        fs::write(&path, "port = 0\n").unwrap();
+        assert!(matches!(shared.reload(), Err(Error::ValidationFailed(_))));
+        assert_eq!(shared.current().port, 80);
+    }
+
+    #[cfg(feature = "reload-tokio")]
+    #[test]
+    fn test_shared_config_watch() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+        let shared = load(dir.path());
+        let mut receiver = shared.watch();
+        assert_eq!(receiver.borrow_and_update().port, 80);
+
+        fs::write(&path, "port = 81\n").unwrap();
+        let reload = thread::spawn(move || shared.reload().unwrap());
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(receiver.changed())
+            .unwrap();
+        assert_eq!(receiver.borrow_and_update().port, 81);
+        reload.join().unwrap();
+    }
+
+    #[cfg(all(unix, feature = "sighup"))]
+    #[test]
+    fn test_shared_config_reload_on_sighup() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+        let shared = load(dir.path());
+        let errors = shared.reload_on(ChangeWatcher::sighup().unwrap());
+
+        fs::write(&path, "port = 81\n").unwrap();
+        signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+        for _ in 0..100 {
+            if shared.current().port == 81 {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(shared.current().port, 81);
+
+        fs::write(&path, "port = \"not a port\"\n").unwrap();
+        signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+        assert!(matches!(errors.recv().unwrap(), Error::InvalidValue { .. }));
+        assert_eq!(shared.current().port, 81);
+    }
+
+    #[test]
+    fn test_shared_config_subscriptions() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n[logger]\nlevel = \"info\"\n").unwrap();
+        let shared = load(dir.path());
+
+        let ports = Arc::new(Mutex::new(Vec::new()));
+        let port_subscription = shared.subscribe("port", {
+            let ports = Arc::clone(&ports);
+            move |value| ports.lock().unwrap().push(value.cloned())
+        });
+        let loggers = Arc::new(Mutex::new(Vec::new()));
+        let _logger_subscription = shared.subscribe_section::<Option<LoggerConfig>, _>("logger", {
+            let loggers = Arc::clone(&loggers);
+            move |logger| loggers.lock().unwrap().push(logger)
+        });
+
+        shared.reload().unwrap();
+        fs::write(&path, "port = 81\n[logger]\nlevel = \"info\"\n").unwrap();
+        shared.reload().unwrap();
+        fs::write(&path, "port = 81\n[logger]\nlevel = \"debug\"\n").unwrap();
+        shared.reload().unwrap();
+        drop(port_subscription);
+        fs::write(&path, "port = 82\n").unwrap();
+        shared.reload().unwrap();
+
+        assert_eq!(*ports.lock().unwrap(), [Some(Value::from(81))]);
+        assert_eq!(
+            *loggers.lock().unwrap(),
+            [
+                Some(LoggerConfig {
+                    level: "debug".to_string()
+                }),
+                None
+            ]
+        );
+    }
+    #[test]
+    fn test_shared_config_reloads_skipped_fields() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Credentials {
+            user: String,
+            #[serde(skip_serializing)]
+            password: String,
+        }
+
+        impl TokenExpandingConfig for Credentials {}
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "user = \"app\"\npassword = \"old\"\n").unwrap();
+        let shared: SharedConfig<Credentials> = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .without_run_mode()
+            .load_shared()
+            .unwrap();
+        let changes = Arc::new(AtomicU64::new(0));
+        let _subscription = shared.on_change({
+            let changes = Arc::clone(&changes);
+            move |_| {
+                changes.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        shared.reload().unwrap();
+        assert_eq!(shared.generation(), 1);
+
+        fs::write(&path, "user = \"app\"\npassword = \"new\"\n").unwrap();
+        shared.reload().unwrap();
+        assert_eq!(shared.generation(), 2);
+        assert_eq!(shared.current().password, "new");
+        assert_eq!(changes.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_shared_config_reload_on_stops() {
+        let stopped = |sender: &mpsc::Sender<Result<(), String>>| {
+            (0..100).any(|_| {
+                thread::sleep(Duration::from_millis(10));
+                sender.send(Ok(())).is_err()
+            })
+        };
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\n").unwrap();
+        let shared = load(dir.path());
+        let inner = Arc::downgrade(&shared.inner);
+
+        let (sender, watcher) = ChangeWatcher::channel("test changes");
+        let errors = shared.reload_on(watcher);
+        drop(shared);
+        assert!(stopped(&sender));
+        assert!(inner.upgrade().is_none());
+        assert!(errors.recv().is_err());
+
+        let shared = load(dir.path());
+        let (sender, watcher) = ChangeWatcher::channel("test changes");
+        drop(shared.reload_on(watcher));
+        fs::write(&path, "port = 81\n").unwrap();
+        sender.send(Ok(())).unwrap();
+        while shared.current().port != 81 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        fs::write(&path, "port = \"not a port\"\n").unwrap();
+        assert!(stopped(&sender));
+        assert_eq!(shared.current().port, 81);
+    }
+
+    #[test]
+    fn test_shared_config_subscribe_indices() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("default.toml");
+        fs::write(&path, "port = 80\nhosts = [\"a\", \"b\"]\n").unwrap();
+        let shared = load(dir.path());
+
+        let hosts = Arc::new(Mutex::new(Vec::new()));
+        let _subscriptions = ["hosts[1]", "hosts.1"].map(|path| {
+            shared.subscribe(path, {
+                let hosts = Arc::clone(&hosts);
+                move |value| hosts.lock().unwrap().push(value.cloned())
+            })
+        });
+
+        fs::write(&path, "port = 80\nhosts = [\"a\", \"c\"]\n").unwrap();
+        shared.reload().unwrap();
+
+        assert_eq!(
+            *hosts.lock().unwrap(),
+            [Some(Value::from("c")), Some(Value::from("c"))]
+        );
+    }
+}
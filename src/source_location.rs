@@ -0,0 +1,237 @@
+#[cfg(feature = "miette")]
+use std::sync::Arc;
+use std::{fs, ops::Range, path::Path};
+
+use figment::{value::Value as FigmentValue, Figment, Metadata};
+use serde_json::Value;
+use toml_edit::{ImDocument, Item};
+
+use crate::{Error, SourceLocation};
+
+/// Describes the failure of the file `file` to parse, as reported by figment in `error`,
+/// with the line and column of the problem when its format reports them.
+pub fn parse_error(file: &Path, error: &figment::Error) -> Error {
+    let contents = fs::read_to_string(file).ok();
+    let text = contents.as_deref().unwrap_or_default();
+    let (span, message) = match extension(file).as_deref() {
+        Some("toml") => ImDocument::parse(text).err().map_or((None, None), |e| {
+            (
+                e.span(),
+                Some(e.message().trim().lines().collect::<Vec<_>>().join(": ")),
+            )
+        }),
+        Some("json") => serde_json::from_str::<Value>(text)
+            .err()
+            .map_or((None, None), |e| {
+                let message = e.to_string();
+                let message = message
+                    .rsplit_once(" at line ")
+                    .map_or(message.as_str(), |(message, _)| message)
+                    .to_string();
+                (span_at(text, e.line(), e.column()), Some(message))
+            }),
+        _ => (None, None),
+    };
+
+    Error::ParseError {
+        location: Box::new(source_location(file, contents.as_deref(), span)),
+        message: message.unwrap_or_else(|| error.kind.to_string()),
+    }
+}
+
+/// Adds the file and position of the offending value to an [`Error::InvalidValue`], looking up
+/// the layer of `figment` that set it.
+pub fn locate(error: Error, figment: &Figment) -> Error {
+    match error {
+        Error::InvalidValue {
+            path,
+            message,
+            location: None,
+        } => {
+            let location = find_metadata(figment, &path)
+                .and_then(|metadata| metadata.source.as_ref())
+                .and_then(|source| source.file_path())
+                .map(|file| Box::new(value_location(file, &path)));
+            Error::InvalidValue {
+                path,
+                message,
+                location,
+            }
+        }
+        error => error,
+    }
+}
+
+/// Finds the metadata of the provider that set the value at `path` in `figment`, descending
+/// into arrays, which figment's own lookups do not.
+fn find_metadata<'a>(figment: &'a Figment, path: &str) -> Option<&'a Metadata> {
+    let key = figment_key(path);
+    let mut segments = key.split('.');
+    let mut value = figment.find_value(segments.next()?).ok()?;
+    for segment in segments {
+        value = match value {
+            FigmentValue::Dict(_, mut dict) => dict.remove(segment)?,
+            FigmentValue::Array(_, items) => items.into_iter().nth(segment.parse().ok()?)?,
+            _ => return None,
+        };
+    }
+    figment.get_metadata(value.tag())
+}
+
+/// Finds the value at `path`, e.g. `servers[0].port`, in the file `file`. Positions are only
+/// found in TOML files.
+fn value_location(file: &Path, path: &str) -> SourceLocation {
+    let contents = fs::read_to_string(file).ok();
+    let span = contents
+        .as_deref()
+        .filter(|_| extension(file).as_deref() == Some("toml"))
+        .and_then(|contents| {
+            let document = ImDocument::parse(contents).ok()?;
+            figment_key(path)
+                .split('.')
+                .try_fold(document.as_item(), |item, segment| {
+                    match (item, segment.parse::<usize>()) {
+                        (Item::ArrayOfTables(_) | Item::Value(_), Ok(index)) => item.get(index),
+                        _ => item.get(segment),
+                    }
+                })?
+                .span()
+        });
+
+    source_location(file, contents.as_deref(), span)
+}
+
+/// Locates `span` within `contents`, the contents of `file`.
+fn source_location(
+    file: &Path,
+    contents: Option<&str>,
+    span: Option<Range<usize>>,
+) -> SourceLocation {
+    let position = contents
+        .zip(span.as_ref())
+        .map(|(contents, span)| line_and_column(contents, span.start));
+    SourceLocation {
+        file: file.to_path_buf(),
+        line: position.map(|(line, _)| line),
+        column: position.map(|(_, column)| column),
+        span,
+        #[cfg(feature = "miette")]
+        contents: contents.map(Arc::from),
+    }
+}
+
+/// Converts a path such as `servers[0].port` into the dotted form figment looks keys up by.
+fn figment_key(path: &str) -> String {
+    path.replace('[', ".").replace(']', "")
+}
+
+fn extension(file: &Path) -> Option<String> {
+    file.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+/// The span of the character at `line` and `column`, counting from 1, in `contents`.
+fn span_at(contents: &str, line: usize, column: usize) -> Option<Range<usize>> {
+    let line_start = if line <= 1 {
+        0
+    } else {
+        contents.match_indices('\n').nth(line - 2)?.0 + 1
+    };
+    let (offset, character) = contents[line_start..]
+        .char_indices()
+        .nth(column.saturating_sub(1))
+        .map_or((contents.len() - line_start, None), |(offset, c)| {
+            (offset, Some(c))
+        });
+    let start = line_start + offset;
+    Some(start..start + character.map_or(0, char::len_utf8))
+}
+
+/// The line and column, counting from 1, of the byte `offset` into `contents`.
+fn line_and_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = contents.get(..offset).unwrap_or(contents);
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count())
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use figment::providers::{Format, Toml};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn write(file: &Path, contents: &str) {
+        fs::File::create(file)
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("default.toml");
+        write(&file, "name = \"app\"\nport = = 8080\n");
+
+        let error = Figment::from(Toml::file(&file))
+            .extract::<Value>()
+            .unwrap_err();
+        match parse_error(&file, &error) {
+            Error::ParseError { location, message } => {
+                assert_eq!((location.line, location.column), (Some(2), Some(8)));
+                assert_eq!(location.to_string(), format!("{}:2:8", file.display()));
+                assert_eq!(message, "invalid string: expected `\"`, `'`");
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+
+        let file = dir.path().join("default.json");
+        write(&file, "{\n  \"port\": 8080,\n}\n");
+        let error = Figment::from(figment::providers::Json::file(&file))
+            .extract::<Value>()
+            .unwrap_err();
+        match parse_error(&file, &error) {
+            Error::ParseError { location, message } => {
+                assert_eq!((location.line, location.column), (Some(3), Some(1)));
+                assert_eq!(location.span, Some(18..19));
+                assert_eq!(message, "trailing comma");
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_locate_invalid_value() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("default.toml");
+        write(
+            &file,
+            "[website]\nport = \"eighty\"\n\n[[servers]]\nname = \"a\"\n\n[[servers]]\nname = 2\n",
+        );
+        let figment = Figment::from(Toml::file(&file));
+
+        let located = |path: &str| match locate(
+            Error::InvalidValue {
+                path: path.to_string(),
+                message: "invalid type".to_string(),
+                location: None,
+            },
+            &figment,
+        ) {
+            Error::InvalidValue { location, .. } => location.map(|l| (l.line, l.column)),
+            other => panic!("expected an invalid value, got {other:?}"),
+        };
+        assert_eq!(located("website.port"), Some((Some(2), Some(8))));
+        assert_eq!(located("servers[1].name"), Some((Some(8), Some(8))));
+        assert_eq!(located("website.missing"), None);
+    }
+}
@@ -0,0 +1,217 @@
+use std::time::Duration;
+
+use serde_json::Value;
+use sqlx::{any::AnyPoolOptions, AnyPool, Row};
+
+use crate::{config_loader::insert_nested, BoxFuture, ConfigSource, Error};
+
+/// The query a [`SqlSource`] runs unless given another with [`SqlSource::query`].
+pub const DEFAULT_SQL_QUERY: &str = "SELECT key, value FROM settings";
+
+/// A [`ConfigSource`] that loads key/value rows from a database table, for settings that
+/// operators edit through an admin interface backed by the database.
+///
+/// The query's first column is the key and its second the value, both text, so with the
+/// default key separator of `.`, the row `database.port`, `5432` sets `database.port`. Values
+/// are read as strings, which are coerced into typed fields on load, and `NULL` values are
+/// skipped. Rows are applied in the order the query returns them, so a later row for a key
+/// overrides an earlier one.
+///
+/// The pool is an [`AnyPool`], so the driver is chosen by its URL from those enabled with the
+/// `sql-sqlite`, `sql-postgres` and `sql-mysql` features.
+pub struct SqlSource {
+    pool: AnyPool,
+    query: String,
+    key_separator: String,
+    priority: i32,
+    refresh_interval: Option<Duration>,
+}
+
+impl SqlSource {
+    #[must_use]
+    pub fn new(pool: AnyPool) -> Self {
+        Self {
+            pool,
+            query: DEFAULT_SQL_QUERY.to_string(),
+            key_separator: ".".to_string(),
+            priority: 0,
+            refresh_interval: None,
+        }
+    }
+
+    /// Creates a source for the database at `url`, such as `postgres://localhost/app`, which
+    /// is connected to when first loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ExternalError`] if `url` is invalid. A driver that is not enabled is
+    /// reported when the source is loaded.
+    pub fn connect_lazy(url: &str) -> Result<Self, Error> {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect_lazy(url)
+            .map_err(|e| Error::ExternalError {
+                context: "invalid database URL".to_string(),
+                source: Box::new(e),
+            })?;
+        Ok(Self::new(pool))
+    }
+
+    /// Sets the query that selects the key and value columns, e.g.
+    /// `SELECT name, setting FROM app_settings WHERE enabled ORDER BY updated_at`.
+    #[must_use]
+    pub fn query(mut self, query: impl Into<String>) -> Self {
+        self.query = query.into();
+        self
+    }
+
+    /// Splits keys on `separator` into paths, e.g. `__` or `/`, rather than on `.`.
+    #[must_use]
+    pub fn key_separator(mut self, separator: impl Into<String>) -> Self {
+        self.key_separator = separator.into();
+        self
+    }
+
+    /// Sets the source's priority among sources, see [`ConfigSource::priority`].
+    #[must_use]
+    pub const fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Refetches the table every `interval`, see [`ConfigSource::refresh_interval`].
+    #[must_use]
+    pub const fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = Some(interval);
+        self
+    }
+
+    async fn fetch(&self) -> Result<Value, sqlx::Error> {
+        let rows = sqlx::query(&self.query).fetch_all(&self.pool).await?;
+
+        let mut root = serde_json::Map::new();
+        for row in rows {
+            let key: String = row.try_get(0)?;
+            let Some(value) = row.try_get::<Option<String>, _>(1)? else {
+                continue;
+            };
+            let segments: Vec<String> = key
+                .split(self.key_separator.as_str())
+                .filter(|segment| !segment.is_empty())
+                .map(ToString::to_string)
+                .collect();
+            if !segments.is_empty() {
+                insert_nested(&mut root, &segments, Value::String(value));
+            }
+        }
+        Ok(Value::Object(root))
+    }
+}
+
+impl ConfigSource for SqlSource {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval
+    }
+
+    fn load(&self) -> BoxFuture<'_, Result<Value, Error>> {
+        Box::pin(async {
+            self.fetch().await.map_err(|e| Error::ExternalError {
+                context: "unable to load configuration from the database".to_string(),
+                source: Box::new(e),
+            })
+        })
+    }
+}
+
+#[cfg(all(test, feature = "sql-sqlite"))]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{config_source::block_on, GraftonConfigBuilder, TokenExpandingConfig};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Database {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct SqlConfig {
+        name: String,
+        url: String,
+        database: Database,
+    }
+
+    impl TokenExpandingConfig for SqlConfig {}
+
+    fn database(dir: &std::path::Path, statements: &[&str]) -> String {
+        let url = format!("sqlite://{}?mode=rwc", dir.join("settings.db").display());
+        let source = SqlSource::connect_lazy(&url).unwrap();
+        block_on(async {
+            for statement in statements {
+                sqlx::query(statement).execute(&source.pool).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_sql_source() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\nurl = \"http://${database.host}:${database.port}\"\n\n\
+             [database]\nhost = \"localhost\"\nport = 5432\n",
+        )
+        .unwrap();
+        let url = database(
+            dir.path(),
+            &[
+                "CREATE TABLE settings (key TEXT, value TEXT)",
+                "INSERT INTO settings VALUES ('database.host', 'db.internal'), \
+                 ('database.port', '6432'), ('name', NULL)",
+            ],
+        );
+
+        let config: SqlConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .source(SqlSource::connect_lazy(&url).unwrap())
+            .load()
+            .unwrap();
+        assert_eq!(config.name, "app");
+        assert_eq!(config.database.port, 6432);
+        assert_eq!(config.url, "http://db.internal:6432");
+
+        let source = SqlSource::connect_lazy(&url)
+            .unwrap()
+            .query("SELECT 'database/port', '7000'")
+            .key_separator("/");
+        assert_eq!(
+            block_on(source.load()).unwrap(),
+            serde_json::json!({ "database": { "port": "7000" } })
+        );
+    }
+
+    #[test]
+    fn test_sql_source_errors() {
+        let dir = tempdir().unwrap();
+        let url = database(dir.path(), &[]);
+        let source = SqlSource::connect_lazy(&url).unwrap();
+        let error = block_on(source.load()).unwrap_err();
+        assert!(matches!(error, Error::ExternalError { .. }), "{error:?}");
+        let source = std::error::Error::source(&error).unwrap();
+        assert!(source.downcast_ref::<sqlx::Error>().is_some());
+        assert!(SqlSource::connect_lazy("not a url").is_err());
+    }
+}
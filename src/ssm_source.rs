@@ -0,0 +1,177 @@
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use serde_json::{json, Value};
+
+use crate::{config_loader::insert_nested, AwsClient};
+
+/// A configuration layer read from the parameters under a path in AWS Systems Manager
+/// Parameter Store, merged with
+/// [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// The path is removed from each parameter's name and the rest split on `/`, so with a path of
+/// `/myapp/`, the parameter `/myapp/database/password` sets `database.password`. `SecureString`
+/// values are decrypted. Values are strings, which are coerced into typed fields when the
+/// configuration is deserialized.
+pub struct SsmSource {
+    client: AwsClient,
+    path: String,
+}
+
+impl SsmSource {
+    #[must_use]
+    pub fn new(client: AwsClient, path: impl Into<String>) -> Self {
+        Self {
+            client,
+            path: path.into(),
+        }
+    }
+
+    /// Reads the parameters under the path into a table, following each page of results.
+    fn read(&self) -> Result<Value, String> {
+        let mut root = serde_json::Map::new();
+        let mut next_token = None;
+        loop {
+            let mut request = json!({
+                "Path": self.path,
+                "Recursive": true,
+                "WithDecryption": true,
+            });
+            if let Some(token) = next_token {
+                request["NextToken"] = token;
+            }
+
+            let mut response = self
+                .client
+                .call_json("ssm", "AmazonSSM.GetParametersByPath", &request)
+                .map_err(|e| format!("unable to read SSM parameters under {}: {e}", self.path))?;
+
+            for parameter in response["Parameters"].as_array().into_iter().flatten() {
+                let name = parameter["Name"].as_str().unwrap_or_default();
+                let segments: Vec<String> = name
+                    .strip_prefix(&self.path)
+                    .unwrap_or(name)
+                    .split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .map(ToString::to_string)
+                    .collect();
+                insert_nested(&mut root, &segments, parameter["Value"].clone());
+            }
+
+            match response["NextToken"].take() {
+                Value::String(token) if !token.is_empty() => {
+                    next_token = Some(Value::String(token));
+                }
+                _ => return Ok(Value::Object(root)),
+            }
+        }
+    }
+}
+
+impl Provider for SsmSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("SSM Parameter Store").source(Source::Custom(self.path.clone()))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        Serialized::defaults(self.read()?).data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        test_server::{ok, serve, status},
+        AwsCredentials, GraftonConfigBuilder, TokenExpandingConfig,
+    };
+
+    fn client(url: String) -> AwsClient {
+        AwsClient::new(
+            "eu-west-1",
+            AwsCredentials::new("AKID", "secret").session_token("session"),
+        )
+        .endpoint(url)
+    }
+
+    #[test]
+    fn test_ssm_source() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Database {
+            host: String,
+            port: u16,
+            password: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct SsmConfig {
+            name: String,
+            database: Database,
+        }
+
+        impl TokenExpandingConfig for SsmConfig {}
+
+        let parameter = |name: &str, kind: &str, value: &str| json!({ "Name": name, "Type": kind, "Value": value });
+        let first = json!({
+            "Parameters": [
+                parameter("/myapp/database/port", "String", "5432"),
+                parameter("/myapp/database/password", "SecureString", "hunter2"),
+            ],
+            "NextToken": "page-2",
+        });
+        let second = json!({
+            "Parameters": [parameter("/myapp/name", "String", "${database.host}")],
+        });
+        let (url, server) = serve(vec![
+            ok("application/x-amz-json-1.1", &first.to_string()),
+            ok("application/x-amz-json-1.1", &second.to_string()),
+        ]);
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\n\n[database]\nhost = \"db\"\nport = 1\npassword = \"\"\n",
+        )
+        .unwrap();
+
+        let config: SsmConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(SsmSource::new(client(url), "/myapp/"))
+            .load()
+            .unwrap();
+
+        assert_eq!(config.database.port, 5432);
+        assert_eq!(config.database.password, "hunter2");
+        assert_eq!(config.name, "db");
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].line, "POST / HTTP/1.1");
+        assert!(requests[0].has_header("x-amz-target: AmazonSSM.GetParametersByPath"));
+        assert!(requests[0].has_header("x-amz-security-token: session"));
+        assert!(requests[0].headers.iter().any(|header| header
+            .starts_with("Authorization: AWS4-HMAC-SHA256 Credential=AKID/")
+            && header.contains("/eu-west-1/ssm/aws4_request, ")));
+
+        let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["Path"], "/myapp/");
+        assert_eq!(body["WithDecryption"], true);
+        assert!(body.get("NextToken").is_none());
+        let body: Value = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(body["NextToken"], "page-2");
+    }
+
+    #[test]
+    fn test_ssm_source_error() {
+        let (url, server) = serve(vec![status("400 Bad Request")]);
+
+        let result = SsmSource::new(client(url), "/myapp/").read();
+
+        assert!(result.unwrap_err().contains("/myapp/"));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,30 @@
+/// Finds the candidate closest to `name`, to suggest in place of what is probably a typo.
+///
+/// Only candidates within a few edits of `name` are considered, counting a swap of adjacent
+/// characters as one edit, and one per three characters of `name`, so that short names are
+/// not matched with unrelated ones.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (strsim::damerau_levenshtein(name, candidate), candidate))
+        .filter(|&(distance, _)| (1..=max_distance).contains(&distance))
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest() {
+        let fields = ["port", "hosts", "bind_address"];
+        assert_eq!(closest("prot", fields), Some("port"));
+        assert_eq!(closest("host", fields), Some("hosts"));
+        assert_eq!(closest("bind_adress", fields), Some("bind_address"));
+        assert_eq!(closest("pr", fields), None);
+        assert_eq!(closest("timeout", fields), None);
+        assert_eq!(closest("port", fields), None);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    thread,
+};
+
+/// A request received by [`serve`].
+pub struct Request {
+    pub line: String,
+    pub headers: Vec<String>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn has_header(&self, header: &str) -> bool {
+        self.headers.iter().any(|line| line == header)
+    }
+}
+
+/// Serves one canned HTTP response per entry on a local port, returning its base URL and a
+/// handle yielding the requests received.
+pub fn serve(responses: Vec<String>) -> (String, thread::JoinHandle<Vec<Request>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = thread::spawn(move || {
+        responses
+            .into_iter()
+            .map(|response| {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut lines = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_string();
+                    if line.is_empty() {
+                        break;
+                    }
+                    lines.push(line);
+                }
+
+                let length = lines
+                    .iter()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                stream.write_all(response.as_bytes()).unwrap();
+                Request {
+                    line: lines.remove(0),
+                    headers: lines,
+                    body: String::from_utf8(body).unwrap(),
+                }
+            })
+            .collect()
+    });
+    (url, handle)
+}
+
+/// A `200 OK` response with the given content type and body.
+pub fn ok(content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// An empty response with the given status line, e.g. `404 Not Found`.
+pub fn status(status: &str) -> String {
+    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+}
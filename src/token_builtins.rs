@@ -0,0 +1,520 @@
+use std::{fmt, sync::Arc};
+
+#[cfg(feature = "exec-tokens")]
+use std::time::Duration;
+
+use serde_json::Value;
+
+type BuiltinResolver = fn(&str) -> Option<Result<Value, String>>;
+
+const RESOLVERS: &[BuiltinResolver] = &[
+    resolve_sys,
+    #[cfg(feature = "chrono")]
+    resolve_time,
+];
+
+/// Resolves the tokens in a custom namespace, such as `${vault:secret/app#password}`, registered
+/// with [`ExpansionOptions::resolver`](crate::ExpansionOptions::resolver).
+pub trait TokenResolver: Send + Sync {
+    /// Resolves the part of a token after its namespace and colon, e.g. `secret/app#password`.
+    /// Returns `Ok(None)` to leave the token unresolved, so that a fallback may apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message describing why the token could not be resolved.
+    fn resolve(&self, key: &str) -> Result<Option<Value>, String>;
+}
+
+/// The custom token resolvers, by namespace.
+#[derive(Clone, Default)]
+pub struct TokenResolvers(Vec<(String, Arc<dyn TokenResolver>)>);
+
+impl TokenResolvers {
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Registers `resolver` for `namespace`, replacing any resolver already registered for it.
+    pub fn insert(&mut self, namespace: String, resolver: Arc<dyn TokenResolver>) {
+        self.0.retain(|(existing, _)| *existing != namespace);
+        self.0.push((namespace, resolver));
+    }
+
+    /// Resolves `key` if its namespace has a resolver registered.
+    pub fn resolve(&self, key: &str) -> Option<Result<Value, String>> {
+        let (namespace, rest) = key.split_once(':')?;
+        let (_, resolver) = self.0.iter().find(|(existing, _)| existing == namespace)?;
+        resolver.resolve(rest).transpose()
+    }
+}
+
+impl fmt::Debug for TokenResolvers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(namespace, _)| namespace))
+            .finish()
+    }
+}
+
+/// Resolves a built-in token that is not defined in the configuration itself. Generated values
+/// longer than `max_len` bytes are rejected before they are generated.
+///
+/// Returns `None` when `key` is not a built-in token.
+pub fn resolve_builtin(key: &str, max_len: usize) -> Option<Result<Value, String>> {
+    resolve_generated(key, max_len).or_else(|| RESOLVERS.iter().find_map(|resolve| resolve(key)))
+}
+
+/// Whether `key` is a built-in token that generates a new value each time it is resolved, such
+/// as `${uuid}`.
+pub fn is_generated(key: &str) -> bool {
+    key == "uuid" || key.starts_with("random:")
+}
+
+/// Resolves `${exec:<command>}` by running `command` and substituting its trimmed standard
+/// output. The command line is split on whitespace and run directly, without a shell.
+///
+/// Commands are only run when `allowed` is set; otherwise resolving the token is an error. A
+/// command that runs for longer than `timeout` is killed, and one that writes more than
+/// `max_len` bytes to its standard output fails, either way with an error.
+#[cfg(feature = "exec-tokens")]
+pub fn resolve_exec(
+    key: &str,
+    allowed: bool,
+    timeout: Duration,
+    max_len: usize,
+) -> Option<Result<Value, String>> {
+    let command = key.strip_prefix("exec:")?;
+    if !allowed {
+        return Some(Err(
+            "command execution tokens are disabled, enable them with `ExpansionOptions::allow_exec`"
+                .to_string(),
+        ));
+    }
+
+    let mut args = command.split_whitespace();
+    let Some(program) = args.next() else {
+        return Some(Err("no command given".to_string()));
+    };
+
+    Some(run_command(command, program, args, timeout, max_len))
+}
+
+/// How often [`run_command`] checks whether the command has exited.
+#[cfg(feature = "exec-tokens")]
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[cfg(feature = "exec-tokens")]
+fn run_command<'a>(
+    command: &str,
+    program: &str,
+    args: impl Iterator<Item = &'a str>,
+    timeout: Duration,
+    max_len: usize,
+) -> Result<Value, String> {
+    use std::{
+        process::{Command, Stdio},
+        time::Instant,
+    };
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("unable to run `{command}`: {e}"))?;
+    let stdout = read_pipe(child.stdout.take(), max_len);
+    let stderr = read_pipe(child.stderr.take(), max_len);
+
+    let timed_out = || format!("`{command}` did not finish within {timeout:?}");
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(EXEC_POLL_INTERVAL),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(timed_out());
+            }
+            Err(e) => return Err(format!("unable to wait for `{command}`: {e}")),
+        }
+    };
+
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let stdout = stdout.recv_timeout(remaining).map_err(|_| timed_out())?;
+    if stdout.len() > max_len {
+        return Err(format!(
+            "output of `{command}` exceeds the maximum output size of {max_len} bytes"
+        ));
+    }
+    if !status.success() {
+        let stderr = stderr.recv_timeout(EXEC_POLL_INTERVAL).unwrap_or_default();
+        return Err(format!(
+            "`{command}` failed with {status}: {}",
+            String::from_utf8_lossy(&stderr).trim()
+        ));
+    }
+    Ok(Value::String(
+        String::from_utf8_lossy(&stdout).trim().to_string(),
+    ))
+}
+
+/// Reads up to one byte more than `limit` from `pipe` on another thread, so that a command
+/// cannot block on a full pipe while [`run_command`] waits for it. Closing the pipe early makes
+/// a command that writes more fail rather than run on.
+#[cfg(feature = "exec-tokens")]
+fn read_pipe(
+    pipe: Option<impl std::io::Read + Send + 'static>,
+    limit: usize,
+) -> std::sync::mpsc::Receiver<Vec<u8>> {
+    use std::io::Read;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(pipe) = pipe {
+            let limit = u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1);
+            let _ = pipe.take(limit).read_to_end(&mut buf);
+        }
+        let _ = sender.send(buf);
+    });
+    receiver
+}
+
+/// Resolves `${uuid}`, a random version 4 UUID, and `${random:<charset>:<length>}`, a random
+/// string of `length` characters drawn from the `hex` or `alnum` character set, which may be at
+/// most `max_len`.
+fn resolve_generated(key: &str, max_len: usize) -> Option<Result<Value, String>> {
+    if key == "uuid" {
+        return Some(Ok(Value::String(uuid::Uuid::new_v4().to_string())));
+    }
+
+    let spec = key.strip_prefix("random:")?;
+    let Some((charset, length)) = spec.split_once(':') else {
+        return Some(Err(format!(
+            "expected `random:<charset>:<length>` but got `{key}`"
+        )));
+    };
+    let charset: &[u8] = match charset {
+        "hex" => b"0123456789abcdef",
+        "alnum" => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+        _ => return Some(Err(format!("unknown random character set `{charset}`"))),
+    };
+    let Ok(length) = length.parse::<usize>() else {
+        return Some(Err(format!("invalid random length `{length}`")));
+    };
+    Some(random_string(charset, length, max_len).map(Value::String))
+}
+
+fn random_string(charset: &[u8], length: usize, max_len: usize) -> Result<String, String> {
+    let too_large =
+        || format!("length {length} exceeds the maximum output size of {max_len} bytes");
+    if length > max_len {
+        return Err(too_large());
+    }
+    // Reject bytes beyond the largest multiple of the charset length to avoid modulo bias
+    let limit = 256 / charset.len() * charset.len();
+    let mut result = String::new();
+    result.try_reserve(length).map_err(|_| too_large())?;
+    let mut buf = [0u8; 64];
+
+    while result.len() < length {
+        getrandom::getrandom(&mut buf)
+            .map_err(|e| format!("unable to generate random data: {e}"))?;
+        result.extend(
+            buf.iter()
+                .filter(|&&b| usize::from(b) < limit)
+                .map(|&b| char::from(charset[usize::from(b) % charset.len()]))
+                .take(length - result.len()),
+        );
+    }
+
+    Ok(result)
+}
+
+/// Resolves `${env:<name>}` to the value of the environment variable `name`. An unset variable
+/// leaves the token unresolved, so that a fallback may apply.
+///
+/// Variables are only read when `allowed` is set; otherwise resolving the token is an error.
+pub fn resolve_env(key: &str, allowed: bool) -> Option<Result<Value, String>> {
+    let name = key.strip_prefix("env:")?;
+    if !allowed {
+        return Some(Err(
+            "environment variable tokens are disabled, enable them with `ExpansionOptions::allow_env_tokens`"
+                .to_string(),
+        ));
+    }
+    std::env::var(name)
+        .ok()
+        .map(|value| Ok(Value::String(value)))
+}
+
+/// Resolves `${cred:<name>}` to the contents of the systemd credential `name`, without a
+/// trailing newline, read from `$CREDENTIALS_DIRECTORY` as set up by `LoadCredential=`. The
+/// token is left unresolved when there is no such credential.
+///
+/// Like environment variables, credentials are only read when `allowed` is set.
+pub fn resolve_credential(key: &str, allowed: bool) -> Option<Result<Value, String>> {
+    let name = key.strip_prefix("cred:")?;
+    if !allowed {
+        return Some(Err(
+            "credential tokens are disabled, enable them with `ExpansionOptions::allow_env_tokens`"
+                .to_string(),
+        ));
+    }
+    let dir = std::env::var_os("CREDENTIALS_DIRECTORY")?;
+    read_credential(std::path::Path::new(&dir), name)
+}
+
+fn read_credential(dir: &std::path::Path, name: &str) -> Option<Result<Value, String>> {
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Some(Err(format!("invalid credential name `{name}`")));
+    }
+    match std::fs::read_to_string(dir.join(name)) {
+        Ok(contents) => {
+            let value = contents.strip_suffix('\n').unwrap_or(&contents);
+            Some(Ok(Value::String(value.to_string())))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => Some(Err(format!("unable to read credential `{name}`: {e}"))),
+    }
+}
+
+/// Resolves the `sys:` namespace: `${sys:hostname}`, `${sys:username}`, `${sys:pid}` and
+/// `${sys:cpus}`.
+fn resolve_sys(key: &str) -> Option<Result<Value, String>> {
+    let name = key.strip_prefix("sys:")?;
+    Some(match name {
+        "hostname" => whoami::fallible::hostname()
+            .map(Value::String)
+            .map_err(|e| format!("unable to determine hostname: {e}")),
+        "username" => whoami::fallible::username()
+            .map(Value::String)
+            .map_err(|e| format!("unable to determine username: {e}")),
+        "pid" => Ok(Value::from(std::process::id())),
+        "cpus" => std::thread::available_parallelism()
+            .map(|cpus| Value::from(cpus.get()))
+            .map_err(|e| format!("unable to determine CPU count: {e}")),
+        _ => Err(format!("unknown system value `{name}`")),
+    })
+}
+
+/// Resolves `${now_unix}` and `${now:<format>}`, where `<format>` is a `strftime` style format
+/// string applied to the current UTC time.
+#[cfg(feature = "chrono")]
+fn resolve_time(key: &str) -> Option<Result<Value, String>> {
+    use chrono::{
+        format::{Item, StrftimeItems},
+        Utc,
+    };
+
+    if key == "now_unix" {
+        return Some(Ok(Value::from(Utc::now().timestamp())));
+    }
+
+    let format = key.strip_prefix("now:")?;
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Some(Err(format!("invalid date/time format `{format}`")));
+    }
+    Some(Ok(Value::String(
+        Utc::now().format_with_items(items.into_iter()).to_string(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_builtin() {
+        assert!(resolve_builtin("not_a_builtin", usize::MAX).is_none());
+    }
+
+    #[test]
+    fn test_token_resolvers() {
+        struct Upper;
+
+        impl TokenResolver for Upper {
+            fn resolve(&self, key: &str) -> Result<Option<Value>, String> {
+                match key {
+                    "" => Err("empty key".to_string()),
+                    "missing" => Ok(None),
+                    key => Ok(Some(Value::String(key.to_uppercase()))),
+                }
+            }
+        }
+
+        let mut resolvers = TokenResolvers::new();
+        resolvers.insert("upper".to_string(), Arc::new(Upper));
+
+        assert_eq!(
+            resolvers.resolve("upper:name"),
+            Some(Ok(Value::String("NAME".to_string())))
+        );
+        assert_eq!(resolvers.resolve("upper:missing"), None);
+        assert!(resolvers.resolve("upper:").unwrap().is_err());
+        assert_eq!(resolvers.resolve("lower:name"), None);
+        assert_eq!(resolvers.resolve("upper"), None);
+        assert_eq!(format!("{resolvers:?}"), r#"["upper"]"#);
+    }
+
+    #[test]
+    fn test_env_builtin() {
+        std::env::set_var("GRAFTON_CONFIG_TEST_ENV_BUILTIN", "from env");
+        assert_eq!(
+            resolve_env("env:GRAFTON_CONFIG_TEST_ENV_BUILTIN", true).unwrap(),
+            Ok(Value::String("from env".to_string()))
+        );
+        assert!(resolve_env("env:GRAFTON_CONFIG_TEST_UNSET", true).is_none());
+        assert!(resolve_env("env:GRAFTON_CONFIG_TEST_ENV_BUILTIN", false)
+            .unwrap()
+            .is_err());
+        assert!(resolve_env("sys:pid", true).is_none());
+    }
+
+    #[test]
+    fn test_credential_builtin() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("db-password"), "hunter2\n").unwrap();
+
+        assert_eq!(
+            read_credential(dir.path(), "db-password").unwrap(),
+            Ok(Value::String("hunter2".to_string()))
+        );
+        assert!(read_credential(dir.path(), "missing").is_none());
+        assert!(read_credential(dir.path(), "../db-password")
+            .unwrap()
+            .is_err());
+
+        std::env::set_var("CREDENTIALS_DIRECTORY", dir.path());
+        let resolved = resolve_credential("cred:db-password", true);
+        let disabled = resolve_credential("cred:db-password", false);
+        std::env::remove_var("CREDENTIALS_DIRECTORY");
+
+        assert_eq!(resolved.unwrap(), Ok(Value::String("hunter2".to_string())));
+        assert!(disabled.unwrap().is_err());
+        assert!(resolve_credential("cred:db-password", true).is_none());
+        assert!(resolve_credential("env:HOME", true).is_none());
+    }
+
+    #[test]
+    fn test_generated_builtins() {
+        let uuid = resolve_builtin("uuid", usize::MAX).unwrap().unwrap();
+        assert!(uuid::Uuid::parse_str(uuid.as_str().unwrap()).is_ok());
+        assert_ne!(uuid, resolve_builtin("uuid", usize::MAX).unwrap().unwrap());
+
+        let hex = resolve_builtin("random:hex:16", usize::MAX)
+            .unwrap()
+            .unwrap();
+        let hex = hex.as_str().unwrap();
+        assert_eq!(hex.len(), 16);
+        assert!(hex.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let alnum = resolve_builtin("random:alnum:100", usize::MAX)
+            .unwrap()
+            .unwrap();
+        let alnum = alnum.as_str().unwrap();
+        assert_eq!(alnum.len(), 100);
+        assert!(alnum.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        assert!(resolve_builtin("random:hex", usize::MAX).unwrap().is_err());
+        assert!(resolve_builtin("random:emoji:4", usize::MAX)
+            .unwrap()
+            .is_err());
+        assert!(resolve_builtin("random:hex:lots", usize::MAX)
+            .unwrap()
+            .is_err());
+        assert_eq!(
+            resolve_builtin("random:hex:16", 16)
+                .unwrap()
+                .unwrap()
+                .as_str()
+                .map(str::len),
+            Some(16)
+        );
+        let error = resolve_builtin("random:hex:17", 16).unwrap().unwrap_err();
+        assert!(error.contains("maximum output size"), "{error}");
+
+        assert!(is_generated("uuid"));
+        assert!(is_generated("random:hex:8"));
+        assert!(!is_generated("sys:pid"));
+    }
+
+    #[test]
+    fn test_sys_builtins() {
+        assert_eq!(
+            resolve_builtin("sys:pid", usize::MAX).unwrap().unwrap(),
+            Value::from(std::process::id())
+        );
+        assert!(
+            resolve_builtin("sys:cpus", usize::MAX)
+                .unwrap()
+                .unwrap()
+                .as_u64()
+                .unwrap()
+                >= 1
+        );
+        assert!(!resolve_builtin("sys:hostname", usize::MAX)
+            .unwrap()
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .is_empty());
+        assert!(resolve_builtin("sys:username", usize::MAX).unwrap().is_ok());
+        assert!(resolve_builtin("sys:kernel", usize::MAX).unwrap().is_err());
+    }
+
+    #[cfg(feature = "exec-tokens")]
+    #[test]
+    fn test_exec_builtin() {
+        let exec = |key, allowed| resolve_exec(key, allowed, Duration::from_secs(10), 64);
+        assert_eq!(
+            exec("exec:echo  hello world ", true).unwrap().unwrap(),
+            Value::String("hello world".to_string())
+        );
+        assert!(exec("exec:echo hello", false).unwrap().is_err());
+        assert!(exec("exec:false", true).unwrap().is_err());
+        assert!(exec("exec:", true).unwrap().is_err());
+        assert!(exec("sys:pid", true).is_none());
+    }
+
+    #[cfg(feature = "exec-tokens")]
+    #[test]
+    fn test_exec_limits() {
+        let started = std::time::Instant::now();
+        let error = resolve_exec("exec:sleep 10", true, Duration::from_millis(100), 64)
+            .unwrap()
+            .unwrap_err();
+        assert!(error.contains("`sleep 10` did not finish"), "{error}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let error = resolve_exec("exec:yes", true, Duration::from_secs(10), 64)
+            .unwrap()
+            .unwrap_err();
+        assert!(error.contains("exceeds the maximum output size"), "{error}");
+        assert!(
+            resolve_exec("exec:echo 12345", true, Duration::from_secs(10), 6)
+                .unwrap()
+                .is_ok()
+        );
+        assert!(
+            resolve_exec("exec:echo 12345", true, Duration::from_secs(10), 5)
+                .unwrap()
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_time_builtins() {
+        let year = resolve_builtin("now:%Y", usize::MAX).unwrap().unwrap();
+        assert_eq!(year.as_str().unwrap().len(), 4);
+
+        let unix = resolve_builtin("now_unix", usize::MAX).unwrap().unwrap();
+        assert!(unix.as_i64().unwrap() > 1_700_000_000);
+
+        assert!(resolve_builtin("now:%Q", usize::MAX).unwrap().is_err());
+    }
+}
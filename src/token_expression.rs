@@ -0,0 +1,244 @@
+use serde_json::{Number, Value};
+
+/// The deepest that parentheses and unary minus may nest within an expression, so that
+/// configuration input cannot exhaust the stack.
+const MAX_NESTING: usize = 64;
+
+/// Evaluates a simple arithmetic expression such as `8080 + 1000` or `(4 * 1024) / 3`.
+///
+/// Supports `+`, `-`, `*`, `/` and `%`, parentheses and unary minus. The result is an integer
+/// when every operand is an integer and the result is exact, otherwise a float. Parentheses
+/// and unary minus may nest up to 64 levels deep.
+pub fn evaluate(expression: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        input: expression.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    let result = parser.expression()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.input.len() {
+        return Err(format!(
+            "unexpected `{}` in expression `{expression}`",
+            &expression[parser.pos..]
+        ));
+    }
+    result.into_value()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Int(i64),
+    Float(f64),
+}
+
+impl Operand {
+    #[allow(clippy::cast_precision_loss)]
+    const fn as_f64(self) -> f64 {
+        match self {
+            Self::Int(i) => i as f64,
+            Self::Float(f) => f,
+        }
+    }
+
+    fn into_value(self) -> Result<Value, String> {
+        match self {
+            Self::Int(i) => Ok(Value::Number(i.into())),
+            Self::Float(f) => Number::from_f64(f)
+                .map(Value::Number)
+                .ok_or_else(|| format!("expression result `{f}` is not a finite number")),
+        }
+    }
+
+    fn apply(self, op: u8, rhs: Self) -> Result<Self, String> {
+        if let (Self::Int(a), Self::Int(b)) = (self, rhs) {
+            if matches!(op, b'/' | b'%') && b == 0 {
+                return Err("division by zero".to_string());
+            }
+            let result = match op {
+                b'+' => a.checked_add(b),
+                b'-' => a.checked_sub(b),
+                b'*' => a.checked_mul(b),
+                b'/' if a.checked_rem(b) == Some(0) => a.checked_div(b),
+                // Inexact integer division falls through to float division
+                b'/' => None,
+                _ => a.checked_rem(b),
+            };
+            match result {
+                Some(result) => return Ok(Self::Int(result)),
+                None if op != b'/' => return Err("integer overflow".to_string()),
+                None => {}
+            }
+        }
+
+        let (a, b) = (self.as_f64(), rhs.as_f64());
+        Ok(Self::Float(match op {
+            b'+' => a + b,
+            b'-' => a - b,
+            b'*' => a * b,
+            b'/' => a / b,
+            _ => a % b,
+        }))
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// How deeply the factor being parsed is nested.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.pos).copied()
+    }
+
+    fn expression(&mut self) -> Result<Operand, String> {
+        let mut lhs = self.term()?;
+        while let Some(op @ (b'+' | b'-')) = self.peek() {
+            self.pos += 1;
+            lhs = lhs.apply(op, self.term()?)?;
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Operand, String> {
+        let mut lhs = self.factor()?;
+        while let Some(op @ (b'*' | b'/' | b'%')) = self.peek() {
+            self.pos += 1;
+            lhs = lhs.apply(op, self.factor()?)?;
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Operand, String> {
+        if self.depth == MAX_NESTING {
+            return Err(format!(
+                "expression nested more than {MAX_NESTING} levels deep"
+            ));
+        }
+        self.depth += 1;
+        let factor = self.nested_factor();
+        self.depth -= 1;
+        factor
+    }
+
+    fn nested_factor(&mut self) -> Result<Operand, String> {
+        match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                Operand::Int(0).apply(b'-', self.factor()?)
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                let inner = self.expression()?;
+                if self.peek() != Some(b')') {
+                    return Err("missing `)` in expression".to_string());
+                }
+                self.pos += 1;
+                Ok(inner)
+            }
+            Some(c) if c.is_ascii_digit() || c == b'.' => self.number(),
+            Some(c) => Err(format!("unexpected `{}` in expression", char::from(c))),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Operand, String> {
+        let start = self.pos;
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_digit() || *c == b'.')
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default();
+        if text.contains('.') {
+            text.parse()
+                .map(Operand::Float)
+                .map_err(|_| format!("invalid number `{text}`"))
+        } else {
+            text.parse()
+                .map(Operand::Int)
+                .map_err(|_| format!("invalid number `{text}`"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_evaluate() {
+        let test_cases = vec![
+            ("8080 + 1000", json!(9080)),
+            ("2 + 3 * 4", json!(14)),
+            ("(2 + 3) * 4", json!(20)),
+            ("10 / 2", json!(5)),
+            ("10 / 4", json!(2.5)),
+            ("10 % 3", json!(1)),
+            ("-5 + 2", json!(-3)),
+            ("1.5 * 2", json!(3.0)),
+            ("  7  ", json!(7)),
+        ];
+
+        for (expression, expected) in test_cases {
+            assert_eq!(
+                evaluate(expression).unwrap(),
+                expected,
+                "Failed on expression: {expression:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        for expression in [
+            "",
+            "1 +",
+            "(1 + 2",
+            "1 / 0",
+            "abc",
+            "1 2",
+            "9223372036854775807 + 1",
+        ] {
+            assert!(
+                evaluate(expression).is_err(),
+                "Expected an error for expression: {expression:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(evaluate(&nested(MAX_NESTING - 1)).unwrap(), json!(1));
+
+        for expression in [
+            nested(MAX_NESTING),
+            nested(1_000_000),
+            "-".repeat(1_000_000) + "1",
+        ] {
+            assert_eq!(
+                evaluate(&expression).unwrap_err(),
+                "expression nested more than 64 levels deep"
+            );
+        }
+    }
+}
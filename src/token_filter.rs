@@ -0,0 +1,230 @@
+use std::{fmt::Write, ops::RangeInclusive};
+
+use {
+    base64::{engine::general_purpose::STANDARD as BASE64, Engine},
+    percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC},
+    serde_json::Value,
+    sha2::{Digest, Sha256},
+};
+
+use crate::token_expander::{convert_value_to_string, join_values};
+
+/// Applies a single token filter, e.g. `base64` from `${key|base64}`, to a resolved value.
+///
+/// Filter arguments follow the filter name and are separated by `:`. Filters that build their
+/// output from an argument, such as `pad_left`, fail rather than produce more than `max_len`
+/// bytes.
+pub fn apply_filter(filter: &str, value: Value, max_len: usize) -> Result<Value, String> {
+    let mut parts = filter.split(':');
+    let name = parts.next().unwrap_or_default().trim();
+    let args: Vec<&str> = parts.collect();
+
+    match name {
+        "base64" => string_filter(name, &args, 0..=0, value, |s, _| Ok(BASE64.encode(s))),
+        "urlencode" => string_filter(name, &args, 0..=0, value, |s, _| {
+            Ok(utf8_percent_encode(&s, NON_ALPHANUMERIC).to_string())
+        }),
+        "sha256" => string_filter(name, &args, 0..=0, value, |s, _| {
+            Ok(Sha256::digest(s.as_bytes())
+                .iter()
+                .fold(String::with_capacity(64), |mut hex, b| {
+                    let _ = write!(hex, "{b:02x}");
+                    hex
+                }))
+        }),
+        "replace" => string_filter(name, &args, 2..=2, value, |s, args| {
+            Ok(s.replace(args[0], args[1]))
+        }),
+        "slice" => string_filter(name, &args, 1..=2, value, |s, args| {
+            let len = s.chars().count();
+            let start = parse_index(args[0], len)?;
+            let end = args.get(1).map_or(Ok(len), |end| parse_index(end, len))?;
+            Ok(s.chars()
+                .skip(start)
+                .take(end.saturating_sub(start))
+                .collect())
+        }),
+        "pad_left" => string_filter(name, &args, 1..=2, value, |s, args| {
+            let padding = padding(&s, args, max_len)?;
+            Ok(padding + &s)
+        }),
+        "pad_right" => string_filter(name, &args, 1..=2, value, |s, args| {
+            let padding = padding(&s, args, max_len)?;
+            Ok(s + &padding)
+        }),
+        "join" => {
+            // The separator is taken verbatim, so it may itself contain `:`
+            let separator = filter
+                .split_once(':')
+                .map_or(",", |(_, separator)| separator);
+            match value {
+                Value::Array(values) => Ok(Value::String(join_values(values, separator))),
+                other => Err(format!("filter `join` requires an array but got `{other}`")),
+            }
+        }
+        _ => Err(format!("unknown filter `{name}`")),
+    }
+}
+
+fn string_filter(
+    name: &str,
+    args: &[&str],
+    expected_args: RangeInclusive<usize>,
+    value: Value,
+    f: impl FnOnce(String, &[&str]) -> Result<String, String>,
+) -> Result<Value, String> {
+    if !expected_args.contains(&args.len()) {
+        let expected = if expected_args.start() == expected_args.end() {
+            expected_args.start().to_string()
+        } else {
+            format!("{} to {}", expected_args.start(), expected_args.end())
+        };
+        return Err(format!(
+            "filter `{name}` takes {expected} argument(s) but {} were given",
+            args.len()
+        ));
+    }
+    f(convert_value_to_string(value), args).map(Value::String)
+}
+
+/// Parses a character index for `slice`. Negative indices count back from the end of the
+/// string and out of range indices are clamped.
+fn parse_index(arg: &str, len: usize) -> Result<usize, String> {
+    let index: i64 = arg
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid index `{arg}`"))?;
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let index = if index < 0 { len + index } else { index };
+    Ok(usize::try_from(index.clamp(0, len)).unwrap_or_default())
+}
+
+/// Builds the padding for `pad_left` and `pad_right`, taking a target width and an optional
+/// single padding character that defaults to a space. Fails if the padded string would be
+/// longer than `max_len` bytes.
+fn padding(s: &str, args: &[&str], max_len: usize) -> Result<String, String> {
+    let width: usize = args[0]
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid width `{}`", args[0]))?;
+    let mut fill = args.get(1).map_or(" ", |fill| fill).chars();
+    let (Some(fill), None) = (fill.next(), fill.next()) else {
+        return Err("padding must be a single character".to_string());
+    };
+    let count = width.saturating_sub(s.chars().count());
+    let too_large = || format!("width {width} exceeds the maximum output size of {max_len} bytes");
+    let bytes = count.saturating_mul(fill.len_utf8());
+    if bytes.saturating_add(s.len()) > max_len {
+        return Err(too_large());
+    }
+    let mut padding = String::new();
+    padding.try_reserve(bytes).map_err(|_| too_large())?;
+    padding.extend(std::iter::repeat_n(fill, count));
+    Ok(padding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_encoding_filters() {
+        let test_cases = vec![
+            ("base64", json!("user:pass"), json!("dXNlcjpwYXNz")),
+            ("urlencode", json!("p@ss w/rd"), json!("p%40ss%20w%2Frd")),
+            (
+                "sha256",
+                json!("abc"),
+                json!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            ),
+            ("base64", json!(8080), json!("ODA4MA==")),
+        ];
+
+        for (filter, input, expected) in test_cases {
+            assert_eq!(
+                apply_filter(filter, input, usize::MAX).unwrap(),
+                expected,
+                "Failed on filter: {filter}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_string_filters() {
+        let test_cases = vec![
+            (
+                "replace:.:-",
+                json!("api.example.com"),
+                json!("api-example-com"),
+            ),
+            ("replace:-:", json!("a-b-c"), json!("abc")),
+            ("slice:0:8", json!("0123456789abcdef"), json!("01234567")),
+            ("slice:4", json!("0123456789"), json!("456789")),
+            ("slice:-3", json!("0123456789"), json!("789")),
+            ("slice:2:100", json!("abc"), json!("c")),
+            ("slice:5:2", json!("abcdefgh"), json!("")),
+            ("pad_left:5:0", json!(80), json!("00080")),
+            ("pad_left:2:0", json!(8080), json!("8080")),
+            ("pad_left:4", json!("ab"), json!("  ab")),
+            ("pad_right:4:.", json!("ab"), json!("ab..")),
+        ];
+
+        for (filter, input, expected) in test_cases {
+            assert_eq!(
+                apply_filter(filter, input, usize::MAX).unwrap(),
+                expected,
+                "Failed on filter: {filter}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_join_filter() {
+        let origins = json!(["https://a.example", "https://b.example"]);
+        let test_cases = vec![
+            ("join", json!("https://a.example,https://b.example")),
+            ("join:, ", json!("https://a.example, https://b.example")),
+            ("join: :: ", json!("https://a.example :: https://b.example")),
+            ("join:", json!("https://a.examplehttps://b.example")),
+        ];
+
+        for (filter, expected) in test_cases {
+            assert_eq!(
+                apply_filter(filter, origins.clone(), usize::MAX).unwrap(),
+                expected,
+                "Failed on filter: {filter}"
+            );
+        }
+
+        assert_eq!(
+            apply_filter("join:-", json!([1, true, null]), usize::MAX).unwrap(),
+            json!("1-true-null")
+        );
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        assert!(apply_filter("join", json!("not an array"), usize::MAX).is_err());
+        assert!(apply_filter("rot13", json!("abc"), usize::MAX).is_err());
+        assert!(apply_filter("base64:extra", json!("abc"), usize::MAX).is_err());
+        assert!(apply_filter("replace:a", json!("abc"), usize::MAX).is_err());
+        assert!(apply_filter("slice:x", json!("abc"), usize::MAX).is_err());
+        assert!(apply_filter("pad_left:4:ab", json!("abc"), usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_padding_limit() {
+        assert_eq!(
+            apply_filter("pad_left:4:0", json!("a"), 4).unwrap(),
+            json!("000a")
+        );
+        let error = apply_filter("pad_left:5:0", json!("a"), 4).unwrap_err();
+        assert_eq!(error, "width 5 exceeds the maximum output size of 4 bytes");
+        assert!(apply_filter("pad_right:5:é", json!("a"), 8).is_err());
+
+        let width = usize::MAX;
+        assert!(apply_filter(&format!("pad_left:{width}:0"), json!("a"), usize::MAX).is_err());
+    }
+}
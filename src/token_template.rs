@@ -0,0 +1,151 @@
+use {
+    crate::{
+        token_expander::{format_new_array_path, format_new_path, ExpansionOptions},
+        Error,
+    },
+    minijinja::{Environment, UndefinedBehavior},
+    serde_json::{Map, Value},
+};
+
+/// Renders every string value that contains template syntax as a minijinja template.
+///
+/// The configuration is available to each template as written, with any top level keys from
+/// `context` taking precedence. Referring to an undefined variable is an error rather than
+/// rendering as an empty string.
+pub fn render_templates(
+    val: &Value,
+    options: &ExpansionOptions,
+    context: Option<&Value>,
+) -> Result<Value, Error> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_keep_trailing_newline(true);
+
+    let variables = template_variables(val, context);
+    render_value(val, &env, &variables, options, "")
+}
+
+fn template_variables(val: &Value, context: Option<&Value>) -> Value {
+    let mut variables = match val {
+        Value::Object(map) => map.clone(),
+        _ => Map::new(),
+    };
+    if let Some(Value::Object(context)) = context {
+        variables.extend(context.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+    Value::Object(variables)
+}
+
+fn render_value(
+    val: &Value,
+    env: &Environment,
+    variables: &Value,
+    options: &ExpansionOptions,
+    current_path: &str,
+) -> Result<Value, Error> {
+    if options.is_skipped(current_path) {
+        return Ok(val.clone());
+    }
+
+    match val {
+        Value::String(s) if is_template(s) => env
+            .render_str(s, variables)
+            .map(Value::String)
+            .map_err(|e| Error::TemplateError {
+                path: current_path.to_string(),
+                message: e.to_string(),
+            }),
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| {
+                let new_path = format_new_path(current_path, key);
+                render_value(value, env, variables, options, &new_path).map(|v| (key.clone(), v))
+            })
+            .collect::<Result<Map<_, _>, _>>()
+            .map(Value::Object),
+        Value::Array(arr) => arr
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let new_path = format_new_array_path(current_path, index);
+                render_value(value, env, variables, options, &new_path)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        _ => Ok(val.clone()),
+    }
+}
+
+fn is_template(s: &str) -> bool {
+    s.contains("{{") || s.contains("{%") || s.contains("{#")
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::token_expander::ExpansionBackend, serde_json::json};
+
+    fn render(input: &Value) -> Result<Value, Error> {
+        let options = ExpansionOptions::new().backend(ExpansionBackend::MiniJinja);
+        crate::expand_tokens_with_options(input, &options)
+    }
+
+    #[test]
+    fn test_renders_variables() {
+        let input = json!({
+            "host": "example.com",
+            "url": "https://{{ host }}/{{ paths.api }}",
+            "paths": { "api": "v1" }
+        });
+
+        let output = render(&input).unwrap();
+        assert_eq!(output["url"], json!("https://example.com/v1"));
+    }
+
+    #[test]
+    fn test_renders_loops_and_conditionals() {
+        let input = json!({
+            "hosts": ["a", "b", "c"],
+            "debug": false,
+            "upstream": "{% for h in hosts %}{{ h }}:80{% if not loop.last %},{% endif %}{% endfor %}",
+            "level": "{% if debug %}debug{% else %}info{% endif %}"
+        });
+
+        let output = render(&input).unwrap();
+        assert_eq!(output["upstream"], json!("a:80,b:80,c:80"));
+        assert_eq!(output["level"], json!("info"));
+    }
+
+    #[test]
+    fn test_token_syntax_is_left_alone() {
+        let input = json!({ "a": "x", "b": "${a}", "c": 1 });
+        assert_eq!(render(&input).unwrap(), input);
+    }
+
+    #[test]
+    fn test_context_takes_precedence() {
+        let input = json!({ "port": 80, "addr": "0.0.0.0:{{ port }}" });
+        let options = ExpansionOptions::new().backend(ExpansionBackend::MiniJinja);
+        let output = render_templates(&input, &options, Some(&json!({ "port": 8080 }))).unwrap();
+        assert_eq!(output["addr"], json!("0.0.0.0:8080"));
+    }
+
+    #[test]
+    fn test_skip_path() {
+        let input = json!({ "name": "x", "raw": "{{ name }}", "out": "{{ name }}" });
+        let options = ExpansionOptions::new()
+            .backend(ExpansionBackend::MiniJinja)
+            .skip_path("raw");
+        let output = render_templates(&input, &options, None).unwrap();
+        assert_eq!(output["raw"], json!("{{ name }}"));
+        assert_eq!(output["out"], json!("x"));
+    }
+
+    #[test]
+    fn test_undefined_variable_names_path() {
+        let input = json!({ "server": { "url": "{{ missing }}" } });
+        match render(&input) {
+            Err(Error::TemplateError { path, .. }) => assert_eq!(path, "server.url"),
+            other => panic!("expected TemplateError, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,650 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+use figment::{
+    providers::Serialized,
+    value::{Dict, Map as ProfileMap},
+    Metadata, Profile, Provider, Source,
+};
+use serde_json::Value;
+
+use crate::{ChangeWatcher, Error};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest frame read from the server, well above `ZooKeeper`'s default `jute.maxbuffer`
+/// of just under 1 MiB, so that a corrupt length cannot allocate gigabytes.
+const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+const OP_GET_DATA: i32 = 4;
+const OP_GET_CHILDREN: i32 = 8;
+const OP_PING: i32 = 11;
+const OP_CLOSE: i32 = -11;
+
+const XID_WATCH_EVENT: i32 = -1;
+const XID_PING: i32 = -2;
+
+const ERROR_NO_NODE: i32 = -101;
+
+const EVENT_NONE: i32 = -1;
+const STATE_EXPIRED: i32 = -112;
+
+/// A configuration layer read from a tree of znodes in `ZooKeeper`, merged with
+/// [`GraftonConfigBuilder::provider`](crate::GraftonConfigBuilder::provider).
+///
+/// Each znode under the root path becomes a key: znodes with children become tables and those
+/// without become values, their data as a string. So with a root of `/myapp/config`, the znode
+/// `/myapp/config/website/port` sets `website.port`. Values are coerced into typed fields when
+/// the configuration is deserialized.
+///
+/// [`watch`](ZooKeeperSource::watch) sets `ZooKeeper` watches on the tree, so the configuration
+/// can be reloaded when a znode is created, changed or deleted.
+#[derive(Clone)]
+pub struct ZooKeeperSource {
+    hosts: Vec<String>,
+    root: String,
+    timeout: Duration,
+}
+
+impl ZooKeeperSource {
+    /// Creates a source for the znodes under `root` in the ensemble at `hosts`, a comma
+    /// separated list such as `zk1:2181,zk2:2181`, which are tried in order.
+    #[must_use]
+    pub fn new(hosts: &str, root: impl Into<String>) -> Self {
+        let root = root.into();
+        Self {
+            hosts: hosts
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(ToString::to_string)
+                .collect(),
+            root: format!("/{}", root.trim_matches('/')),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets the session timeout, which also limits how long connecting and each request may
+    /// take. Defaults to 30 seconds.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets watches on the tree, returning a [`ChangeWatcher`] that reports when it changes.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ExternalError`] if the ensemble cannot be reached or the root does not
+    /// exist.
+    pub fn watch(&self) -> Result<ChangeWatcher, Error> {
+        let watch_error = |e: io::Error| Error::ExternalError {
+            context: "unable to watch ZooKeeper".to_string(),
+            source: Box::new(e),
+        };
+        let mut session = self.connect().map_err(watch_error)?;
+        self.read_tree(&mut session, true).map_err(watch_error)?;
+
+        let (sender, watcher) = ChangeWatcher::channel("ZooKeeper watches");
+        let source = self.clone();
+        thread::spawn(move || loop {
+            let changed = session
+                .wait_for_event()
+                .and_then(|()| source.read_tree(&mut session, true))
+                .map(drop)
+                .map_err(|e| e.to_string());
+            let failed = changed.is_err();
+            if sender.send(changed).is_err() || failed {
+                return;
+            }
+        });
+        Ok(watcher)
+    }
+
+    fn connect(&self) -> io::Result<Session> {
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no ZooKeeper hosts");
+        for host in &self.hosts {
+            for address in host.to_socket_addrs()? {
+                match Session::connect(&address, self.timeout) {
+                    Ok(session) => return Ok(session),
+                    Err(e) => last_error = e,
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Reads the tree under the root into a table, setting watches on each znode if `watch`.
+    fn read_tree(&self, session: &mut Session, watch: bool) -> io::Result<Value> {
+        match read_node(session, &self.root, watch)? {
+            Some(Value::Object(table)) => Ok(Value::Object(table)),
+            Some(_) => Ok(Value::Object(serde_json::Map::new())),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("znode {} does not exist", self.root),
+            )),
+        }
+    }
+
+    fn read(&self) -> Result<Value, String> {
+        let mut session = self
+            .connect()
+            .map_err(|e| format!("unable to connect to ZooKeeper: {e}"))?;
+        let tree = self
+            .read_tree(&mut session, false)
+            .map_err(|e| format!("unable to read ZooKeeper tree {}: {e}", self.root))?;
+        session.close();
+        Ok(tree)
+    }
+}
+
+/// Reads the znode at `path`, a table if it has children and otherwise its data, or `None` if
+/// it does not exist.
+fn read_node(session: &mut Session, path: &str, watch: bool) -> io::Result<Option<Value>> {
+    let Some(mut children) = session.get_children(path, watch)? else {
+        return Ok(None);
+    };
+    if children.is_empty() {
+        let Some(data) = session.get_data(path, watch)? else {
+            return Ok(None);
+        };
+        return String::from_utf8(data)
+            .map(|data| Some(Value::String(data)))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {e}")));
+    }
+
+    children.sort();
+    let mut table = serde_json::Map::new();
+    for child in children {
+        let child_path = format!("{}/{child}", path.trim_end_matches('/'));
+        if let Some(value) = read_node(session, &child_path, watch)? {
+            table.insert(child, value);
+        }
+    }
+    Ok(Some(Value::Object(table)))
+}
+
+/// A `ZooKeeper` client session, speaking enough of the protocol to read and watch znodes.
+struct Session {
+    stream: TcpStream,
+    timeout: Duration,
+    xid: i32,
+    pending_event: bool,
+}
+
+impl Session {
+    fn connect(address: &std::net::SocketAddr, timeout: Duration) -> io::Result<Self> {
+        let mut stream = TcpStream::connect_timeout(address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let mut request = Vec::new();
+        write_i32(&mut request, 0);
+        write_i64(&mut request, 0);
+        write_i32(
+            &mut request,
+            i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX),
+        );
+        write_i64(&mut request, 0);
+        write_buffer(&mut request, &[0; 16]);
+        write_frame(&mut stream, &request)?;
+
+        let response = read_frame(&mut stream)?;
+        let mut reader = Reader(&response);
+        reader.i32()?;
+        let negotiated = reader.i32()?;
+        if negotiated <= 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "ZooKeeper refused the session",
+            ));
+        }
+
+        Ok(Self {
+            stream,
+            timeout: Duration::from_millis(negotiated.unsigned_abs().into()),
+            xid: 0,
+            pending_event: false,
+        })
+    }
+
+    /// Sends a request, returning its response, or the `ZooKeeper` error code it failed with.
+    fn request(&mut self, op: i32, payload: &[u8]) -> io::Result<Result<Vec<u8>, i32>> {
+        self.xid += 1;
+        let mut request = Vec::with_capacity(payload.len() + 8);
+        write_i32(&mut request, self.xid);
+        write_i32(&mut request, op);
+        request.extend_from_slice(payload);
+        write_frame(&mut self.stream, &request)?;
+
+        loop {
+            let response = read_frame(&mut self.stream)?;
+            let mut reader = Reader(&response);
+            let xid = reader.i32()?;
+            reader.i64()?;
+            let error = reader.i32()?;
+            match xid {
+                XID_WATCH_EVENT => self.pending_event |= is_change(&mut reader)?,
+                XID_PING => {}
+                xid if xid == self.xid => {
+                    return Ok(if error == 0 {
+                        Ok(reader.0.to_vec())
+                    } else {
+                        Err(error)
+                    });
+                }
+                xid => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected ZooKeeper response {xid}"),
+                    ))
+                }
+            }
+        }
+    }
+
+    fn get_children(&mut self, path: &str, watch: bool) -> io::Result<Option<Vec<String>>> {
+        let mut payload = Vec::new();
+        write_string(&mut payload, path);
+        payload.push(u8::from(watch));
+        match self.request(OP_GET_CHILDREN, &payload)? {
+            Ok(response) => {
+                let mut reader = Reader(&response);
+                let count = reader.i32()?;
+                (0..count.max(0))
+                    .map(|_| reader.string())
+                    .collect::<io::Result<_>>()
+                    .map(Some)
+            }
+            Err(ERROR_NO_NODE) => Ok(None),
+            Err(code) => Err(zookeeper_error(path, code)),
+        }
+    }
+
+    fn get_data(&mut self, path: &str, watch: bool) -> io::Result<Option<Vec<u8>>> {
+        let mut payload = Vec::new();
+        write_string(&mut payload, path);
+        payload.push(u8::from(watch));
+        match self.request(OP_GET_DATA, &payload)? {
+            Ok(response) => Reader(&response).buffer().map(Some),
+            Err(ERROR_NO_NODE) => Ok(None),
+            Err(code) => Err(zookeeper_error(path, code)),
+        }
+    }
+
+    /// Waits for a watch to fire, pinging the server so the session stays alive.
+    fn wait_for_event(&mut self) -> io::Result<()> {
+        let ping_interval = self.timeout / 3;
+        loop {
+            if std::mem::take(&mut self.pending_event) {
+                return Ok(());
+            }
+
+            self.stream.set_read_timeout(Some(ping_interval))?;
+            let ready = self.stream.peek(&mut [0]);
+            self.stream.set_read_timeout(Some(self.timeout))?;
+            match ready {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "ZooKeeper closed the connection",
+                    ))
+                }
+                Ok(_) => {
+                    let response = read_frame(&mut self.stream)?;
+                    let mut reader = Reader(&response);
+                    if reader.i32()? == XID_WATCH_EVENT {
+                        reader.i64()?;
+                        reader.i32()?;
+                        self.pending_event |= is_change(&mut reader)?;
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    let mut ping = Vec::new();
+                    write_i32(&mut ping, XID_PING);
+                    write_i32(&mut ping, OP_PING);
+                    write_frame(&mut self.stream, &ping)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn close(mut self) {
+        self.xid += 1;
+        let mut request = Vec::new();
+        write_i32(&mut request, self.xid);
+        write_i32(&mut request, OP_CLOSE);
+        let _ = write_frame(&mut self.stream, &request);
+    }
+}
+
+/// Reads a watch event, returning whether it reports a change to a znode. An expired session
+/// is an error, since its watches are gone.
+fn is_change(reader: &mut Reader<'_>) -> io::Result<bool> {
+    let event = reader.i32()?;
+    let state = reader.i32()?;
+    if state == STATE_EXPIRED {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "ZooKeeper session expired",
+        ));
+    }
+    Ok(event != EVENT_NONE)
+}
+
+fn zookeeper_error(path: &str, code: i32) -> io::Error {
+    io::Error::other(format!("ZooKeeper error {code} reading {path}"))
+}
+
+fn write_i32(buffer: &mut Vec<u8>, value: i32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_i64(buffer: &mut Vec<u8>, value: i64) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_buffer(buffer: &mut Vec<u8>, value: &[u8]) {
+    write_i32(buffer, i32::try_from(value.len()).unwrap_or(i32::MAX));
+    buffer.extend_from_slice(value);
+}
+
+fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    write_buffer(buffer, value.as_bytes());
+}
+
+fn write_frame(stream: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 4);
+    write_buffer(&mut frame, payload);
+    stream.write_all(&frame)
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0; 4];
+    stream.read_exact(&mut length)?;
+    let length = usize::try_from(i32::from_be_bytes(length))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "negative ZooKeeper frame"))?;
+    if length > MAX_FRAME_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("ZooKeeper frame of {length} bytes exceeds the maximum of {MAX_FRAME_LENGTH}"),
+        ));
+    }
+    let mut frame = vec![0; length];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+/// Reads the fields of a `ZooKeeper` record in order.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> io::Result<&[u8]> {
+        if self.0.len() < length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated ZooKeeper record",
+            ));
+        }
+        let (value, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        let high = i64::from(self.i32()?);
+        let low = i64::from(self.i32()?.cast_unsigned());
+        Ok((high << 32) | low)
+    }
+
+    /// A length-prefixed buffer, where a negative length is an empty buffer.
+    fn buffer(&mut self) -> io::Result<Vec<u8>> {
+        let length = usize::try_from(self.i32()?).unwrap_or(0);
+        self.take(length).map(<[u8]>::to_vec)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.buffer()?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Provider for ZooKeeperSource {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("ZooKeeper").source(Source::Custom(format!(
+            "{}{}",
+            self.hosts.join(","),
+            self.root
+        )))
+    }
+
+    fn data(&self) -> Result<ProfileMap<Profile, Dict>, figment::Error> {
+        Serialized::defaults(self.read()?).data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        net::TcpListener,
+        sync::{mpsc, Arc, Mutex},
+    };
+
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{GraftonConfigBuilder, TokenExpandingConfig};
+
+    type Tree = Arc<Mutex<BTreeMap<String, String>>>;
+
+    /// Serves `sessions` sessions of a `ZooKeeper` ensemble of one on a local port, answering
+    /// reads from `tree` and sending a `NodeDataChanged` event for each path received on
+    /// `changes`, until the session is closed or `changes` is dropped. Returns its address and a
+    /// handle yielding the paths read with a watch.
+    fn serve(
+        tree: Tree,
+        changes: mpsc::Receiver<String>,
+        sessions: usize,
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let handle = thread::spawn(move || {
+            let mut watched = Vec::new();
+            for stream in listener.incoming().take(sessions) {
+                let mut stream = stream.unwrap();
+                read_frame(&mut stream).unwrap();
+                let mut response = Vec::new();
+                write_i32(&mut response, 0);
+                write_i32(&mut response, 6000);
+                write_i64(&mut response, 1);
+                write_buffer(&mut response, &[0; 16]);
+                write_frame(&mut stream, &response).unwrap();
+
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(10)))
+                    .unwrap();
+                loop {
+                    let change = changes.try_recv();
+                    if change == Err(mpsc::TryRecvError::Disconnected) {
+                        break;
+                    }
+                    if let Ok(path) = change {
+                        let mut event = Vec::new();
+                        write_i32(&mut event, XID_WATCH_EVENT);
+                        write_i64(&mut event, 0);
+                        write_i32(&mut event, 0);
+                        write_i32(&mut event, 3);
+                        write_i32(&mut event, 3);
+                        write_string(&mut event, &path);
+                        write_frame(&mut stream, &event).unwrap();
+                    }
+
+                    let mut length = [0; 4];
+                    match stream.read_exact(&mut length) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                        Err(_) => break,
+                    }
+                    let mut request = vec![0; i32::from_be_bytes(length).unsigned_abs() as usize];
+                    stream.read_exact(&mut request).unwrap();
+                    let mut reader = Reader(&request);
+                    let xid = reader.i32().unwrap();
+                    let op = reader.i32().unwrap();
+                    if op == OP_CLOSE {
+                        break;
+                    }
+                    let path = if op == OP_PING {
+                        String::new()
+                    } else {
+                        reader.string().unwrap()
+                    };
+                    if reader.0.first() == Some(&1) && !watched.contains(&path) {
+                        watched.push(path.clone());
+                    }
+
+                    let tree = tree.lock().unwrap().clone();
+                    let mut response = Vec::new();
+                    write_i32(&mut response, xid);
+                    write_i64(&mut response, 0);
+                    match (op, tree.get(&path)) {
+                        (OP_PING, _) => write_i32(&mut response, 0),
+                        (_, None) => write_i32(&mut response, ERROR_NO_NODE),
+                        (OP_GET_CHILDREN, Some(_)) => {
+                            let prefix = format!("{}/", path.trim_end_matches('/'));
+                            let children: Vec<&str> = tree
+                                .keys()
+                                .filter_map(|key| key.strip_prefix(&prefix))
+                                .filter(|child| !child.contains('/'))
+                                .collect();
+                            write_i32(&mut response, 0);
+                            write_i32(&mut response, children.len().try_into().unwrap());
+                            for child in children {
+                                write_string(&mut response, child);
+                            }
+                        }
+                        (_, Some(data)) => {
+                            write_i32(&mut response, 0);
+                            write_string(&mut response, data);
+                            response.extend_from_slice(&[0; 68]);
+                        }
+                    }
+                    write_frame(&mut stream, &response).unwrap();
+                }
+            }
+            watched
+        });
+        (address, handle)
+    }
+
+    fn tree(znodes: &[(&str, &str)]) -> Tree {
+        Arc::new(Mutex::new(
+            znodes
+                .iter()
+                .map(|(path, data)| ((*path).to_string(), (*data).to_string()))
+                .collect(),
+        ))
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Website {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ZooKeeperConfig {
+        name: String,
+        website: Website,
+    }
+
+    impl TokenExpandingConfig for ZooKeeperConfig {}
+
+    #[test]
+    fn test_read_frame() {
+        let mut frame = Vec::new();
+        write_buffer(&mut frame, b"data");
+        assert_eq!(read_frame(&mut frame.as_slice()).unwrap(), b"data");
+
+        for length in [i32::MAX, -1] {
+            let bytes = length.to_be_bytes();
+            let error = read_frame(&mut bytes.as_slice()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_zookeeper_source() {
+        let tree = tree(&[
+            ("/app", ""),
+            ("/app/config", ""),
+            ("/app/config/name", "${website.host}"),
+            ("/app/config/website", ""),
+            ("/app/config/website/port", "8080"),
+        ]);
+        let (changes, received) = mpsc::channel();
+        let (address, server) = serve(tree, received, 2);
+
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.toml"),
+            "name = \"app\"\n\n[website]\nhost = \"example.com\"\nport = 80\n",
+        )
+        .unwrap();
+
+        let config: ZooKeeperConfig = GraftonConfigBuilder::new()
+            .dir(dir.path())
+            .provider(
+                ZooKeeperSource::new(&format!("127.0.0.1:1,{address}"), "/app/config/")
+                    .timeout(Duration::from_secs(1)),
+            )
+            .load()
+            .unwrap();
+        assert_eq!(config.name, "example.com");
+        assert_eq!(config.website.port, 8080);
+
+        assert!(ZooKeeperSource::new(&address, "/missing").read().is_err());
+
+        drop(changes);
+        assert!(server.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_zookeeper_watch() {
+        let tree = tree(&[("/app", ""), ("/app/port", "8080")]);
+        let (changes, received) = mpsc::channel();
+        let (address, server) = serve(Arc::clone(&tree), received, 1);
+
+        let source = ZooKeeperSource::new(&address, "/app");
+        let watcher = source.watch().unwrap();
+        assert!(!watcher.wait(Some(Duration::from_millis(50))).unwrap());
+
+        tree.lock()
+            .unwrap()
+            .insert("/app/port".to_string(), "9090".to_string());
+        changes.send("/app/port".to_string()).unwrap();
+        assert!(watcher.wait(Some(Duration::from_secs(5))).unwrap());
+
+        drop(changes);
+        drop(watcher);
+        let paths = server.join().unwrap();
+        assert_eq!(paths, ["/app", "/app/port"]);
+    }
+}